@@ -0,0 +1,153 @@
+use rust_coff::*;
+
+fn main() -> Result<(), CoffError> {
+    make_hello()
+}
+
+fn make_hello() -> Result<(), CoffError> {
+    let mut coff = create_coff();
+    
+    let mut hello_ir = create_intermediate_representation(
+        compilation_unit_id(1), 
+        string("hello.hep")
+    );
+
+    let main_index = add_symbol(&mut hello_ir.symbols, external_function(string("main")));
+    let print_index = add_symbol(&mut hello_ir.symbols, static_function(string("print")));
+
+    add_main(&mut hello_ir, main_index, print_index);
+    add_print(&mut hello_ir, print_index);
+
+    let file_name = build_machine_code_object(&mut coff, hello_ir)?;
+    write_coff_to_file(&coff, &mut create_coff_file(&file_name)?)?;
+
+    let mut archive = create_coff_archive();
+    add_coff_to_archive(&mut archive, &file_name, &coff)?;
+    write_coff_archive_to_file(&archive, &mut create_coff_file("hello.lib")?)?;
+
+    let kernel32 = create_import_library(
+        "kernel32.dll", 
+        &[dll_function_export("GetStdHandle"), dll_function_export("WriteFile")]
+    )?;
+    write_coff_archive_to_file(&kernel32, &mut create_coff_file("kernel32.lib")?)?;
+
+    let image = link_executable(&[&coff], &get_import_library_imports(&kernel32), &console_executable_options("main"))?;
+    write_executable_to_file(&image, &mut create_coff_file("hello.exe")?)
+}
+
+fn add_main(hello_ir: &mut IntermediateRepresentation, main_index: u32, print_index: u32) {
+    //main
+    add_byte_code(&mut hello_ir.byte_code, define_function_instruction(main_index));
+    // fn prologue    
+    add_byte_code(&mut hello_ir.byte_code, push_reg_64_instruction(base_pointer_register()));
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_to_reg_64_instruction(stack_pointer_register(), base_pointer_register())
+    );
+    
+    // print call:
+    // set shadow space for print call
+    add_byte_code(&mut hello_ir.byte_code, sub_value_from_reg_8_instruction(32, stack_pointer_register()));
+    
+    // set pointer to hello world first arg for print call
+    let hello = "Hello world!\r\n\0";
+    let ds0 = add_data_item(&mut hello_ir.data, read_only_string_data_item(string(hello)));
+    add_symbol(&mut hello_ir.symbols, data_section_item(string("ds0"), ds0));
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        load_data_section_address_to_reg_64(ds0, call_arg_register(0))
+    );
+    
+    // set hello world length second arg for print call
+    add_byte_code(&mut hello_ir.byte_code, move_value_to_reg_32_instruction(hello.len() as u32, call_arg_register(1)));
+    //call print
+    add_byte_code(&mut hello_ir.byte_code, call_to_symbol_instruction(print_index));
+    // release shadow space for print call   
+    add_byte_code(&mut hello_ir.byte_code, add_value_to_reg_8_instruction(32, stack_pointer_register()));
+    
+    // fn epilogue    
+    add_byte_code(&mut hello_ir.byte_code, move_reg_to_reg_64_instruction(
+        base_pointer_register(), 
+        stack_pointer_register())
+    );
+    add_byte_code(&mut hello_ir.byte_code, pop_reg_64_instruction(base_pointer_register()));
+    
+    // return
+    add_byte_code(&mut hello_ir.byte_code, ret_instruction());
+}
+
+fn add_print(hello_ir: &mut IntermediateRepresentation, print_index: u32) {
+    //print
+    add_byte_code(&mut hello_ir.byte_code, define_function_instruction(print_index));
+    // fn prologue    
+    add_byte_code(&mut hello_ir.byte_code, push_reg_64_instruction(base_pointer_register()));
+    add_byte_code(&mut hello_ir.byte_code, move_reg_to_reg_64_instruction(
+        stack_pointer_register(), 
+        base_pointer_register())
+    );
+    //store args 1 and 2 in shadow
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_to_reg_plus_offset_64_instruction(call_arg_register(0), base_pointer_register(), 16)
+    );
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_to_reg_plus_offset_32_instruction(call_arg_register(1), base_pointer_register(), 24)
+    );
+    //resesrve space for 1 local var (8 bytes)    
+    add_byte_code(&mut hello_ir.byte_code, sub_value_from_reg_8_instruction(8, stack_pointer_register()));
+    // call to GetStdHandle
+    // resesrve shadow space for call to GetStdHandle
+    add_byte_code(&mut hello_ir.byte_code, sub_value_from_reg_8_instruction(32, stack_pointer_register()));
+    // set first arg (STD_OUTPUT_HANDLE) for call to GetStdHandle
+    let symbol_index = add_symbol(&mut hello_ir.symbols, absolute_external(string("STD_OUTPUT_HANDLE"), 0xFFFFFFF5));
+    add_byte_code(&mut hello_ir.byte_code,move_symbol_to_reg_32_instruction(symbol_index, call_arg_register(0)));
+    // call GetStdHandle
+    
+    let symbol_index = add_symbol(&mut hello_ir.symbols, foreign_external(string("GetStdHandle")));
+    add_byte_code(&mut hello_ir.byte_code, call_to_symbol_instruction(symbol_index));
+    // release shadow space for call to GetStdHandle
+    add_byte_code(&mut hello_ir.byte_code, add_value_to_reg_8_instruction(32, stack_pointer_register()));
+    // store local variable handle returned
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_to_reg_plus_offset_32_instruction(call_return_arg_register(0), base_pointer_register(), 0xF8)
+    );
+    // call to WriteFile
+    // resesrve space for 5 args, shadow + 1    
+    add_byte_code(&mut hello_ir.byte_code, sub_value_from_reg_8_instruction(40, stack_pointer_register()));
+    // get values for args for call from storage
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_plus_offset_to_reg_32_instruction(base_pointer_register(), 0xF8, call_arg_register(0))
+    );
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_plus_offset_to_reg_64_instruction(base_pointer_register(), 16, call_arg_register(1))
+    );
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_reg_plus_offset_to_reg_32_instruction(base_pointer_register(), 24, call_arg_register(2))
+    );
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        zero_reg_64_instruction(call_arg_register(3))
+    );
+    add_byte_code(
+        &mut hello_ir.byte_code, 
+        move_value_to_reg_plus_offset_32_instruction(0x0, stack_pointer_register(), 32)
+    );
+    // call WriteFile
+    let symbol_index = add_symbol(&mut hello_ir.symbols, foreign_external(string("WriteFile")));
+    add_byte_code(&mut hello_ir.byte_code, call_to_symbol_instruction(symbol_index));
+    // release space for 5 args, shadow + 1    
+    add_byte_code(&mut hello_ir.byte_code, add_value_to_reg_8_instruction(40, stack_pointer_register()));
+    // fn epilogue    
+    add_byte_code(&mut hello_ir.byte_code, move_reg_to_reg_64_instruction(
+        base_pointer_register(), 
+        stack_pointer_register())
+    );
+    add_byte_code(&mut hello_ir.byte_code, pop_reg_64_instruction(base_pointer_register()));
+    // return
+    add_byte_code(&mut hello_ir.byte_code, ret_instruction());
+}
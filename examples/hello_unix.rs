@@ -0,0 +1,59 @@
+use rust_coff::*;
+
+fn main() -> Result<(), CoffError> {
+    match std::env::args().nth(1).as_deref() {
+        Some("mach-o") => make_hello_mach_o(),
+        _ => make_hello_elf()
+    }
+}
+
+fn make_hello_elf() -> Result<(), CoffError> {
+    let (file_name, elf) = build_elf_object(hello_ir())?;
+    write_elf_to_file(&elf, &mut create_coff_file(&file_name)?)
+}
+
+fn make_hello_mach_o() -> Result<(), CoffError> {
+    let (file_name, mach_o) = build_mach_o_object(hello_ir())?;
+    write_mach_o_to_file(&mach_o, &mut create_coff_file(&file_name)?)
+}
+
+fn hello_ir() -> IntermediateRepresentation {
+    let mut hello_ir = create_intermediate_representation(
+        compilation_unit_id(1),
+        string("hello.hep")
+    );
+
+    let main_index = add_symbol(&mut hello_ir.symbols, external_function(string("main")));
+    let puts_index = add_symbol(&mut hello_ir.symbols, foreign_external(string("puts")));
+
+    //main
+    add_byte_code(&mut hello_ir.byte_code, define_function_instruction(main_index));
+    // fn prologue, which leaves the stack 16 byte aligned for the call
+    add_byte_code(&mut hello_ir.byte_code, push_reg_64_instruction(base_pointer_register()));
+    add_byte_code(
+        &mut hello_ir.byte_code,
+        move_reg_to_reg_64_instruction(stack_pointer_register(), base_pointer_register())
+    );
+
+    // set pointer to hello world first arg for puts call
+    let ds0 = add_data_item(&mut hello_ir.data, read_only_string_data_item(string("Hello world!\0")));
+    add_symbol(&mut hello_ir.symbols, data_section_item(string("ds0"), ds0));
+    add_byte_code(
+        &mut hello_ir.byte_code,
+        load_data_section_address_to_reg_64(ds0, call_arg_register(0))
+    );
+    // call puts
+    add_byte_code(&mut hello_ir.byte_code, call_to_symbol_instruction(puts_index));
+
+    // return 0
+    add_byte_code(&mut hello_ir.byte_code, move_value_to_reg_32_instruction(0, call_return_arg_register(0)));
+    // fn epilogue
+    add_byte_code(&mut hello_ir.byte_code, move_reg_to_reg_64_instruction(
+        base_pointer_register(),
+        stack_pointer_register())
+    );
+    add_byte_code(&mut hello_ir.byte_code, pop_reg_64_instruction(base_pointer_register()));
+    add_byte_code(&mut hello_ir.byte_code, ret_instruction());
+
+    hello_ir
+}
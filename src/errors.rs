@@ -0,0 +1,147 @@
+use std::*;
+use crate::machine_code::*;
+use crate::intemediate_representation::*;
+use crate::textual_representation::*;
+
+#[derive(Debug)]
+pub enum CoffError {
+    Io { context: String, error: io::Error },
+    Read(CoffReadError),
+    Parse(IrParseError),
+    UnsupportedRegister { register: ByteCodeRegister, reason: &'static str },
+    SymbolIndexOutOfRange { symbol_index: u32, number_of_symbols: usize },
+    SymbolHasNoValue(u32),
+    DataItemIndexOutOfRange { data_item: u32, number_of_data_items: usize },
+    LabelIndexOutOfRange { label: u32, number_of_labels: usize },
+    LabelNotDefined(u32),
+    LabelDefinedTwice(u32),
+    FunctionNotDefined(u32),
+    FunctionDefinedTwice(u32),
+    NotAFunction(u32),
+    LineNumberTooLarge(u32),
+    ValueTooLargeForWidth { value: i32, width_in_bits: u32 },
+    UnsupportedWidth { instruction: &'static str, width_in_bits: u32 },
+    ShiftCountTooLarge { count: u8, width_in_bits: u32 },
+    InvalidAlignment(u32),
+    InvalidMemoryIndex { register: u8, scale: u8, reason: &'static str },
+    SectionNumberOutOfRange { section_number: u16, number_of_sections: u16 },
+    UninitialisedDataSectionEntries(String),
+    NameTooLong { name: String, maximum: usize },
+    SectionTooLarge { section: String, size: usize },
+    TooManyRelocations { section: String, count: usize },
+    UnknownSymbolHandle { section: String },
+    OffsetTooLarge { context: &'static str },
+    SymbolNotDefined(String),
+    SymbolDefinedTwice(String),
+    EntryPointNotDefined(String),
+    UnsupportedRelocation { section: String, relocation_type: u16 },
+    RelocationOutOfRange { symbol: String, relocation_type: u16 },
+    ArchiveSymbolDefinedTwice { symbol: String, member: String },
+    TooManyArchiveMembers(usize),
+    TimestampBeforeUnixEpoch,
+    SourceDateEpochInvalid(String),
+}
+
+impl fmt::Display for CoffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoffError::Io { context, error } =>
+                write!(f, "{}: {}", context, error),
+            CoffError::Read(error) =>
+                write!(f, "{}", error),
+            CoffError::Parse(error) =>
+                write!(f, "{}", error),
+            CoffError::UnsupportedRegister { register, reason } =>
+                write!(f, "register {:?} is not supported: {}", register, reason),
+            CoffError::SymbolIndexOutOfRange { symbol_index, number_of_symbols } =>
+                write!(f, "symbol index {} is out of range for {} symbols", symbol_index, number_of_symbols),
+            CoffError::SymbolHasNoValue(symbol_index) =>
+                write!(f, "symbol {} is not a named symbol and has no value", symbol_index),
+            CoffError::DataItemIndexOutOfRange { data_item, number_of_data_items } =>
+                write!(f, "data item {} is out of range for {} data items", data_item, number_of_data_items),
+            CoffError::LabelIndexOutOfRange { label, number_of_labels } =>
+                write!(f, "label {} is out of range for {} labels", label, number_of_labels),
+            CoffError::LabelNotDefined(label) =>
+                write!(f, "label {} is jumped to but never defined", label),
+            CoffError::LabelDefinedTwice(label) =>
+                write!(f, "label {} is defined more than once", label),
+            CoffError::FunctionNotDefined(symbol_index) =>
+                write!(f, "function symbol {} has no definition in the byte code", symbol_index),
+            CoffError::FunctionDefinedTwice(symbol_index) =>
+                write!(f, "function symbol {} is defined more than once", symbol_index),
+            CoffError::NotAFunction(symbol_index) =>
+                write!(f, "symbol {} is defined as a function but is not a function symbol", symbol_index),
+            CoffError::LineNumberTooLarge(line) =>
+                write!(f, "source line {} is larger than a line table can record", line),
+            CoffError::ValueTooLargeForWidth { value, width_in_bits } =>
+                write!(f, "value {} does not fit in {} bits", value, width_in_bits),
+            CoffError::UnsupportedWidth { instruction, width_in_bits } =>
+                write!(f, "{} has no {} bit form", instruction, width_in_bits),
+            CoffError::ShiftCountTooLarge { count, width_in_bits } =>
+                write!(f, "shift count {} is not less than the {} bit width", count, width_in_bits),
+            CoffError::InvalidAlignment(alignment) =>
+                write!(f, "alignment {} is not a power of two up to 8192", alignment),
+            CoffError::InvalidMemoryIndex { register, scale, reason } =>
+                write!(f, "register {} with scale {} is not a valid memory index: {}", register, scale, reason),
+            CoffError::SectionNumberOutOfRange { section_number, number_of_sections } =>
+                write!(f, "section number {} is out of range for {} sections", section_number, number_of_sections),
+            CoffError::UninitialisedDataSectionEntries(section) =>
+                write!(f, "section {} holds uninitialised data and cannot have entries added", section),
+            CoffError::NameTooLong { name, maximum } =>
+                write!(f, "name {} is longer than {} bytes", name, maximum),
+            CoffError::SectionTooLarge { section, size } =>
+                write!(f, "section {} of {} bytes does not fit in an object file", section, size),
+            CoffError::TooManyRelocations { section, count } =>
+                write!(f, "section {} has {} relocations, more than an object file can record", section, count),
+            CoffError::UnknownSymbolHandle { section } =>
+                write!(f, "section {} has a relocation against a symbol that is not in this object", section),
+            CoffError::OffsetTooLarge { context } =>
+                write!(f, "{} does not fit in a 32 bit file offset", context),
+            CoffError::SymbolNotDefined(symbol) =>
+                write!(f, "symbol {} is referenced but not defined by any object or import", symbol),
+            CoffError::SymbolDefinedTwice(symbol) =>
+                write!(f, "symbol {} is defined more than once", symbol),
+            CoffError::EntryPointNotDefined(symbol) =>
+                write!(f, "entry point {} is not defined in a section of any object", symbol),
+            CoffError::UnsupportedRelocation { section, relocation_type } =>
+                write!(f, "section {} has relocation type {:#x} which the linker does not support", section, relocation_type),
+            CoffError::RelocationOutOfRange { symbol, relocation_type } =>
+                write!(f, "relocation type {:#x} against {} does not fit its field", relocation_type, symbol),
+            CoffError::ArchiveSymbolDefinedTwice { symbol, member } =>
+                write!(f, "symbol {} is already defined by archive member {}", symbol, member),
+            CoffError::TooManyArchiveMembers(count) =>
+                write!(f, "archive has {} members, more than its symbol index can refer to", count),
+            CoffError::TimestampBeforeUnixEpoch =>
+                write!(f, "system time is before the unix epoch"),
+            CoffError::SourceDateEpochInvalid(value) =>
+                write!(f, "SOURCE_DATE_EPOCH {} is not a number of seconds that fits a 32 bit timestamp", value),
+        }
+    }
+}
+
+impl error::Error for CoffError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CoffError::Io { error, .. } => Some(error),
+            CoffError::Read(error) => Some(error),
+            CoffError::Parse(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<CoffReadError> for CoffError {
+    fn from(error: CoffReadError) -> Self {
+        CoffError::Read(error)
+    }
+}
+
+impl From<IrParseError> for CoffError {
+    fn from(error: IrParseError) -> Self {
+        CoffError::Parse(error)
+    }
+}
+
+pub fn io_error(context: &str, error: io::Error) -> CoffError {
+    CoffError::Io { context: string(context), error }
+}
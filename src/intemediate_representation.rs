@@ -9,18 +9,22 @@ pub fn compilation_unit_id(id: usize) -> CompilationUnitId {
     }
 }
 
+pub fn get_compilation_unit_number(id: CompilationUnitId) -> usize {
+    id.id
+}
+
 #[derive(Debug, Clone)]
 pub struct IntermediateRepresentation {
     pub id: CompilationUnitId,
     pub filename: String,
-    pub top_level_symbol: String,
     pub byte_code: ByteCodeInstructionStream,
     pub symbols: ByteCodeSymbols,
-    pub data: ByteCodeData
+    pub data: ByteCodeData,
+    pub labels: ByteCodeLabels
 }
 
-pub fn create_intermediate_representation(id: CompilationUnitId, filename: String, top_level_symbol: String) -> IntermediateRepresentation {
-    IntermediateRepresentation { id, filename, top_level_symbol, byte_code: vec!(), symbols: vec!(), data: vec!() }
+pub fn create_intermediate_representation(id: CompilationUnitId, filename: String) -> IntermediateRepresentation {
+    IntermediateRepresentation { id, filename, byte_code: vec!(), symbols: vec!(), data: vec!(), labels: vec!() }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -28,7 +32,9 @@ pub enum ByteCodeRegister {
     CallArg(usize),
     CallReturnArg(usize),
     StackPointer,
-    BasePointer
+    BasePointer,
+    ShiftCount,
+    Remainder
 }
 
 pub fn call_arg_register(number: usize) -> ByteCodeRegister {
@@ -47,6 +53,16 @@ pub fn stack_pointer_register() -> ByteCodeRegister {
     ByteCodeRegister::StackPointer
 }
 
+/// `cl` in every format, so it is a different call arg register under each calling convention.
+pub fn shift_count_register() -> ByteCodeRegister {
+    ByteCodeRegister::ShiftCount
+}
+
+/// `rdx` in every format, so it is a different call arg register under each calling convention.
+pub fn remainder_register() -> ByteCodeRegister {
+    ByteCodeRegister::Remainder
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ByteCodeInstruction {
     CallToSymbol(u32),
@@ -60,13 +76,67 @@ pub enum ByteCodeInstruction {
     MoveRegToRegPlusOffset64 { from: ByteCodeRegister, to: ByteCodeRegister, offset: u8 },
     MoveRegPlusOffsetToReg32 { from: ByteCodeRegister, offset: u8, to: ByteCodeRegister },
     MoveRegPlusOffsetToReg64 { from: ByteCodeRegister, offset: u8, to: ByteCodeRegister },
-    LoadDataSectionAddressToReg64 { data_section_offset: u32, to: ByteCodeRegister },
+    LoadDataSectionAddressToReg64 { data_item: u32, to: ByteCodeRegister },
     PushReg64(ByteCodeRegister),
     PopReg64(ByteCodeRegister),
     ZeroReg64(ByteCodeRegister),
+    ArithmeticRegToReg { operation: ByteCodeArithmetic, width: ByteCodeWidth, from: ByteCodeRegister, to: ByteCodeRegister },
+    ArithmeticValueToReg { operation: ByteCodeArithmetic, width: ByteCodeWidth, value: i32, to: ByteCodeRegister },
+    NotReg { width: ByteCodeWidth, register: ByteCodeRegister },
+    NegateReg { width: ByteCodeWidth, register: ByteCodeRegister },
+    ShiftRegByValue { shift: ByteCodeShift, width: ByteCodeWidth, value: u8, register: ByteCodeRegister },
+    ShiftRegByCount { shift: ByteCodeShift, width: ByteCodeWidth, register: ByteCodeRegister },
+    Divide { signed: bool, width: ByteCodeWidth, divisor: ByteCodeRegister },
+    DefineFunction(u32),
+    DefineLabel(u32),
+    SourceLocation { line: u32, column: u16 },
+    Jump(u32),
+    JumpIf { condition: ByteCodeCondition, label: u32 },
     Return
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ByteCodeWidth {
+    Byte,
+    Word,
+    DWord,
+    QWord
+}
+
+/// A two operand operation applied as `to = to <operation> from`; `Compare` only sets the flags for a following `JumpIf`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ByteCodeArithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    And,
+    Or,
+    Xor,
+    Compare
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ByteCodeShift {
+    Left,
+    Right,
+    ArithmeticRight
+}
+
+/// The outcome of the last flag setting instruction to branch on; `Less`/`Greater` are signed, `Below`/`Above` unsigned.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ByteCodeCondition {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Below,
+    BelowOrEqual,
+    Above,
+    AboveOrEqual
+}
+
 pub fn call_to_symbol_instruction(symbol_index: u32) -> ByteCodeInstruction {
     ByteCodeInstruction::CallToSymbol(symbol_index)
 }
@@ -119,14 +189,74 @@ pub fn move_reg_plus_offset_to_reg_64_instruction(from: ByteCodeRegister, offset
     ByteCodeInstruction::MoveRegPlusOffsetToReg64 { from, offset, to }
 }
 
-pub fn load_data_section_address_to_reg_64(data_section_offset: u32, to: ByteCodeRegister) -> ByteCodeInstruction {
-    ByteCodeInstruction::LoadDataSectionAddressToReg64 { data_section_offset, to }
+pub fn load_data_section_address_to_reg_64(data_item: u32, to: ByteCodeRegister) -> ByteCodeInstruction {
+    ByteCodeInstruction::LoadDataSectionAddressToReg64 { data_item, to }
 }
 
 pub fn zero_reg_64_instruction(register: ByteCodeRegister) -> ByteCodeInstruction {
     ByteCodeInstruction::ZeroReg64(register)
 }
 
+pub fn arithmetic_reg_to_reg_instruction(
+    operation: ByteCodeArithmetic, 
+    width: ByteCodeWidth, 
+    from: ByteCodeRegister, 
+    to: ByteCodeRegister
+) -> ByteCodeInstruction {
+    ByteCodeInstruction::ArithmeticRegToReg { operation, width, from, to }
+}
+
+pub fn arithmetic_value_to_reg_instruction(
+    operation: ByteCodeArithmetic, 
+    width: ByteCodeWidth, 
+    value: i32, 
+    to: ByteCodeRegister
+) -> ByteCodeInstruction {
+    ByteCodeInstruction::ArithmeticValueToReg { operation, width, value, to }
+}
+
+pub fn not_reg_instruction(width: ByteCodeWidth, register: ByteCodeRegister) -> ByteCodeInstruction {
+    ByteCodeInstruction::NotReg { width, register }
+}
+
+pub fn negate_reg_instruction(width: ByteCodeWidth, register: ByteCodeRegister) -> ByteCodeInstruction {
+    ByteCodeInstruction::NegateReg { width, register }
+}
+
+pub fn shift_reg_by_value_instruction(shift: ByteCodeShift, width: ByteCodeWidth, value: u8, register: ByteCodeRegister) -> ByteCodeInstruction {
+    ByteCodeInstruction::ShiftRegByValue { shift, width, value, register }
+}
+
+pub fn shift_reg_by_count_instruction(shift: ByteCodeShift, width: ByteCodeWidth, register: ByteCodeRegister) -> ByteCodeInstruction {
+    ByteCodeInstruction::ShiftRegByCount { shift, width, register }
+}
+
+/// Divides the return register by `divisor`, leaving the quotient in the return register 
+/// and the remainder in the remainder register, which is overwritten by the extended dividend.
+pub fn divide_instruction(signed: bool, width: ByteCodeWidth, divisor: ByteCodeRegister) -> ByteCodeInstruction {
+    ByteCodeInstruction::Divide { signed, width, divisor }
+}
+
+pub fn define_function_instruction(symbol_index: u32) -> ByteCodeInstruction {
+    ByteCodeInstruction::DefineFunction(symbol_index)
+}
+
+pub fn define_label_instruction(label: u32) -> ByteCodeInstruction {
+    ByteCodeInstruction::DefineLabel(label)
+}
+
+pub fn source_location_instruction(line: u32, column: u16) -> ByteCodeInstruction {
+    ByteCodeInstruction::SourceLocation { line, column }
+}
+
+pub fn jump_instruction(label: u32) -> ByteCodeInstruction {
+    ByteCodeInstruction::Jump(label)
+}
+
+pub fn jump_if_instruction(condition: ByteCodeCondition, label: u32) -> ByteCodeInstruction {
+    ByteCodeInstruction::JumpIf { condition, label }
+}
+
 pub fn ret_instruction() -> ByteCodeInstruction {
     ByteCodeInstruction::Return
 }
@@ -138,16 +268,23 @@ pub fn add_byte_code(byte_code_stream: &mut Vec<ByteCodeInstruction>, instructio
 
 #[derive(Debug, Clone)]
 pub enum ByteCodeSymbol {
-    DataSectionItem { name: String, value: u32 },
+    DataSectionItem { name: String, data_item: u32 },
     ForeignExternal { name: String },
     AbsoluteExternal { name: String, value: u32 },
     ExternalCodeLabel { name: String, position: u32 },
+    Function { name: String, linkage: ByteCodeLinkage },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ByteCodeLinkage {
+    External,
+    Static
 }
 
 pub type ByteCodeSymbols = Vec<ByteCodeSymbol>;
 
-pub fn data_section_item(name: String, value: u32) -> ByteCodeSymbol{
-    ByteCodeSymbol::DataSectionItem { name, value }
+pub fn data_section_item(name: String, data_item: u32) -> ByteCodeSymbol{
+    ByteCodeSymbol::DataSectionItem { name, data_item }
 }
 
 pub fn foreign_external(name: String) -> ByteCodeSymbol{
@@ -162,6 +299,24 @@ pub fn external_code_label(name: String, position: u32) -> ByteCodeSymbol{
     ByteCodeSymbol::ExternalCodeLabel { name, position }
 }
 
+pub fn external_function(name: String) -> ByteCodeSymbol{
+    ByteCodeSymbol::Function { name, linkage: ByteCodeLinkage::External }
+}
+
+pub fn static_function(name: String) -> ByteCodeSymbol{
+    ByteCodeSymbol::Function { name, linkage: ByteCodeLinkage::Static }
+}
+
+pub fn get_byte_code_symbol_name(symbol: &ByteCodeSymbol) -> &str {
+    match symbol {
+        ByteCodeSymbol::DataSectionItem { name, .. } => name,
+        ByteCodeSymbol::ForeignExternal { name } => name,
+        ByteCodeSymbol::AbsoluteExternal { name, .. } => name,
+        ByteCodeSymbol::ExternalCodeLabel { name, .. } => name,
+        ByteCodeSymbol::Function { name, .. } => name
+    }
+}
+
 pub fn add_symbol(symbols: &mut ByteCodeSymbols, symbol: ByteCodeSymbol) -> u32 {
     symbols.push(symbol);
     (symbols.len() - 1) as u32
@@ -170,7 +325,10 @@ pub fn add_symbol(symbols: &mut ByteCodeSymbols, symbol: ByteCodeSymbol) -> u32
 
 #[derive(Debug, Clone)]
 pub enum ByteCodeDataItem {
-    String { value: String }
+    String { value: String },
+    ReadOnlyString { value: String },
+    ReadOnlyBytes { value: Vec<u8> },
+    ZeroInitialised { size: u32, alignment: u32 }
 }
 
 pub type ByteCodeData = Vec<ByteCodeDataItem>;
@@ -179,7 +337,35 @@ pub fn string_data_item(value: String) -> ByteCodeDataItem{
     ByteCodeDataItem::String { value }
 }
 
+pub fn read_only_string_data_item(value: String) -> ByteCodeDataItem{
+    ByteCodeDataItem::ReadOnlyString { value }
+}
+
+pub fn read_only_bytes_data_item(value: Vec<u8>) -> ByteCodeDataItem{
+    ByteCodeDataItem::ReadOnlyBytes { value }
+}
+
+pub fn zero_initialised_data_item(size: u32, alignment: u32) -> ByteCodeDataItem{
+    ByteCodeDataItem::ZeroInitialised { size, alignment }
+}
+
 pub fn add_data_item(data: &mut ByteCodeData, item: ByteCodeDataItem) -> u32 {
     data.push(item);
     (data.len() - 1) as u32
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone)]
+pub struct ByteCodeLabel {
+    pub name: String
+}
+
+pub type ByteCodeLabels = Vec<ByteCodeLabel>;
+
+pub fn label(name: String) -> ByteCodeLabel {
+    ByteCodeLabel { name }
+}
+
+pub fn add_label(labels: &mut ByteCodeLabels, label: ByteCodeLabel) -> u32 {
+    labels.push(label);
+    (labels.len() - 1) as u32
+}
@@ -0,0 +1,22 @@
+//! Builds x86-64 COFF object files from a small byte code intermediate representation.
+//!
+//! A compilation unit is described with the [`intemediate_representation`] types, lowered into a
+//! [`Coff`] with [`build_machine_code_object`] and serialized with [`write_coff`] or [`coff_to_bytes`].
+//! Existing objects can be loaded back with [`read_coff_from_bytes`] and inspected through the
+//! `get_*` functions of the [`machine_code`] module.
+//!
+//! Units can also be lowered for Linux with [`build_elf_object`] and written as ELF64 relocatable objects with [`write_elf`],
+//! or for macOS with [`build_mach_o_object`] and written as Mach-O objects with [`write_mach_o`].
+//!
+//! Units can also be written as text and read back with [`intermediate_representation_to_text`] and
+//! [`read_intermediate_representation_from_text`].
+
+pub mod machine_code;
+pub mod intemediate_representation;
+pub mod textual_representation;
+pub mod errors;
+
+pub use intemediate_representation::*;
+pub use textual_representation::*;
+pub use machine_code::*;
+pub use errors::*;
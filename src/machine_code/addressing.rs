@@ -0,0 +1,230 @@
+use crate::machine_code::*;
+use crate::errors::*;
+
+pub const MOD_INDIRECT: u8 = 0x00;
+pub const MOD_INDIRECT_DISP8: u8 = 0x01;
+pub const MOD_INDIRECT_DISP32: u8 = 0x02;
+pub const MOD_REGISTER_DIRECT: u8 = 0x03;
+
+const OPERAND_SIZE_OVERRIDE: u8 = 0x66;
+const REX: u8 = 0x40;
+const REX_B: u8 = 0x41;
+const REX_X: u8 = 0x42;
+const REX_R: u8 = 0x44;
+const REX_W: u8 = 0x48;
+
+const RM_SIB: u8 = 0x04;
+const RM_DISP32: u8 = 0x05;
+const SIB_NO_INDEX: u8 = 0x04;
+const SIB_NO_BASE: u8 = 0x05;
+
+pub const OPERAND_SIZE_BYTE: u8 = 1;
+pub const OPERAND_SIZE_WORD: u8 = 2;
+pub const OPERAND_SIZE_DWORD: u8 = 4;
+pub const OPERAND_SIZE_QWORD: u8 = 8;
+
+pub fn mod_rm(mod_part: u8, reg_part: u8, r_m_part: u8) -> u8 {
+    mod_part << 6 | reg_part << 3 | r_m_part
+}
+
+fn sib(scale: u8, index: u8, base: u8) -> u8 {
+    (scale.trailing_zeros() as u8) << 6 | index << 3 | base
+}
+
+pub enum MemoryBase {
+    Register(u8),
+    InstructionPointer,
+    None
+}
+
+pub enum MemoryDisplacement {
+    Value(i32),
+    Relocatable(RelocatableValue)
+}
+
+pub struct MemoryIndex {
+    register: u8,
+    scale: u8
+}
+
+pub struct MemoryOperand {
+    base: MemoryBase,
+    index: Option<MemoryIndex>,
+    displacement: MemoryDisplacement
+}
+
+pub fn base_memory_operand(base_register: u8, displacement: i32) -> MemoryOperand {
+    MemoryOperand { base: MemoryBase::Register(base_register), index: None, displacement: MemoryDisplacement::Value(displacement) }
+}
+
+pub fn indexed_memory_operand(base_register: u8, index_register: u8, scale: u8, displacement: i32) -> Result<MemoryOperand, CoffError> {
+    Ok(MemoryOperand { 
+        base: MemoryBase::Register(base_register), 
+        index: Some(memory_index(index_register, scale)?), 
+        displacement: MemoryDisplacement::Value(displacement) 
+    })
+}
+
+pub fn scaled_index_memory_operand(index_register: u8, scale: u8, displacement: MemoryDisplacement) -> Result<MemoryOperand, CoffError> {
+    Ok(MemoryOperand { base: MemoryBase::None, index: Some(memory_index(index_register, scale)?), displacement })
+}
+
+pub fn absolute_memory_operand(displacement: MemoryDisplacement) -> MemoryOperand {
+    MemoryOperand { base: MemoryBase::None, index: None, displacement }
+}
+
+pub fn rip_relative_memory_operand(displacement: MemoryDisplacement) -> MemoryOperand {
+    MemoryOperand { base: MemoryBase::InstructionPointer, index: None, displacement }
+}
+
+fn memory_index(register: u8, scale: u8) -> Result<MemoryIndex, CoffError> {
+    if !matches!(scale, 1 | 2 | 4 | 8) {
+        return Err(CoffError::InvalidMemoryIndex { register, scale, reason: "the scale must be 1, 2, 4 or 8" });
+    }
+    if register == REG_SP {
+        return Err(CoffError::InvalidMemoryIndex { register, scale, reason: "rsp cannot be used as an index register" });
+    }
+    Ok(MemoryIndex { register, scale })
+}
+
+fn add_prefixes(
+    entries: &mut Vec<u8>, 
+    operand_size: u8, 
+    reg_part: u8, 
+    reg_part_is_register: bool, 
+    index_register: u8, 
+    rm_register: u8,
+    rm_is_register: bool
+) {
+    let mut rex = 0;
+
+    if operand_size == OPERAND_SIZE_WORD {
+        entries.push(OPERAND_SIZE_OVERRIDE);
+    }
+    if operand_size == OPERAND_SIZE_QWORD {
+        rex |= REX_W;
+    }
+    if reg_part_is_register && register_has_high_bit(reg_part) {
+        rex |= REX_R;
+    }
+    if register_has_high_bit(index_register) {
+        rex |= REX_X;
+    }
+    if register_has_high_bit(rm_register) {
+        rex |= REX_B;
+    }
+    if operand_size == OPERAND_SIZE_BYTE 
+        && ((rm_is_register && register_needs_rex_for_byte_access(rm_register)) 
+            || (reg_part_is_register && register_needs_rex_for_byte_access(reg_part))) {
+        rex |= REX;
+    }
+    if rex != 0 {
+        entries.push(rex);
+    }
+}
+
+pub fn add_register_in_opcode_op(coff: &mut Coff, operand_size: u8, opcode: u8, register: u8) {
+    let mut entries = vec!();
+    add_prefixes(&mut entries, operand_size, 0, false, 0, register, true);
+    entries.push(opcode + remove_register_high_bit(register));
+    add_entries_to_text_section(coff, entries);
+}
+
+/// `reg_part` is either a second register or an opcode extension.
+pub fn add_register_direct_op(coff: &mut Coff, operand_size: u8, opcode: &[u8], reg_part: u8, register: u8, reg_part_is_register: bool) {
+    let mut entries = vec!();
+    add_prefixes(&mut entries, operand_size, reg_part, reg_part_is_register, 0, register, true);
+    entries.extend_from_slice(opcode);
+    entries.push(mod_rm(MOD_REGISTER_DIRECT, remove_register_high_bit(reg_part), remove_register_high_bit(register)));
+    add_entries_to_text_section(coff, entries);
+}
+
+/// `immediate_size` is the number of immediate bytes the caller adds after the operand, which RIP relative 
+/// relocations have to account for.
+pub fn add_memory_operand_op(
+    coff: &mut Coff, 
+    operand_size: u8, 
+    opcode: &[u8], 
+    reg_part: u8, 
+    reg_part_is_register: bool, 
+    memory: MemoryOperand, 
+    immediate_size: u8
+) {
+    let mut entries = vec!();
+    let index_register = memory.index.as_ref().map_or(0, |index| index.register);
+    let base_register = match memory.base {
+        MemoryBase::Register(register) => register,
+        _ => 0
+    };
+    add_prefixes(&mut entries, operand_size, reg_part, reg_part_is_register, index_register, base_register, false);
+    entries.extend_from_slice(opcode);
+
+    let reg_part = remove_register_high_bit(reg_part);
+    let index = memory.index.as_ref().map_or(SIB_NO_INDEX, |index| remove_register_high_bit(index.register));
+    let scale = memory.index.as_ref().map_or(1, |index| index.scale);
+
+    let displacement_mod = match memory.base {
+        MemoryBase::InstructionPointer => {
+            entries.push(mod_rm(MOD_INDIRECT, reg_part, RM_DISP32));
+            None
+        },
+        MemoryBase::None => {
+            entries.push(mod_rm(MOD_INDIRECT, reg_part, RM_SIB));
+            entries.push(sib(scale, index, SIB_NO_BASE));
+            None
+        },
+        MemoryBase::Register(register) => {
+            let base = remove_register_high_bit(register);
+            let displacement_mod = match memory.displacement {
+                MemoryDisplacement::Value(0) if base != RM_DISP32 => MOD_INDIRECT,
+                MemoryDisplacement::Value(value) if i8::try_from(value).is_ok() => MOD_INDIRECT_DISP8,
+                _ => MOD_INDIRECT_DISP32
+            };
+            if memory.index.is_some() || base == RM_SIB {
+                entries.push(mod_rm(displacement_mod, reg_part, RM_SIB));
+                entries.push(sib(scale, index, base));
+            } else {
+                entries.push(mod_rm(displacement_mod, reg_part, base));
+            }
+            Some(displacement_mod)
+        }
+    };
+
+    add_entries_to_text_section(coff, entries);
+
+    match (memory.displacement, displacement_mod) {
+        (MemoryDisplacement::Value(_), Some(MOD_INDIRECT)) => {},
+        (MemoryDisplacement::Value(value), Some(MOD_INDIRECT_DISP8)) => 
+            add_entry_to_text_section(coff, value as i8 as u8),
+        (MemoryDisplacement::Value(value), _) => 
+            add_entries_to_text_section(coff, value.to_le_bytes().to_vec()),
+        (MemoryDisplacement::Relocatable(relocatable), None) if matches!(memory.base, MemoryBase::InstructionPointer) => 
+            add_relocatable_entry_and_text_section_inital_entry(coff, relocatable, rip_relative_relocation_type(immediate_size)),
+        (MemoryDisplacement::Relocatable(relocatable), _) => 
+            add_relocatable_entry_and_text_section_inital_entry(coff, relocatable, IMAGE_REL_AMD64_ADDR32),
+    }
+}
+
+/// RIP relative displacements are relative to the end of the instruction, so any immediate after them needs a `REL32_n` relocation.
+fn rip_relative_relocation_type(immediate_size: u8) -> u16 {
+    match immediate_size {
+        0 => IMAGE_REL_AMD64_REL32,
+        1 => IMAGE_REL_AMD64_REL32_1,
+        2 => IMAGE_REL_AMD64_REL32_2,
+        3 => IMAGE_REL_AMD64_REL32_3,
+        4 => IMAGE_REL_AMD64_REL32_4,
+        _ => IMAGE_REL_AMD64_REL32_5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_memory_indexes_are_rejected() {
+        assert!(matches!(indexed_memory_operand(REG_AX, REG_CX, 3, 0), Err(CoffError::InvalidMemoryIndex { scale: 3, .. })));
+        assert!(matches!(indexed_memory_operand(REG_AX, REG_SP, 1, 0), Err(CoffError::InvalidMemoryIndex { register: REG_SP, .. })));
+        assert!(scaled_index_memory_operand(REG_R8, 8, MemoryDisplacement::Value(0)).is_ok());
+    }
+}
@@ -0,0 +1,260 @@
+use std::*;
+use std::fs::File;
+use std::io::Write;
+use crate::machine_code::*;
+use crate::errors::*;
+
+const ARCHIVE_SIGNATURE: &[u8] = b"!<arch>\n";
+const ARCHIVE_MEMBER_HEADER_END: &[u8] = b"`\n";
+const ARCHIVE_MEMBER_HEADER_SIZE: usize = 60;
+const ARCHIVE_PADDING: u8 = b'\n';
+const LINKER_MEMBER_NAME: &str = "/";
+const LONG_NAMES_MEMBER_NAME: &str = "//";
+const MAX_SHORT_MEMBER_NAME_LENGTH: usize = 15;
+const SPECIAL_MEMBER_MODE: &str = "0";
+const OBJECT_MEMBER_MODE: &str = "644";
+
+pub struct CoffArchiveMember {
+    name: String,
+    data: Vec<u8>,
+    symbols: Vec<String>
+}
+
+/// A static library in the Microsoft `!<arch>` format, written with first and second linker members
+/// indexing the public symbols of its members and a long names member for member names over 15 bytes.
+pub struct CoffArchive {
+    members: Vec<CoffArchiveMember>
+}
+
+pub fn create_coff_archive() -> CoffArchive {
+    CoffArchive { members: vec!() }
+}
+
+pub fn get_archive_members(archive: &CoffArchive) -> &[CoffArchiveMember] {
+    &archive.members
+}
+
+pub fn get_archive_member_name(member: &CoffArchiveMember) -> &str {
+    &member.name
+}
+
+pub fn get_archive_member_data(member: &CoffArchiveMember) -> &[u8] {
+    &member.data
+}
+
+pub fn get_archive_member_symbols(member: &CoffArchiveMember) -> &[String] {
+    &member.symbols
+}
+
+pub fn add_archive_member(archive: &mut CoffArchive, name: &str, data: Vec<u8>, symbols: Vec<String>) -> Result<(), CoffError> {
+    if archive.members.len() >= u16::MAX as usize {
+        return Err(CoffError::TooManyArchiveMembers(archive.members.len() + 1));
+    }
+
+    for symbol in &symbols {
+        if let Some(member) = archive.members.iter().find(|member| member.symbols.contains(symbol)) {
+            return Err(CoffError::ArchiveSymbolDefinedTwice { symbol: symbol.clone(), member: member.name.clone() });
+        }
+    }
+
+    archive.members.push(CoffArchiveMember { name: string(name), data, symbols });
+    Ok(())
+}
+
+pub fn add_coff_to_archive(archive: &mut CoffArchive, name: &str, coff: &Coff) -> Result<(), CoffError> {
+    add_archive_member(archive, name, coff_to_bytes(coff)?, get_defined_external_symbol_names(coff))
+}
+
+fn archive_member_header(name: &str, mode: &str, size: usize) -> Vec<u8> {
+    let mut header = vec!();
+    for (field, width) in [(name, 16), ("0", 12), ("", 6), ("", 6), (mode, 8), (&size.to_string(), 10)] {
+        header.extend(format!("{:<width$}", field, width = width).bytes());
+    }
+    header.extend_from_slice(ARCHIVE_MEMBER_HEADER_END);
+    header
+}
+
+fn add_archive_member_entries(entries: &mut Vec<u8>, name: &str, mode: &str, data: &[u8]) {
+    entries.extend(archive_member_header(name, mode, data.len()));
+    entries.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        entries.push(ARCHIVE_PADDING);
+    }
+}
+
+fn archive_member_size(data_size: usize) -> usize {
+    ARCHIVE_MEMBER_HEADER_SIZE + data_size + data_size % 2
+}
+
+fn symbol_names_size<'a>(names: impl Iterator<Item = &'a String>) -> usize {
+    names.map(|name| name.len() + 1).sum()
+}
+
+fn archive_member_names(archive: &CoffArchive) -> (Vec<String>, Vec<u8>) {
+    let mut long_names = vec!();
+    let header_names = archive.members
+        .iter()
+        .map(|member| {
+            if member.name.len() <= MAX_SHORT_MEMBER_NAME_LENGTH {
+                return format!("{}/", member.name);
+            }
+            let offset = long_names.len();
+            long_names.extend(string_to_bytes_zero_terminated(&member.name));
+            format!("/{}", offset)
+        })
+        .collect();
+    (header_names, long_names)
+}
+
+pub fn coff_archive_to_bytes(archive: &CoffArchive) -> Result<Vec<u8>, CoffError> {
+    let (header_names, long_names) = archive_member_names(archive);
+    let symbols: Vec<(&String, usize)> = archive.members
+        .iter()
+        .enumerate()
+        .flat_map(|(member_index, member)| member.symbols.iter().map(move |symbol| (symbol, member_index)))
+        .collect();
+    let mut sorted_symbols = symbols.clone();
+    sorted_symbols.sort();
+
+    let names_size = symbol_names_size(symbols.iter().map(|(symbol, _)| *symbol));
+    let first_linker_member_size = 4 + 4 * symbols.len() + names_size;
+    let second_linker_member_size = 4 + 4 * archive.members.len() + 4 + 2 * symbols.len() + names_size;
+
+    let mut member_offset = ARCHIVE_SIGNATURE.len()
+        + archive_member_size(first_linker_member_size)
+        + archive_member_size(second_linker_member_size)
+        + archive_member_size(long_names.len());
+    let mut member_offsets = vec!();
+    for member in &archive.members {
+        member_offsets.push(u32::try_from(member_offset).map_err(|_| CoffError::OffsetTooLarge { context: "archive member" })?);
+        member_offset += archive_member_size(member.data.len());
+    }
+
+    let mut first_linker_member = vec!();
+    first_linker_member.extend((symbols.len() as u32).to_be_bytes());
+    for (_, member_index) in &symbols {
+        first_linker_member.extend(member_offsets[*member_index].to_be_bytes());
+    }
+    for (symbol, _) in &symbols {
+        first_linker_member.extend(string_to_bytes_zero_terminated(symbol));
+    }
+
+    let mut second_linker_member = vec!();
+    second_linker_member.extend((archive.members.len() as u32).to_le_bytes());
+    for offset in &member_offsets {
+        second_linker_member.extend(offset.to_le_bytes());
+    }
+    second_linker_member.extend((symbols.len() as u32).to_le_bytes());
+    for (_, member_index) in &sorted_symbols {
+        second_linker_member.extend((*member_index as u16 + 1).to_le_bytes());
+    }
+    for (symbol, _) in &sorted_symbols {
+        second_linker_member.extend(string_to_bytes_zero_terminated(symbol));
+    }
+
+    let mut entries = ARCHIVE_SIGNATURE.to_vec();
+    add_archive_member_entries(&mut entries, LINKER_MEMBER_NAME, SPECIAL_MEMBER_MODE, &first_linker_member);
+    add_archive_member_entries(&mut entries, LINKER_MEMBER_NAME, SPECIAL_MEMBER_MODE, &second_linker_member);
+    add_archive_member_entries(&mut entries, LONG_NAMES_MEMBER_NAME, SPECIAL_MEMBER_MODE, &long_names);
+    for (member, header_name) in archive.members.iter().zip(&header_names) {
+        add_archive_member_entries(&mut entries, header_name, OBJECT_MEMBER_MODE, &member.data);
+    }
+    Ok(entries)
+}
+
+pub fn write_coff_archive<W: Write>(archive: &CoffArchive, writer: &mut W) -> Result<(), CoffError> {
+    let entries = coff_archive_to_bytes(archive)?;
+    writer.write_all(&entries).map_err(|error| io_error("could not write archive", error))?;
+    writer.flush().map_err(|error| io_error("could not write archive", error))
+}
+
+pub fn write_coff_archive_to_file(archive: &CoffArchive, file: &mut File) -> Result<(), CoffError> {
+    write_coff_archive(archive, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG_MEMBER_NAME: &str = "hello-STD_OUTPUT_HANDLE.obj";
+
+    fn coff_with_functions(names: &[&str]) -> Coff {
+        let mut coff = create_coff();
+        for (index, name) in names.iter().enumerate() {
+            add_text_section_external_function_symbol(&mut coff, name, index as u32);
+            add_ret_op(&mut coff);
+        }
+        coff
+    }
+
+    fn archive_bytes() -> Vec<u8> {
+        let mut archive = create_coff_archive();
+        add_coff_to_archive(&mut archive, "print.obj", &coff_with_functions(&["print", "write"])).unwrap();
+        add_coff_to_archive(&mut archive, LONG_MEMBER_NAME, &coff_with_functions(&["main"])).unwrap();
+        coff_archive_to_bytes(&archive).unwrap()
+    }
+
+    fn archive_members(bytes: &[u8]) -> Vec<(usize, String, &[u8])> {
+        let mut members = vec!();
+        let mut offset = ARCHIVE_SIGNATURE.len();
+        while offset < bytes.len() {
+            let header = &bytes[offset..offset + ARCHIVE_MEMBER_HEADER_SIZE];
+            assert_eq!(&header[58..], ARCHIVE_MEMBER_HEADER_END);
+            let name = String::from_utf8_lossy(&header[..16]).trim_end().to_string();
+            let size: usize = String::from_utf8_lossy(&header[48..58]).trim_end().parse().unwrap();
+            let data_offset = offset + ARCHIVE_MEMBER_HEADER_SIZE;
+            members.push((offset, name, &bytes[data_offset..data_offset + size]));
+            offset = data_offset + size + size % 2;
+        }
+        members
+    }
+
+    fn symbol_names(names: &[u8]) -> Vec<String> {
+        names.split(|byte| *byte == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn archives_start_with_the_linker_and_long_names_members() {
+        let bytes = archive_bytes();
+        assert_eq!(&bytes[..ARCHIVE_SIGNATURE.len()], b"!<arch>\n");
+
+        let members = archive_members(&bytes);
+        let names: Vec<&str> = members.iter().map(|(_, name, _)| name.as_str()).collect();
+        assert_eq!(names, ["/", "/", "//", "print.obj/", "/0"]);
+        assert_eq!(symbol_names(members[2].2), [LONG_MEMBER_NAME]);
+    }
+
+    #[test]
+    fn first_linker_member_has_big_endian_offsets_in_member_order() {
+        let bytes = archive_bytes();
+        let members = archive_members(&bytes);
+        let first_linker_member = members[0].2;
+
+        let number_of_symbols = u32::from_be_bytes(first_linker_member[..4].try_into().unwrap()) as usize;
+        assert_eq!(number_of_symbols, 3);
+        let offsets: Vec<usize> = (0..number_of_symbols)
+            .map(|index| u32::from_be_bytes(first_linker_member[4 + index * 4..8 + index * 4].try_into().unwrap()) as usize)
+            .collect();
+        assert_eq!(offsets, [members[3].0, members[3].0, members[4].0]);
+        assert_eq!(symbol_names(&first_linker_member[4 + number_of_symbols * 4..]), ["print", "write", "main"]);
+    }
+
+    #[test]
+    fn second_linker_member_has_little_endian_offsets_and_sorted_symbols() {
+        let bytes = archive_bytes();
+        let members = archive_members(&bytes);
+        let second_linker_member = members[1].2;
+
+        let offsets: Vec<usize> = (0..3)
+            .map(|index| u32_from_bytes(second_linker_member, index * 4) as usize)
+            .collect();
+        assert_eq!(offsets, [2, members[3].0, members[4].0]);
+        assert_eq!(u32_from_bytes(second_linker_member, 12), 3);
+        let member_indices: Vec<u16> = (0..3).map(|index| u16_from_bytes(second_linker_member, 16 + index * 2)).collect();
+        assert_eq!(member_indices, [2, 1, 1]);
+        assert_eq!(symbol_names(&second_linker_member[22..]), ["main", "print", "write"]);
+    }
+}
@@ -1,103 +1,674 @@
+use std::collections::HashMap;
 use super::Coff;
 
 use crate::{
     machine_code::*,
-    intemediate_representation::*
+    intemediate_representation::*,
+    errors::*
 };
 
-pub fn build_machine_code_object(coff: &mut Coff, ir: IntermediateRepresentation) -> String {
-    for op in ir.byte_code  {
-        match op {
+struct DataItemLocation {
+    section_number: u16,
+    offset: u32
+}
+
+fn data_item_location(section_number: u16, offset: u32) -> DataItemLocation {
+    DataItemLocation { section_number, offset }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ObjectFormat {
+    Coff,
+    Elf,
+    MachO
+}
+
+pub fn build_machine_code_object(coff: &mut Coff, ir: IntermediateRepresentation) -> Result<String, CoffError> {
+    build_machine_code_object_for_format(coff, ir, ObjectFormat::Coff)
+}
+
+pub fn build_machine_code_object_for_format(coff: &mut Coff, ir: IntermediateRepresentation, format: ObjectFormat) -> Result<String, CoffError> {
+    let data_item_locations = ir.data
+        .iter()
+        .map(|data_item| add_data_item_to_coff(coff, data_item))
+        .collect::<Result<Vec<DataItemLocation>, CoffError>>()?;
+
+    add_debug_file_name_symbols(coff, &ir.filename);
+    add_section_header_symbols(coff)?;
+    if format == ObjectFormat::Coff {
+        add_absolute_static_symbol(coff, ".absolut", 0);
+    }
+
+    let symbol_handles = ir.symbols
+        .iter()
+        .map(|symbol| add_symbol_to_coff(coff, symbol, &data_item_locations))
+        .collect::<Result<Vec<CoffSymbolHandle>, CoffError>>()?;
+
+    let data_item_references = data_item_locations
+        .iter()
+        .map(|location| Ok(data_item_reference(get_or_add_section_symbol(coff, location.section_number)?, location.offset)))
+        .collect::<Result<Vec<DataItemReference>, CoffError>>()?;
+
+    let references = byte_code_references(symbol_handles, data_item_references, ir.labels.len());
+    let jump_sizes = select_jump_sizes(&ir.byte_code, &references, format)?;
+    let byte_code_lowering = lower_byte_code(coff, &ir.byte_code, &references, &jump_sizes, format)?;
+    patch_jumps(coff, &byte_code_lowering)?;
+    bind_function_symbols(coff, &ir.symbols, &references, &byte_code_lowering)?;
+
+    let file_root = ir.filename.replace(".hep", "");
+    match format {
+        ObjectFormat::Coff => {
+            add_unwind_info(coff, &byte_code_lowering.function_unwinds)?;
+            let object_name = format!("{}.obj", file_root);
+            add_source_debug_info(coff, &ir, &references, &byte_code_lowering, &object_name)?;
+            Ok(object_name)
+        },
+        ObjectFormat::Elf | ObjectFormat::MachO => Ok(format!("{}.o", file_root))
+    }
+}
+
+struct DataItemReference {
+    section_symbol: CoffSymbolHandle,
+    offset: u32
+}
+
+fn data_item_reference(section_symbol: CoffSymbolHandle, offset: u32) -> DataItemReference {
+    DataItemReference { section_symbol, offset }
+}
+
+struct ByteCodeReferences {
+    symbol_handles: Vec<CoffSymbolHandle>,
+    data_item_references: Vec<DataItemReference>,
+    number_of_labels: usize
+}
+
+fn byte_code_references(
+    symbol_handles: Vec<CoffSymbolHandle>, 
+    data_item_references: Vec<DataItemReference>, 
+    number_of_labels: usize
+) -> ByteCodeReferences {
+    ByteCodeReferences { symbol_handles, data_item_references, number_of_labels }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum JumpSize {
+    Short,
+    Near
+}
+
+struct JumpFixup {
+    instruction_index: usize,
+    label: u32,
+    size: JumpSize,
+    end_of_jump: u32
+}
+
+struct ByteCodeLowering {
+    function_positions: HashMap<u32, u32>,
+    function_unwinds: Vec<FunctionUnwindInfo>,
+    source_lines: Vec<SourceLine>,
+    label_positions: HashMap<u32, u32>,
+    jump_fixups: Vec<JumpFixup>
+}
+
+/// Picks the smallest encoding for every jump by lowering into a scratch object, 
+/// growing jumps whose displacement does not fit in a rel8 until no more need to grow.
+fn select_jump_sizes(
+    byte_code: &[ByteCodeInstruction], 
+    references: &ByteCodeReferences, 
+    format: ObjectFormat
+) -> Result<Vec<JumpSize>, CoffError> {
+    let mut jump_sizes = vec![JumpSize::Short; byte_code.len()];
+
+    loop {
+        let mut scratch = create_coff();
+        let byte_code_lowering = lower_byte_code(&mut scratch, byte_code, references, &jump_sizes, format)?;
+        let mut grown = false;
+
+        for fixup in &byte_code_lowering.jump_fixups {
+            let displacement = get_jump_displacement(&byte_code_lowering, fixup)?;
+            if fixup.size == JumpSize::Short && i8::try_from(displacement).is_err() {
+                jump_sizes[fixup.instruction_index] = JumpSize::Near;
+                grown = true;
+            }
+        }
+
+        if !grown {
+            return Ok(jump_sizes);
+        }
+    }
+}
+
+fn lower_byte_code(
+    coff: &mut Coff, 
+    byte_code: &[ByteCodeInstruction], 
+    references: &ByteCodeReferences, 
+    jump_sizes: &[JumpSize],
+    format: ObjectFormat
+) -> Result<ByteCodeLowering, CoffError> {
+    let get_register = |register| get_register_for_format(register, format);
+    let get_divisor_register = |divisor| get_divisor_register_for_format(divisor, format);
+    let get_shifted_register = |register| get_shifted_register_for_format(register, format);
+    let mut byte_code_lowering = ByteCodeLowering { 
+        function_positions: HashMap::new(), 
+        function_unwinds: vec!(), 
+        source_lines: vec!(),
+        label_positions: HashMap::new(), 
+        jump_fixups: vec!() 
+    };
+
+    for (instruction_index, op) in byte_code.iter().enumerate() {
+        match *op {
             ByteCodeInstruction::CallToSymbol(symbol_index) => 
                 add_call_relocatable_addr_op(
                     coff, 
-                    relocatable_value(convert_byte_code_to_coff_symbol_index(ir.symbols.len(), symbol_index), 
-                    0x0)
+                    relocatable_value(get_symbol_handle(&references.symbol_handles, symbol_index)?, 0x0)
                 ),
             ByteCodeInstruction::PushReg64(register) => 
-                add_push_reg_op(coff, get_register(register)),
+                add_push_reg_op(coff, get_register(register)?),
             ByteCodeInstruction::PopReg64(register) =>
-                add_pop_reg_op(coff, get_register(register)),    
+                add_pop_reg_op(coff, get_register(register)?),    
             ByteCodeInstruction::MoveSymbolToReg32 { symbol_index, to } => 
                 add_mov_dword_relocatable_value_to_reg_op(
                     coff,
-                    relocatable_value(convert_byte_code_to_coff_symbol_index(ir.symbols.len(), symbol_index), 0x0), 
-                    get_register(to)
+                    relocatable_value(get_symbol_handle(&references.symbol_handles, symbol_index)?, 0x0), 
+                    get_register(to)?
                 ),      
             ByteCodeInstruction::MoveValueToReg32 { value, to } => 
-                add_mov_dword_value_to_reg_op(coff, value, get_register(to)),      
+                add_mov_dword_value_to_reg_op(coff, value, get_register(to)?),      
             ByteCodeInstruction::MoveRegToReg64 { from, to } => 
-                add_mov_from_qword_reg_to_reg_op(coff, get_register(from), get_register(to)),
+                add_mov_from_qword_reg_to_reg_op(coff, get_register(from)?, get_register(to)?),
             ByteCodeInstruction::MoveValueToRegPlusOffset32 { value, to, offset } => 
-                add_mov_dword_value_into_reg_plus_offset_pointer_op(coff, value, get_register(to), offset),
+                add_mov_dword_value_into_reg_plus_offset_pointer_op(coff, value, get_register(to)?, offset),
             ByteCodeInstruction::MoveRegToRegPlusOffset32 { from, to, offset } => 
-                add_mov_reg_to_reg_plus_offset_dword_pointer_op(coff, get_register(from), get_register(to), offset),
+                add_mov_reg_to_reg_plus_offset_dword_pointer_op(coff, get_register(from)?, get_register(to)?, offset),
             ByteCodeInstruction::MoveRegToRegPlusOffset64 { from, to, offset } => 
-                add_mov_reg_to_reg_plus_offset_qword_pointer_op(coff, get_register(from), get_register(to), offset),
+                add_mov_reg_to_reg_plus_offset_qword_pointer_op(coff, get_register(from)?, get_register(to)?, offset),
             ByteCodeInstruction::MoveRegPlusOffsetToReg32 { from, offset, to } => 
-                add_mov_dword_reg_plus_offset_pointer_to_reg_op(coff, get_register(from), offset, get_register(to)),
+                add_mov_dword_reg_plus_offset_pointer_to_reg_op(coff, get_register(from)?, offset, get_register(to)?),
             ByteCodeInstruction::MoveRegPlusOffsetToReg64 { from, offset, to } => 
-                add_mov_qword_reg_plus_offset_pointer_to_reg_op(coff, get_register(from), offset, get_register(to)),
+                add_mov_qword_reg_plus_offset_pointer_to_reg_op(coff, get_register(from)?, offset, get_register(to)?),
             ByteCodeInstruction::SubValueFromReg8 { value, from } => 
-                add_sub_byte_value_from_reg_op(coff, value, get_register(from)),
+                add_sub_byte_value_from_reg_op(coff, value, get_register(from)?),
             ByteCodeInstruction::AddValueToReg8 { value, to } => 
-                add_add_byte_value_to_reg_op(coff, value, get_register(to)),
+                add_add_byte_value_to_reg_op(coff, value, get_register(to)?),
             ByteCodeInstruction::ZeroReg64(register) =>
-                add_xor_qword_reg_into_reg_op(coff, get_register(register), get_register(register)),
+                add_xor_qword_reg_into_reg_op(coff, get_register(register)?, get_register(register)?),
+            ByteCodeInstruction::ArithmeticRegToReg { operation, width, from, to } => 
+                match get_alu_operation(operation) {
+                    Some(alu_operation) => 
+                        add_alu_reg_into_reg_op(coff, alu_operation, get_operand_size(width), get_register(from)?, get_register(to)?),
+                    None => 
+                        add_imul_reg_into_reg_op(coff, get_multiply_operand_size(width)?, get_register(from)?, get_register(to)?)
+                },
+            ByteCodeInstruction::ArithmeticValueToReg { operation, width, value, to } => 
+                match get_alu_operation(operation) {
+                    Some(alu_operation) => 
+                        add_alu_value_into_reg_op(coff, alu_operation, get_operand_size(width), check_value_fits_width(value, width)?, get_register(to)?),
+                    None => 
+                        add_imul_value_into_reg_op(coff, get_multiply_operand_size(width)?, check_value_fits_width(value, width)?, get_register(to)?)
+                },
+            ByteCodeInstruction::NotReg { width, register } => 
+                add_not_reg_op(coff, get_operand_size(width), get_register(register)?),
+            ByteCodeInstruction::NegateReg { width, register } => 
+                add_neg_reg_op(coff, get_operand_size(width), get_register(register)?),
+            ByteCodeInstruction::ShiftRegByValue { shift, width, value, register } => 
+                add_shift_reg_by_value_op(coff, get_shift(shift), get_operand_size(width), check_shift_count_fits_width(value, width)?, get_register(register)?),
+            ByteCodeInstruction::ShiftRegByCount { shift, width, register } => 
+                add_shift_reg_by_cl_op(coff, get_shift(shift), get_operand_size(width), get_shifted_register(register)?),
+            ByteCodeInstruction::Divide { signed, width, divisor } => {
+                let divisor_register = get_divisor_register(divisor)?;
+                if signed {
+                    add_sign_extend_dividend_op(coff, get_operand_size(width));
+                    add_idiv_reg_op(coff, get_operand_size(width), divisor_register);
+                } else {
+                    add_zero_extend_dividend_op(coff, get_operand_size(width));
+                    add_div_reg_op(coff, get_operand_size(width), divisor_register);
+                }
+            },
+            ByteCodeInstruction::DefineFunction(symbol_index) => {
+                get_symbol_handle(&references.symbol_handles, symbol_index)?;
+                let position = get_current_text_section_pointer(coff);
+                if byte_code_lowering.function_positions.insert(symbol_index, position).is_some() {
+                    return Err(CoffError::FunctionDefinedTwice(symbol_index));
+                }
+                byte_code_lowering.function_unwinds.push(function_unwind_info(position));
+            },
+            ByteCodeInstruction::DefineLabel(label) => {
+                check_label(references, label)?;
+                let position = get_current_text_section_pointer(coff);
+                if byte_code_lowering.label_positions.insert(label, position).is_some() {
+                    return Err(CoffError::LabelDefinedTwice(label));
+                }
+            },
+            ByteCodeInstruction::SourceLocation { line, column } => {
+                if line > MAX_SOURCE_LINE_NUMBER {
+                    return Err(CoffError::LineNumberTooLarge(line));
+                }
+                let position = get_current_text_section_pointer(coff);
+                let source_lines = &mut byte_code_lowering.source_lines;
+                if source_lines.last().is_some_and(|source_line| source_line.offset == position) {
+                    source_lines.pop();
+                }
+                source_lines.push(source_line(position, line, column));
+            },
+            ByteCodeInstruction::Jump(label) => {
+                check_label(references, label)?;
+                match jump_sizes[instruction_index] {
+                    JumpSize::Short => add_jmp_short_op(coff, 0),
+                    JumpSize::Near => add_jmp_near_op(coff, 0)
+                }
+                add_jump_fixup(coff, &mut byte_code_lowering, instruction_index, label, jump_sizes[instruction_index]);
+            },
+            ByteCodeInstruction::JumpIf { condition, label } => {
+                check_label(references, label)?;
+                match jump_sizes[instruction_index] {
+                    JumpSize::Short => add_jcc_short_op(coff, get_condition(condition), 0),
+                    JumpSize::Near => add_jcc_near_op(coff, get_condition(condition), 0)
+                }
+                add_jump_fixup(coff, &mut byte_code_lowering, instruction_index, label, jump_sizes[instruction_index]);
+            },
             ByteCodeInstruction::Return => add_ret_op(coff),
-            ByteCodeInstruction::LoadDataSectionAddressToReg64 { data_section_offset, to } => 
-                add_lea_reg_plus_offset_pointer_to_reg_op(
+            ByteCodeInstruction::LoadDataSectionAddressToReg64 { data_item, to } => {
+                let reference = get_data_item_reference(&references.data_item_references, data_item)?;
+                add_lea_rip_relative_to_reg_op(
                     coff, 
-                    REG_IP, 
-                    relocatable_value(0x02, data_section_offset), 
-                    get_register(to)
-                ),
+                    relocatable_value(reference.section_symbol, reference.offset), 
+                    get_register(to)?
+                )
+            },
+        }
+
+        if let ByteCodeInstruction::DefineFunction(_) | ByteCodeInstruction::SourceLocation { .. } = *op {
+            continue;
+        }
+        if let Some(function_unwind) = byte_code_lowering.function_unwinds.last_mut() {
+            record_prologue_operation(function_unwind, get_prologue_operation(op, format)?, get_current_text_section_pointer(coff));
         }
     }
 
-    for data_item in ir.data {
-        match data_item {
-            ByteCodeDataItem::String { value } => add_string_to_data_section(coff, &value),
-        };
+    Ok(byte_code_lowering)
+}
+
+fn get_prologue_operation(instruction: &ByteCodeInstruction, format: ObjectFormat) -> Result<Option<UnwindOperation>, CoffError> {
+    let get_register = |register| get_register_for_format(register, format);
+    Ok(match *instruction {
+        ByteCodeInstruction::PushReg64(register) => 
+            Some(UnwindOperation::PushNonVolatile(get_register(register)?)),
+        ByteCodeInstruction::MoveRegToReg64 { from: ByteCodeRegister::StackPointer, to: ByteCodeRegister::BasePointer } => 
+            Some(UnwindOperation::SetFramePointer(REG_BP)),
+        ByteCodeInstruction::SubValueFromReg8 { value, from: ByteCodeRegister::StackPointer } if value <= i8::MAX as u8 => 
+            Some(UnwindOperation::Allocate(value as u32)),
+        ByteCodeInstruction::ArithmeticValueToReg { 
+            operation: ByteCodeArithmetic::Subtract, 
+            width: ByteCodeWidth::QWord, 
+            value, 
+            to: ByteCodeRegister::StackPointer 
+        } => u32::try_from(value).ok().map(UnwindOperation::Allocate),
+        _ => None
+    })
+}
+
+fn add_jump_fixup(coff: &Coff, byte_code_lowering: &mut ByteCodeLowering, instruction_index: usize, label: u32, size: JumpSize) {
+    byte_code_lowering.jump_fixups.push(JumpFixup { 
+        instruction_index, 
+        label, 
+        size, 
+        end_of_jump: get_current_text_section_pointer(coff) 
+    });
+}
+
+fn get_jump_displacement(byte_code_lowering: &ByteCodeLowering, fixup: &JumpFixup) -> Result<i64, CoffError> {
+    match byte_code_lowering.label_positions.get(&fixup.label) {
+        Some(position) => Ok(*position as i64 - fixup.end_of_jump as i64),
+        None => Err(CoffError::LabelNotDefined(fixup.label))
     }
+}
 
-    add_debug_file_name_symbols(coff, &ir.filename);
-    add_data_section_header_symbols(coff);
-    add_text_section_header_symbols(coff);
-    add_absolute_static_symbol(coff, ".absolut", 0);
-    
-    for symbol_index in (0..ir.symbols.len()).rev() {
-        match &ir.symbols[symbol_index] {
-            ByteCodeSymbol::DataSectionItem { name, value } => add_data_section_static_symbol(coff, name, *value),
-            ByteCodeSymbol::ForeignExternal { name } => add_foreign_external_symbol(coff, name),
-            ByteCodeSymbol::AbsoluteExternal { name, value } => add_absolute_external_symbol(coff, name, *value),
-            ByteCodeSymbol::ExternalCodeLabel { name, position } => add_text_section_external_symbol(coff, name, *position),
+fn patch_jumps(coff: &mut Coff, byte_code_lowering: &ByteCodeLowering) -> Result<(), CoffError> {
+    for fixup in &byte_code_lowering.jump_fixups {
+        let displacement = get_jump_displacement(byte_code_lowering, fixup)?;
+        match fixup.size {
+            JumpSize::Short => 
+                patch_entries_in_text_section(coff, fixup.end_of_jump - 1, &(displacement as i8).to_le_bytes()),
+            JumpSize::Near => 
+                patch_entries_in_text_section(coff, fixup.end_of_jump - 4, &(displacement as i32).to_le_bytes())
         }
     }
+    Ok(())
+}
 
-    let file_root = ir.filename.replace(".hep", "");
-    format!("{}-{}.obj", file_root, ir.top_level_symbol)
+fn check_label(references: &ByteCodeReferences, label: u32) -> Result<(), CoffError> {
+    if label as usize >= references.number_of_labels {
+        return Err(CoffError::LabelIndexOutOfRange { label, number_of_labels: references.number_of_labels });
+    }
+    Ok(())
+}
 
+fn bind_function_symbols(
+    coff: &mut Coff, 
+    symbols: &[ByteCodeSymbol], 
+    references: &ByteCodeReferences, 
+    byte_code_lowering: &ByteCodeLowering
+) -> Result<(), CoffError> {
+    for (symbol_index, symbol) in symbols.iter().enumerate() {
+        let symbol_index = symbol_index as u32;
+        let position = byte_code_lowering.function_positions.get(&symbol_index);
+        match (symbol, position) {
+            (ByteCodeSymbol::Function { .. }, Some(position)) => 
+                set_symbol_value(coff, get_symbol_handle(&references.symbol_handles, symbol_index)?, *position)?,
+            (ByteCodeSymbol::Function { .. }, None) => 
+                return Err(CoffError::FunctionNotDefined(symbol_index)),
+            (_, Some(_)) => 
+                return Err(CoffError::NotAFunction(symbol_index)),
+            (_, None) => {}
+        }
+    }
+    Ok(())
 }
 
-fn convert_byte_code_to_coff_symbol_index(number_of_symbols: usize, symbol_index: u32) -> u32 {
-    (6 + number_of_symbols) as u32 - symbol_index
+fn add_source_debug_info(
+    coff: &mut Coff,
+    ir: &IntermediateRepresentation,
+    references: &ByteCodeReferences,
+    byte_code_lowering: &ByteCodeLowering,
+    object_name: &str
+) -> Result<(), CoffError> {
+    if byte_code_lowering.source_lines.is_empty() {
+        return Ok(());
+    }
+
+    let defined_functions = ir.byte_code
+        .iter()
+        .filter_map(|instruction| match *instruction {
+            ByteCodeInstruction::DefineFunction(symbol_index) => Some(symbol_index),
+            _ => None
+        });
+
+    let mut functions = vec!();
+    for (symbol_index, extent) in defined_functions.zip(&byte_code_lowering.function_unwinds) {
+        let ByteCodeSymbol::Function { name, linkage } = &ir.symbols[symbol_index as usize] else {
+            return Err(CoffError::NotAFunction(symbol_index));
+        };
+        let lines = byte_code_lowering.source_lines
+            .iter()
+            .filter(|line| line.offset >= extent.start && line.offset < extent.end)
+            .map(|line| source_line(line.offset - extent.start, line.line, line.column))
+            .collect();
+        functions.push(function_debug_info(
+            get_symbol_handle(&references.symbol_handles, symbol_index)?,
+            name,
+            *linkage == ByteCodeLinkage::External,
+            extent.end - extent.start,
+            extent.unwind_codes.last().map_or(0, |code| code.prologue_offset as u32),
+            lines
+        ));
+    }
+
+    add_code_view_debug_info(coff, object_name, &ir.filename, &functions)
 }
 
-fn get_register(register: ByteCodeRegister) -> u8 {
+fn add_data_item_to_coff(coff: &mut Coff, data_item: &ByteCodeDataItem) -> Result<DataItemLocation, CoffError> {
+    Ok(match data_item {
+        ByteCodeDataItem::String { value } => {
+            let offset = add_string_to_data_section(coff, value);
+            data_item_location(get_or_add_data_section_number(coff), offset)
+        },
+        ByteCodeDataItem::ReadOnlyString { value } => {
+            let offset = add_string_to_read_only_data_section(coff, value);
+            data_item_location(get_or_add_read_only_data_section_number(coff), offset)
+        },
+        ByteCodeDataItem::ReadOnlyBytes { value } => {
+            let offset = add_bytes_to_read_only_data_section(coff, value.clone());
+            data_item_location(get_or_add_read_only_data_section_number(coff), offset)
+        },
+        ByteCodeDataItem::ZeroInitialised { size, alignment } => {
+            let offset = reserve_uninitialised_data(coff, *size, *alignment)?;
+            data_item_location(get_or_add_uninitialised_data_section_number(coff), offset)
+        },
+    })
+}
+
+fn add_symbol_to_coff(
+    coff: &mut Coff, 
+    symbol: &ByteCodeSymbol, 
+    data_item_locations: &[DataItemLocation]
+) -> Result<CoffSymbolHandle, CoffError> {
+    Ok(match symbol {
+        ByteCodeSymbol::DataSectionItem { name, data_item } => {
+            let location = get_data_item_location(data_item_locations, *data_item)?;
+            add_section_static_symbol(coff, location.section_number, name, location.offset)
+        },
+        ByteCodeSymbol::ForeignExternal { name } => add_foreign_external_symbol(coff, name),
+        ByteCodeSymbol::AbsoluteExternal { name, value } => add_absolute_external_symbol(coff, name, *value),
+        ByteCodeSymbol::ExternalCodeLabel { name, position } => add_text_section_external_symbol(coff, name, *position),
+        ByteCodeSymbol::Function { name, linkage: ByteCodeLinkage::External } => add_text_section_external_function_symbol(coff, name, 0),
+        ByteCodeSymbol::Function { name, linkage: ByteCodeLinkage::Static } => add_text_section_static_function_symbol(coff, name, 0),
+    })
+}
+
+fn get_data_item_location(data_item_locations: &[DataItemLocation], data_item: u32) -> Result<&DataItemLocation, CoffError> {
+    data_item_locations
+        .get(data_item as usize)
+        .ok_or(CoffError::DataItemIndexOutOfRange { data_item, number_of_data_items: data_item_locations.len() })
+}
+
+fn get_data_item_reference(data_item_references: &[DataItemReference], data_item: u32) -> Result<&DataItemReference, CoffError> {
+    data_item_references
+        .get(data_item as usize)
+        .ok_or(CoffError::DataItemIndexOutOfRange { data_item, number_of_data_items: data_item_references.len() })
+}
+
+fn get_symbol_handle(symbol_handles: &[CoffSymbolHandle], symbol_index: u32) -> Result<CoffSymbolHandle, CoffError> {
+    symbol_handles
+        .get(symbol_index as usize)
+        .copied()
+        .ok_or(CoffError::SymbolIndexOutOfRange { symbol_index, number_of_symbols: symbol_handles.len() })
+}
+
+pub fn get_register(register: ByteCodeRegister) -> Result<u8, CoffError> {
+    get_register_for_format(register, ObjectFormat::Coff)
+}
+
+pub fn get_register_for_format(register: ByteCodeRegister, format: ObjectFormat) -> Result<u8, CoffError> {
     match register {
-        ByteCodeRegister::CallArg(number) => match number {
-            0 => REG_CX,
-            1 => REG_DX,
-            2 => REG_R8,
-            3 => REG_R9,
-            r => panic!("call register {} should be passed on stack", r)
+        ByteCodeRegister::CallArg(number) => match (format, number) {
+            (ObjectFormat::Coff, 0) => Ok(REG_CX),
+            (ObjectFormat::Coff, 1) => Ok(REG_DX),
+            (ObjectFormat::Coff, 2) => Ok(REG_R8),
+            (ObjectFormat::Coff, 3) => Ok(REG_R9),
+            (ObjectFormat::Coff, _) => Err(CoffError::UnsupportedRegister { register, reason: "call args after the fourth are passed on the stack" }),
+            (_, 0) => Ok(REG_DI),
+            (_, 1) => Ok(REG_SI),
+            (_, 2) => Ok(REG_DX),
+            (_, 3) => Ok(REG_CX),
+            (_, 4) => Ok(REG_R8),
+            (_, 5) => Ok(REG_R9),
+            (_, _) => Err(CoffError::UnsupportedRegister { register, reason: "call args after the sixth are passed on the stack" })
         },
         ByteCodeRegister::CallReturnArg(number) => match number {
-            0 => REG_AX,
-            _ => todo!()
+            0 => Ok(REG_AX),
+            _ => Err(CoffError::UnsupportedRegister { register, reason: "only one return register is available" })
         },
-        ByteCodeRegister::StackPointer => REG_SP,
-        ByteCodeRegister::BasePointer => REG_BP
+        ByteCodeRegister::StackPointer => Ok(REG_SP),
+        ByteCodeRegister::BasePointer => Ok(REG_BP),
+        ByteCodeRegister::ShiftCount => Ok(REG_CX),
+        ByteCodeRegister::Remainder => Ok(REG_DX)
+    }
+}
+
+pub fn get_divisor_register(divisor: ByteCodeRegister) -> Result<u8, CoffError> {
+    get_divisor_register_for_format(divisor, ObjectFormat::Coff)
+}
+
+pub fn get_shifted_register_for_format(register: ByteCodeRegister, format: ObjectFormat) -> Result<u8, CoffError> {
+    let shifted_register = get_register_for_format(register, format)?;
+    if shifted_register == REG_CX {
+        return Err(CoffError::UnsupportedRegister { register, reason: "the shifted register cannot be the shift count register" });
+    }
+    Ok(shifted_register)
+}
+
+pub fn get_divisor_register_for_format(divisor: ByteCodeRegister, format: ObjectFormat) -> Result<u8, CoffError> {
+    let divisor_register = get_register_for_format(divisor, format)?;
+    if divisor_register == REG_AX || divisor_register == REG_DX {
+        return Err(CoffError::UnsupportedRegister { register: divisor, reason: "the divisor cannot be one of the dividend registers" });
+    }
+    Ok(divisor_register)
+}
+
+fn get_condition(condition: ByteCodeCondition) -> u8 {
+    match condition {
+        ByteCodeCondition::Equal => CONDITION_EQUAL,
+        ByteCodeCondition::NotEqual => CONDITION_NOT_EQUAL,
+        ByteCodeCondition::Less => CONDITION_LESS,
+        ByteCodeCondition::LessOrEqual => CONDITION_LESS_OR_EQUAL,
+        ByteCodeCondition::Greater => CONDITION_GREATER,
+        ByteCodeCondition::GreaterOrEqual => CONDITION_GREATER_OR_EQUAL,
+        ByteCodeCondition::Below => CONDITION_BELOW,
+        ByteCodeCondition::BelowOrEqual => CONDITION_BELOW_OR_EQUAL,
+        ByteCodeCondition::Above => CONDITION_ABOVE,
+        ByteCodeCondition::AboveOrEqual => CONDITION_ABOVE_OR_EQUAL
+    }
+}
+
+fn get_operand_size(width: ByteCodeWidth) -> u8 {
+    match width {
+        ByteCodeWidth::Byte => OPERAND_SIZE_BYTE,
+        ByteCodeWidth::Word => OPERAND_SIZE_WORD,
+        ByteCodeWidth::DWord => OPERAND_SIZE_DWORD,
+        ByteCodeWidth::QWord => OPERAND_SIZE_QWORD
+    }
+}
+
+pub fn get_multiply_operand_size(width: ByteCodeWidth) -> Result<u8, CoffError> {
+    match width {
+        ByteCodeWidth::Byte => Err(CoffError::UnsupportedWidth { instruction: "multiply", width_in_bits: 8 }),
+        _ => Ok(get_operand_size(width))
+    }
+}
+
+/// Byte and word immediates may be given signed or unsigned, wider ones are sign extended 32 bit values.
+pub fn check_value_fits_width(value: i32, width: ByteCodeWidth) -> Result<i32, CoffError> {
+    let fits = match width {
+        ByteCodeWidth::Byte => (i8::MIN as i32..=u8::MAX as i32).contains(&value),
+        ByteCodeWidth::Word => (i16::MIN as i32..=u16::MAX as i32).contains(&value),
+        ByteCodeWidth::DWord | ByteCodeWidth::QWord => true
+    };
+    if !fits {
+        return Err(CoffError::ValueTooLargeForWidth { value, width_in_bits: get_operand_size(width) as u32 * 8 });
+    }
+    Ok(value)
+}
+
+/// The CPU masks shift counts to the operand size, so larger counts would silently shift by less.
+pub fn check_shift_count_fits_width(count: u8, width: ByteCodeWidth) -> Result<u8, CoffError> {
+    let width_in_bits = get_operand_size(width) as u32 * 8;
+    if count as u32 >= width_in_bits {
+        return Err(CoffError::ShiftCountTooLarge { count, width_in_bits });
+    }
+    Ok(count)
+}
+
+/// Multiply has no ALU group encoding and is lowered to `imul` instead.
+fn get_alu_operation(operation: ByteCodeArithmetic) -> Option<u8> {
+    match operation {
+        ByteCodeArithmetic::Add => Some(ALU_ADD),
+        ByteCodeArithmetic::Subtract => Some(ALU_SUB),
+        ByteCodeArithmetic::And => Some(ALU_AND),
+        ByteCodeArithmetic::Or => Some(ALU_OR),
+        ByteCodeArithmetic::Xor => Some(ALU_XOR),
+        ByteCodeArithmetic::Compare => Some(ALU_CMP),
+        ByteCodeArithmetic::Multiply => None
+    }
+}
+
+fn get_shift(shift: ByteCodeShift) -> u8 {
+    match shift {
+        ByteCodeShift::Left => SHIFT_LEFT,
+        ByteCodeShift::Right => SHIFT_RIGHT,
+        ByteCodeShift::ArithmeticRight => SHIFT_ARITHMETIC_RIGHT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jumps_ir() -> IntermediateRepresentation {
+        create_intermediate_representation(compilation_unit_id(1), string("jumps.hep"))
+    }
+
+    fn add_returns(ir: &mut IntermediateRepresentation, count: usize) {
+        for _ in 0..count {
+            add_byte_code(&mut ir.byte_code, ret_instruction());
+        }
+    }
+
+    fn build_text(ir: IntermediateRepresentation) -> Result<Vec<u8>, CoffError> {
+        let mut coff = create_coff();
+        build_machine_code_object(&mut coff, ir)?;
+        let text = get_or_add_text_section_number(&mut coff);
+        Ok(get_section_data(get_section(&coff, text)?).to_vec())
+    }
+
+    #[test]
+    fn jumps_within_a_byte_displacement_stay_short() {
+        let mut ir = jumps_ir();
+        let forward = add_label(&mut ir.labels, label(string("forward")));
+        let backward = add_label(&mut ir.labels, label(string("backward")));
+        add_byte_code(&mut ir.byte_code, jump_instruction(forward));
+        add_returns(&mut ir, 1);
+        add_byte_code(&mut ir.byte_code, define_label_instruction(forward));
+        add_byte_code(&mut ir.byte_code, define_label_instruction(backward));
+        add_returns(&mut ir, 1);
+        add_byte_code(&mut ir.byte_code, jump_if_instruction(ByteCodeCondition::NotEqual, backward));
+
+        assert_eq!(build_text(ir).unwrap(), [0xEB, 0x01, 0xC3, 0xC3, 0x75, 0xFD]);
+    }
+
+    #[test]
+    fn jumps_beyond_a_byte_displacement_grow_to_near() {
+        let mut ir = jumps_ir();
+        let backward = add_label(&mut ir.labels, label(string("backward")));
+        add_byte_code(&mut ir.byte_code, define_label_instruction(backward));
+        add_returns(&mut ir, 170);
+        add_byte_code(&mut ir.byte_code, jump_if_instruction(ByteCodeCondition::NotEqual, backward));
+
+        let text = build_text(ir).unwrap();
+        assert_eq!(text.len(), 176);
+        assert_eq!(&text[170..], &[0x0F, 0x85, 0x50, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn jumps_grow_when_a_jump_they_cross_grows() {
+        let mut ir = jumps_ir();
+        let near = add_label(&mut ir.labels, label(string("near")));
+        let far = add_label(&mut ir.labels, label(string("far")));
+        add_byte_code(&mut ir.byte_code, jump_instruction(near));
+        add_returns(&mut ir, 124);
+        add_byte_code(&mut ir.byte_code, jump_instruction(far));
+        add_byte_code(&mut ir.byte_code, define_label_instruction(near));
+        add_returns(&mut ir, 200);
+        add_byte_code(&mut ir.byte_code, define_label_instruction(far));
+
+        let text = build_text(ir).unwrap();
+        assert_eq!(text.len(), 334);
+        assert_eq!(&text[..5], &[0xE9, 0x81, 0x00, 0x00, 0x00]);
+        assert_eq!(&text[129..134], &[0xE9, 0xC8, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn labels_must_be_defined_once() {
+        let mut ir = jumps_ir();
+        let undefined = add_label(&mut ir.labels, label(string("undefined")));
+        add_byte_code(&mut ir.byte_code, jump_instruction(undefined));
+        assert!(matches!(build_text(ir), Err(CoffError::LabelNotDefined(0))));
+
+        let mut ir = jumps_ir();
+        let twice = add_label(&mut ir.labels, label(string("twice")));
+        add_byte_code(&mut ir.byte_code, define_label_instruction(twice));
+        add_byte_code(&mut ir.byte_code, define_label_instruction(twice));
+        assert!(matches!(build_text(ir), Err(CoffError::LabelDefinedTwice(0))));
     }
 }
@@ -0,0 +1,400 @@
+use crate::machine_code::*;
+use crate::errors::*;
+
+const CV_SIGNATURE_C13: u32 = 4;
+const DEBUG_S_SYMBOLS: u32 = 0xF1;
+const DEBUG_S_LINES: u32 = 0xF2;
+const DEBUG_S_STRINGTABLE: u32 = 0xF3;
+const DEBUG_S_FILECHKSMS: u32 = 0xF4;
+const S_END: u16 = 0x0006;
+const S_OBJNAME: u16 = 0x1101;
+const S_LPROC32: u16 = 0x110F;
+const S_GPROC32: u16 = 0x1110;
+const S_COMPILE3: u16 = 0x113C;
+const LF_PROCEDURE: u16 = 0x1008;
+const LF_ARGLIST: u16 = 0x1201;
+const LF_PAD0: u8 = 0xF0;
+const T_VOID: u32 = 0x0003;
+const FIRST_TYPE_INDEX: u32 = 0x1000;
+const VOID_ARGUMENT_LIST_TYPE_INDEX: u32 = FIRST_TYPE_INDEX;
+const VOID_PROCEDURE_TYPE_INDEX: u32 = FIRST_TYPE_INDEX + 1;
+const CV_CALL_NEAR_C: u8 = 0x00;
+const CV_CFL_C: u32 = 0x00;
+const CV_CFL_X64: u16 = 0xD0;
+const CV_LINES_HAVE_COLUMNS: u16 = 0x0001;
+const CV_LINE_IS_STATEMENT: u32 = 0x80000000;
+const CHKSUM_TYPE_NONE: u8 = 0;
+const COMPILER_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+const RECORD_ALIGNMENT: usize = 4;
+
+pub const MAX_SOURCE_LINE_NUMBER: u32 = 0x00FFFFFF;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SourceLine {
+    pub offset: u32,
+    pub line: u32,
+    pub column: u16
+}
+
+pub fn source_line(offset: u32, line: u32, column: u16) -> SourceLine {
+    SourceLine { offset, line, column }
+}
+
+/// A function to describe to debuggers, with its line offsets relative to the function symbol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDebugInfo {
+    pub symbol: CoffSymbolHandle,
+    pub name: String,
+    pub external: bool,
+    pub length: u32,
+    pub prologue_size: u32,
+    pub lines: Vec<SourceLine>
+}
+
+pub fn function_debug_info(
+    symbol: CoffSymbolHandle,
+    name: &str,
+    external: bool,
+    length: u32,
+    prologue_size: u32,
+    lines: Vec<SourceLine>
+) -> FunctionDebugInfo {
+    FunctionDebugInfo { symbol, name: string(name), external, length, prologue_size, lines }
+}
+
+struct CodeViewEntries {
+    bytes: Vec<u8>,
+    relocations: Vec<(u32, CoffSymbolHandle, u16)>
+}
+
+fn code_view_entries() -> CodeViewEntries {
+    CodeViewEntries { bytes: vec!(), relocations: vec!() }
+}
+
+impl CodeViewEntries {
+    fn add_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn add_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn add_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn add_string(&mut self, value: &str) {
+        self.bytes.extend(string_to_bytes_zero_terminated(value));
+    }
+
+    fn add_section_offset(&mut self, symbol: CoffSymbolHandle) {
+        self.relocations.push((self.bytes.len() as u32, symbol, IMAGE_REL_AMD64_SECREL));
+        self.add_u32(0);
+    }
+
+    fn add_section_index(&mut self, symbol: CoffSymbolHandle) {
+        self.relocations.push((self.bytes.len() as u32, symbol, IMAGE_REL_AMD64_SECTION));
+        self.add_u16(0);
+    }
+
+    fn add_entries(&mut self, entries: CodeViewEntries) {
+        let base = self.bytes.len() as u32;
+        self.relocations.extend(entries.relocations.into_iter().map(|(offset, symbol, relocation_type)| (base + offset, symbol, relocation_type)));
+        self.bytes.extend(entries.bytes);
+    }
+
+    fn pad(&mut self, padding: impl Fn(usize) -> u8) {
+        while !self.bytes.len().is_multiple_of(RECORD_ALIGNMENT) {
+            let remaining = RECORD_ALIGNMENT - self.bytes.len() % RECORD_ALIGNMENT;
+            self.bytes.push(padding(remaining));
+        }
+    }
+}
+
+/// Wraps a symbol record with its length and kind, zero padded so the next record is aligned.
+fn symbol_record(kind: u16, mut record: CodeViewEntries) -> CodeViewEntries {
+    let mut entries = code_view_entries();
+    record.bytes.resize(align_up(record.bytes.len() as u32 + 4, RECORD_ALIGNMENT as u32) as usize - 4, 0);
+    entries.add_u16(record.bytes.len() as u16 + 2);
+    entries.add_u16(kind);
+    entries.add_entries(record);
+    entries
+}
+
+/// Wraps a type record with its length and kind, padded with the LF_PAD bytes that debuggers skip.
+fn type_record(kind: u16, record: CodeViewEntries) -> CodeViewEntries {
+    let mut entries = code_view_entries();
+    entries.add_u16(0);
+    entries.add_u16(kind);
+    entries.add_entries(record);
+    entries.pad(|remaining| LF_PAD0 + remaining as u8);
+    let length = entries.bytes.len() as u16 - 2;
+    entries.bytes[0..2].copy_from_slice(&length.to_le_bytes());
+    entries
+}
+
+fn subsection(kind: u32, contents: CodeViewEntries) -> CodeViewEntries {
+    let mut entries = code_view_entries();
+    entries.add_u32(kind);
+    entries.add_u32(contents.bytes.len() as u32);
+    entries.add_entries(contents);
+    entries.pad(|_| 0);
+    entries
+}
+
+fn object_name_record(object_name: &str) -> CodeViewEntries {
+    let mut record = code_view_entries();
+    record.add_u32(0);
+    record.add_string(object_name);
+    symbol_record(S_OBJNAME, record)
+}
+
+fn compile_record() -> CodeViewEntries {
+    let mut record = code_view_entries();
+    record.add_u32(CV_CFL_C);
+    record.add_u16(CV_CFL_X64);
+    for _ in 0..8 {
+        record.add_u16(0);
+    }
+    record.add_string(COMPILER_VERSION);
+    symbol_record(S_COMPILE3, record)
+}
+
+fn procedure_record(function: &FunctionDebugInfo) -> CodeViewEntries {
+    let mut record = code_view_entries();
+    record.add_u32(0);
+    record.add_u32(0);
+    record.add_u32(0);
+    record.add_u32(function.length);
+    record.add_u32(function.prologue_size);
+    record.add_u32(function.length);
+    record.add_u32(VOID_PROCEDURE_TYPE_INDEX);
+    record.add_section_offset(function.symbol);
+    record.add_section_index(function.symbol);
+    record.add_u8(0);
+    record.add_string(&function.name);
+    symbol_record(if function.external { S_GPROC32 } else { S_LPROC32 }, record)
+}
+
+fn symbols_subsection(object_name: &str, functions: &[&FunctionDebugInfo]) -> CodeViewEntries {
+    let mut contents = code_view_entries();
+    contents.add_entries(object_name_record(object_name));
+    contents.add_entries(compile_record());
+    for function in functions {
+        contents.add_entries(procedure_record(function));
+        contents.add_entries(symbol_record(S_END, code_view_entries()));
+    }
+    subsection(DEBUG_S_SYMBOLS, contents)
+}
+
+/// The string table starts with an empty string, so the source file name is at offset one.
+fn string_table_subsection(source_file_name: &str) -> CodeViewEntries {
+    let mut contents = code_view_entries();
+    contents.add_u8(0);
+    contents.add_string(source_file_name);
+    subsection(DEBUG_S_STRINGTABLE, contents)
+}
+
+/// A single file entry without a checksum, at offset zero for the line tables to refer to.
+fn file_checksums_subsection() -> CodeViewEntries {
+    let mut contents = code_view_entries();
+    contents.add_u32(1);
+    contents.add_u8(0);
+    contents.add_u8(CHKSUM_TYPE_NONE);
+    contents.pad(|_| 0);
+    subsection(DEBUG_S_FILECHKSMS, contents)
+}
+
+fn lines_subsection(function: &FunctionDebugInfo) -> CodeViewEntries {
+    let mut contents = code_view_entries();
+    contents.add_section_offset(function.symbol);
+    contents.add_section_index(function.symbol);
+    contents.add_u16(CV_LINES_HAVE_COLUMNS);
+    contents.add_u32(function.length);
+
+    let number_of_lines = function.lines.len() as u32;
+    contents.add_u32(0);
+    contents.add_u32(number_of_lines);
+    contents.add_u32(12 + number_of_lines * 12);
+    for line in &function.lines {
+        contents.add_u32(line.offset);
+        contents.add_u32(line.line | CV_LINE_IS_STATEMENT);
+    }
+    for line in &function.lines {
+        contents.add_u16(line.column);
+        contents.add_u16(0);
+    }
+    subsection(DEBUG_S_LINES, contents)
+}
+
+fn types_entries() -> CodeViewEntries {
+    let mut entries = code_view_entries();
+    entries.add_u32(CV_SIGNATURE_C13);
+
+    let mut argument_list = code_view_entries();
+    argument_list.add_u32(0);
+    entries.add_entries(type_record(LF_ARGLIST, argument_list));
+
+    let mut procedure = code_view_entries();
+    procedure.add_u32(T_VOID);
+    procedure.add_u8(CV_CALL_NEAR_C);
+    procedure.add_u8(0);
+    procedure.add_u16(0);
+    procedure.add_u32(VOID_ARGUMENT_LIST_TYPE_INDEX);
+    entries.add_entries(type_record(LF_PROCEDURE, procedure));
+
+    entries
+}
+
+fn add_code_view_entries(coff: &mut Coff, section_number: u16, entries: CodeViewEntries) -> Result<(), CoffError> {
+    get_or_add_section_symbol(coff, section_number)?;
+    let base = add_entries_to_section(coff, section_number, entries.bytes)?;
+    for (offset, symbol, relocation_type) in entries.relocations {
+        add_relocation_at_offset(coff, section_number, base + offset, symbol, relocation_type)?;
+    }
+    Ok(())
+}
+
+/// Functions without any code have no extent to describe and are skipped.
+pub fn add_code_view_debug_info(
+    coff: &mut Coff,
+    object_name: &str,
+    source_file_name: &str,
+    functions: &[FunctionDebugInfo]
+) -> Result<(), CoffError> {
+    let functions: Vec<&FunctionDebugInfo> = functions.iter().filter(|function| function.length > 0).collect();
+    if let Some(line) = functions
+        .iter()
+        .flat_map(|function| function.lines.iter())
+        .find(|line| line.line > MAX_SOURCE_LINE_NUMBER) {
+        return Err(CoffError::LineNumberTooLarge(line.line));
+    }
+
+    let mut symbols = code_view_entries();
+    symbols.add_u32(CV_SIGNATURE_C13);
+    symbols.add_entries(symbols_subsection(object_name, &functions));
+    for function in functions.iter().filter(|function| !function.lines.is_empty()) {
+        symbols.add_entries(lines_subsection(function));
+    }
+    symbols.add_entries(string_table_subsection(source_file_name));
+    symbols.add_entries(file_checksums_subsection());
+
+    let debug_symbols_section_number = get_or_add_debug_symbols_section_number(coff);
+    add_code_view_entries(coff, debug_symbols_section_number, symbols)?;
+    let debug_types_section_number = get_or_add_debug_types_section_number(coff);
+    add_code_view_entries(coff, debug_types_section_number, types_entries())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debug_functions(coff: &mut Coff) -> Vec<FunctionDebugInfo> {
+        let main = add_text_section_external_function_symbol(coff, "main", 0);
+        let helper = add_text_section_static_function_symbol(coff, "helper", 8);
+        let empty = add_text_section_static_function_symbol(coff, "empty", 12);
+        vec!(
+            function_debug_info(main, "main", true, 8, 1, vec!(source_line(0, 1, 5), source_line(4, 2, 9))),
+            function_debug_info(helper, "helper", false, 4, 0, vec!()),
+            function_debug_info(empty, "empty", false, 0, 0, vec!(source_line(0, 3, 1)))
+        )
+    }
+
+    fn debug_symbols(coff: &Coff) -> &CoffSection {
+        get_section(coff, get_section_number(coff, DEBUG_SYMBOLS_SECTION_NAME).unwrap()).unwrap()
+    }
+
+    fn subsections(bytes: &[u8]) -> Vec<(usize, u32)> {
+        let mut subsections = vec!();
+        let mut offset = 4;
+        while offset < bytes.len() {
+            subsections.push((offset, u32_from_bytes(bytes, offset)));
+            offset += 8 + align_up(u32_from_bytes(bytes, offset + 4), RECORD_ALIGNMENT as u32) as usize;
+        }
+        subsections
+    }
+
+    fn symbol_records(bytes: &[u8], subsection: usize) -> Vec<(usize, u16)> {
+        let end = subsection + 8 + u32_from_bytes(bytes, subsection + 4) as usize;
+        let mut records = vec!();
+        let mut offset = subsection + 8;
+        while offset < end {
+            records.push((offset, u16_from_bytes(bytes, offset + 2)));
+            offset += 2 + u16_from_bytes(bytes, offset) as usize;
+        }
+        records
+    }
+
+    #[test]
+    fn subsections_and_records_are_aligned_c13_entries() {
+        let mut coff = create_coff();
+        let functions = debug_functions(&mut coff);
+        add_code_view_debug_info(&mut coff, "main.obj", "main.hep", &functions).unwrap();
+        let bytes = get_section_data(debug_symbols(&coff));
+
+        assert_eq!(u32_from_bytes(bytes, 0), CV_SIGNATURE_C13);
+        let subsections = subsections(bytes);
+        assert_eq!(subsections.iter().map(|(_, kind)| *kind).collect::<Vec<u32>>(), [DEBUG_S_SYMBOLS, DEBUG_S_LINES, DEBUG_S_STRINGTABLE, DEBUG_S_FILECHKSMS]);
+        assert!(subsections.iter().all(|(offset, _)| offset % RECORD_ALIGNMENT == 0));
+
+        let records = symbol_records(bytes, subsections[0].0);
+        assert_eq!(records.iter().map(|(_, kind)| *kind).collect::<Vec<u16>>(), [S_OBJNAME, S_COMPILE3, S_GPROC32, S_END, S_LPROC32, S_END]);
+        assert!(records.iter().all(|(offset, _)| offset % RECORD_ALIGNMENT == 0));
+
+        let types = get_section(&coff, get_section_number(&coff, DEBUG_TYPES_SECTION_NAME).unwrap()).unwrap();
+        assert_eq!(u32_from_bytes(get_section_data(types), 0), CV_SIGNATURE_C13);
+    }
+
+    #[test]
+    fn procedures_and_line_tables_are_relocated_against_their_function() {
+        let mut coff = create_coff();
+        let functions = debug_functions(&mut coff);
+        add_code_view_debug_info(&mut coff, "main.obj", "main.hep", &functions).unwrap();
+        let section = debug_symbols(&coff);
+        let bytes = get_section_data(section);
+        let subsections = subsections(bytes);
+        let records = symbol_records(bytes, subsections[0].0);
+        let (main_procedure, helper_procedure, lines) = (records[2].0 as u32, records[4].0 as u32, subsections[1].0 as u32);
+
+        let relocations: Vec<(u32, u16, String)> = get_section_relocations(section)
+            .iter()
+            .map(|relocation| {
+                let symbol = get_primary_symbol(get_symbol(&coff, get_relocation_symbol(relocation)).unwrap());
+                (get_relocation_offset(relocation), get_relocation_type(relocation), get_symbol_name(&coff, symbol).unwrap())
+            })
+            .collect();
+        assert_eq!(relocations, [
+            (main_procedure + 32, IMAGE_REL_AMD64_SECREL, string("main")),
+            (main_procedure + 36, IMAGE_REL_AMD64_SECTION, string("main")),
+            (helper_procedure + 32, IMAGE_REL_AMD64_SECREL, string("helper")),
+            (helper_procedure + 36, IMAGE_REL_AMD64_SECTION, string("helper")),
+            (lines + 8, IMAGE_REL_AMD64_SECREL, string("main")),
+            (lines + 12, IMAGE_REL_AMD64_SECTION, string("main"))
+        ]);
+    }
+
+    #[test]
+    fn line_tables_have_columns() {
+        let mut coff = create_coff();
+        let functions = debug_functions(&mut coff);
+        add_code_view_debug_info(&mut coff, "main.obj", "main.hep", &functions).unwrap();
+        let bytes = get_section_data(debug_symbols(&coff));
+        let lines = subsections(bytes)[1].0 + 8;
+
+        assert_eq!(u16_from_bytes(bytes, lines + 6), CV_LINES_HAVE_COLUMNS);
+        let fields: Vec<u32> = (0..8).map(|field| u32_from_bytes(bytes, lines + 8 + field * 4)).collect();
+        assert_eq!(fields, [8, 0, 2, 36, 0, 1 | CV_LINE_IS_STATEMENT, 4, 2 | CV_LINE_IS_STATEMENT]);
+        let columns: Vec<u16> = (0..4).map(|field| u16_from_bytes(bytes, lines + 40 + field * 2)).collect();
+        assert_eq!(columns, [5, 0, 9, 0]);
+    }
+
+    #[test]
+    fn line_numbers_must_fit_in_24_bits() {
+        let mut coff = create_coff();
+        let main = add_text_section_external_function_symbol(&mut coff, "main", 0);
+        let functions = [function_debug_info(main, "main", true, 1, 0, vec!(source_line(0, MAX_SOURCE_LINE_NUMBER + 1, 0)))];
+        let result = add_code_view_debug_info(&mut coff, "main.obj", "main.hep", &functions);
+        assert!(matches!(result, Err(CoffError::LineNumberTooLarge(line)) if line == MAX_SOURCE_LINE_NUMBER + 1));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::machine_code::*;
+
+pub const COFF_HEADER_SIZE: usize = 20;
+pub const COFF_SECTION_HEADER_SIZE: usize = 40;
+pub const COFF_RELOCATION_ENTRY_SIZE: usize = 10;
+pub const COFF_SYMBOL_SIZE: usize = 18;
+
+pub fn encode_header(header: &CoffHeader) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(COFF_HEADER_SIZE);
+    bytes.extend_from_slice(&header.magic.to_le_bytes());
+    bytes.extend_from_slice(&header.number_of_sections.to_le_bytes());
+    bytes.extend_from_slice(&header.time_date_stamp.to_le_bytes());
+    bytes.extend_from_slice(&header.pointer_to_symbol_table.to_le_bytes());
+    bytes.extend_from_slice(&header.number_of_symbols.to_le_bytes());
+    bytes.extend_from_slice(&header.size_of_optional_header.to_le_bytes());
+    bytes.extend_from_slice(&header.flags.to_le_bytes());
+    bytes
+}
+
+pub fn decode_header(bytes: &[u8]) -> CoffHeader {
+    header(
+        u16_from_bytes(bytes, 0),
+        u16_from_bytes(bytes, 2),
+        u32_from_bytes(bytes, 4),
+        u32_from_bytes(bytes, 8),
+        u32_from_bytes(bytes, 12),
+        u16_from_bytes(bytes, 16),
+        u16_from_bytes(bytes, 18),
+    )
+}
+
+pub fn encode_section_header(section_header: &CoffSectionHeader) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(COFF_SECTION_HEADER_SIZE);
+    bytes.extend_from_slice(&section_header.short_name);
+    bytes.extend_from_slice(&section_header.physical_address.to_le_bytes());
+    bytes.extend_from_slice(&section_header.virtual_address.to_le_bytes());
+    bytes.extend_from_slice(&section_header.size_of_section.to_le_bytes());
+    bytes.extend_from_slice(&section_header.pointer_to_section.to_le_bytes());
+    bytes.extend_from_slice(&section_header.pointer_to_relocations.to_le_bytes());
+    bytes.extend_from_slice(&section_header.pointer_to_line_numbers.to_le_bytes());
+    bytes.extend_from_slice(&section_header.number_of_relocations.to_le_bytes());
+    bytes.extend_from_slice(&section_header.number_of_line_numbers.to_le_bytes());
+    bytes.extend_from_slice(&section_header.flags.to_le_bytes());
+    bytes
+}
+
+pub fn decode_section_header(bytes: &[u8]) -> CoffSectionHeader {
+    let mut short_name = [0; 8];
+    short_name.copy_from_slice(&bytes[0..8]);
+    CoffSectionHeader {
+        short_name,
+        physical_address: u32_from_bytes(bytes, 8),
+        virtual_address: u32_from_bytes(bytes, 12),
+        size_of_section: u32_from_bytes(bytes, 16),
+        pointer_to_section: u32_from_bytes(bytes, 20),
+        pointer_to_relocations: u32_from_bytes(bytes, 24),
+        pointer_to_line_numbers: u32_from_bytes(bytes, 28),
+        number_of_relocations: u16_from_bytes(bytes, 32),
+        number_of_line_numbers: u16_from_bytes(bytes, 34),
+        flags: u32_from_bytes(bytes, 36),
+    }
+}
+
+pub fn encode_relocation_entry(entry: &CoffRelocationEntry) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(COFF_RELOCATION_ENTRY_SIZE);
+    bytes.extend_from_slice(&entry.pointer_to_reference.to_le_bytes());
+    bytes.extend_from_slice(&entry.symbol_index.to_le_bytes());
+    bytes.extend_from_slice(&entry.relocation_type.to_le_bytes());
+    bytes
+}
+
+pub fn decode_relocation_entry(bytes: &[u8]) -> CoffRelocationEntry {
+    CoffRelocationEntry {
+        pointer_to_reference: u32_from_bytes(bytes, 0),
+        symbol_index: u32_from_bytes(bytes, 4),
+        relocation_type: u16_from_bytes(bytes, 8)
+    }
+}
+
+pub fn encode_symbol(symbol: &CoffSymbol) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(COFF_SYMBOL_SIZE);
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => {
+            bytes.extend_from_slice(&short_named.name);
+            bytes.extend_from_slice(&short_named.value.to_le_bytes());
+            bytes.extend_from_slice(&short_named.section_number.to_le_bytes());
+            bytes.extend_from_slice(&short_named.symbol_type.to_le_bytes());
+            bytes.push(short_named.storage_class);
+            bytes.push(short_named.number_of_auxillary_symbols);
+        },
+        CoffSymbol::LongNamed(long_named) => {
+            bytes.extend_from_slice(&long_named.pad.to_le_bytes());
+            bytes.extend_from_slice(&long_named.pointer_to_string_table.to_le_bytes());
+            bytes.extend_from_slice(&long_named.value.to_le_bytes());
+            bytes.extend_from_slice(&long_named.section_number.to_le_bytes());
+            bytes.extend_from_slice(&long_named.symbol_type.to_le_bytes());
+            bytes.push(long_named.storage_class);
+            bytes.push(long_named.number_of_auxillary_symbols);
+        },
+        CoffSymbol::Name(name) => {
+            bytes.extend_from_slice(&name.0);
+        },
+        CoffSymbol::Section(section) => {
+            bytes.extend_from_slice(&section.length.to_le_bytes());
+            bytes.extend_from_slice(&section.number_of_relocations.to_le_bytes());
+            bytes.extend_from_slice(&section.number_of_line_numbers.to_le_bytes());
+            bytes.extend_from_slice(&section.checksum.to_le_bytes());
+            bytes.extend_from_slice(&section.number.to_le_bytes());
+            bytes.push(section.selection);
+            bytes.extend_from_slice(&section.pad1.to_le_bytes());
+            bytes.push(section.pad2);
+        },
+    }
+    bytes
+}
+
+pub fn decode_primary_symbol(bytes: &[u8]) -> CoffSymbol {
+    let value = u32_from_bytes(bytes, 8);
+    let section_number = u16_from_bytes(bytes, 12);
+    let symbol_type = u16_from_bytes(bytes, 14);
+    let storage_class = bytes[16];
+    let number_of_auxillary_symbols = bytes[17];
+
+    if u32_from_bytes(bytes, 0) == 0 {
+        return CoffSymbol::LongNamed(CoffSymbolLongNamed {
+            pad: 0,
+            pointer_to_string_table: u32_from_bytes(bytes, 4),
+            value,
+            section_number,
+            symbol_type,
+            storage_class,
+            number_of_auxillary_symbols
+        });
+    }
+
+    let mut name = [0; 8];
+    name.copy_from_slice(&bytes[0..8]);
+    CoffSymbol::ShortNamed(CoffSymbolShortNamed {
+        name,
+        value,
+        section_number,
+        symbol_type,
+        storage_class,
+        number_of_auxillary_symbols
+    })
+}
+
+pub fn decode_name_symbol(bytes: &[u8]) -> CoffSymbol {
+    let mut name = [0; COFF_SYMBOL_SIZE];
+    name.copy_from_slice(&bytes[..COFF_SYMBOL_SIZE]);
+    CoffSymbol::Name(CoffSymbolName(name))
+}
+
+pub fn decode_section_symbol(bytes: &[u8]) -> CoffSymbol {
+    CoffSymbol::Section(CoffSymbolSection {
+        length: u32_from_bytes(bytes, 0),
+        number_of_relocations: u16_from_bytes(bytes, 4),
+        number_of_line_numbers: u16_from_bytes(bytes, 6),
+        checksum: u32_from_bytes(bytes, 8),
+        number: u16_from_bytes(bytes, 12),
+        selection: bytes[14],
+        pad1: u16_from_bytes(bytes, 15),
+        pad2: bytes[17]
+    })
+}
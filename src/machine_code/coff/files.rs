@@ -1,26 +1,22 @@
-use std::*;
 use std::fs::File;
-use std::io::Write;
+use std::io::Read;
 use crate::machine_code::*;
+use crate::errors::*;
 
-pub fn create_coff_file(name: &str) -> io::Result<File> {
-    File::create(name)
+pub fn create_coff_file(name: &str) -> Result<File, CoffError> {
+    File::create(name).map_err(|error| io_error(&format!("could not create {}", name), error))
 }
 
-pub fn write_coff_to_file(coff: &Coff, file: &mut File) -> io::Result<()> {
-    file.write_all(any_as_u8_slice(&coff.header))?;
-    file.write_all(any_as_u8_slice(&coff.data_section_header))?;
-    file.write_all(any_as_u8_slice(&coff.text_section_header))?;
-    file.write_all(&coff.data_section)?;
-    file.write_all(&coff.text_section)?;
-    for relocation in &coff.relocations {
-        file.write_all(any_as_u8_slice(relocation))?;
-    }
-    for symbol in &coff.symbols {
-        file.write_all(any_as_u8_slice(symbol))?;
-    }
-    file.write_all(any_as_u8_slice(&coff.strings_table_length))?;
-    file.write_all(&coff.strings)?;
-    file.flush()?;
-    Ok(())
+pub fn open_coff_file(name: &str) -> Result<File, CoffError> {
+    File::open(name).map_err(|error| io_error(&format!("could not open {}", name), error))
+}
+
+pub fn read_coff_from_file(file: &mut File) -> Result<Coff, CoffError> {
+    let mut bytes = vec!();
+    file.read_to_end(&mut bytes).map_err(|error| io_error("could not read object", error))?;
+    Ok(read_coff_from_bytes(&bytes)?)
+}
+
+pub fn write_coff_to_file(coff: &Coff, file: &mut File) -> Result<(), CoffError> {
+    write_coff(coff, file)
 }
\ No newline at end of file
@@ -1,9 +1,8 @@
-use std::mem::size_of;
+use std::env;
 use crate::machine_code::*;
+use crate::errors::*;
 
-pub fn initial_base_dynamic_data_pointer() -> u32 {
-    (size_of::<CoffHeader>() + (size_of::<CoffSectionHeader>() * 2)) as u32
-}
+const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
 
 pub fn header(
     magic: u16,
@@ -25,8 +24,9 @@ pub fn header(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn section_header(
-    short_name: &str,
+    short_name: [u8; 8],
     physical_address: u32,
     virtual_address: u32,
     size_of_section: u32,
@@ -38,7 +38,7 @@ pub fn section_header(
     flags: u32,
 ) -> CoffSectionHeader {
     CoffSectionHeader {
-        short_name: get_8_padded_u8_array_from_string(short_name),
+        short_name,
         physical_address,
         virtual_address,
         size_of_section,
@@ -51,6 +51,65 @@ pub fn section_header(
     }
 }
 
-pub fn set_current_timestamp(coff: &mut Coff) {
-    coff.header.time_date_stamp = get_current_timestamp();
-}
\ No newline at end of file
+pub fn current_timestamp() -> CoffTimestamp {
+    CoffTimestamp::Current
+}
+
+pub fn fixed_timestamp(value: u32) -> CoffTimestamp {
+    CoffTimestamp::Fixed(value)
+}
+
+pub fn reproducible_timestamp() -> Result<CoffTimestamp, CoffError> {
+    match env::var(SOURCE_DATE_EPOCH) {
+        Ok(value) => source_date_epoch_timestamp(&value),
+        Err(env::VarError::NotPresent) => Ok(fixed_timestamp(0)),
+        Err(env::VarError::NotUnicode(value)) => Err(CoffError::SourceDateEpochInvalid(value.to_string_lossy().into_owned()))
+    }
+}
+
+fn source_date_epoch_timestamp(value: &str) -> Result<CoffTimestamp, CoffError> {
+    match value.trim().parse::<u32>() {
+        Ok(seconds) => Ok(fixed_timestamp(seconds)),
+        Err(_) => Err(CoffError::SourceDateEpochInvalid(string(value)))
+    }
+}
+
+pub fn get_machine(coff: &Coff) -> u16 {
+    coff.machine
+}
+
+pub fn get_flags(coff: &Coff) -> u16 {
+    coff.flags
+}
+
+pub fn set_timestamp(coff: &mut Coff, timestamp: CoffTimestamp) {
+    coff.timestamp = timestamp;
+}
+
+pub fn get_timestamp(coff: &Coff) -> CoffTimestamp {
+    coff.timestamp
+}
+
+pub fn resolve_timestamp(timestamp: CoffTimestamp) -> Result<u32, CoffError> {
+    match timestamp {
+        CoffTimestamp::Current => get_current_timestamp(),
+        CoffTimestamp::Fixed(value) => Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_date_epoch_is_parsed_as_seconds() {
+        assert_eq!(source_date_epoch_timestamp(" 1700000000\n").unwrap(), fixed_timestamp(1700000000));
+    }
+
+    #[test]
+    fn invalid_source_date_epoch_is_rejected() {
+        for value in ["", "yesterday", "-1", "4294967296"] {
+            assert!(matches!(source_date_epoch_timestamp(value), Err(CoffError::SourceDateEpochInvalid(_))));
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use crate::machine_code::*;
+use crate::errors::*;
+
+pub struct CoffLayout {
+    pub header: CoffHeader,
+    pub section_headers: Vec<CoffSectionHeader>,
+    pub section_relocations: Vec<Vec<CoffRelocationEntry>>,
+    pub section_definition_symbols: HashMap<CoffSymbolHandle, CoffSymbol>,
+    pub symbol_indices: Vec<u32>,
+    pub strings_table_length: u32
+}
+
+pub fn layout_coff(coff: &Coff) -> Result<CoffLayout, CoffError> {
+    let number_of_sections = coff.sections.len() as u16;
+    let mut pointer = (COFF_HEADER_SIZE + coff.sections.len() * COFF_SECTION_HEADER_SIZE) as u32;
+    let mut section_headers = vec!();
+    let mut section_relocations = vec!();
+    let mut section_definition_symbols = HashMap::new();
+    let (symbol_indices, number_of_symbols) = layout_symbols(coff)?;
+
+    for section in &coff.sections {
+        if section.data.len() > u32::MAX as usize {
+            return Err(CoffError::SectionTooLarge { section: section.name.clone(), size: section.data.len() });
+        }
+        if section.relocations.len() > u16::MAX as usize {
+            return Err(CoffError::TooManyRelocations { section: section.name.clone(), count: section.relocations.len() });
+        }
+
+        let size_of_section = get_section_size(section);
+        let number_of_relocations = section.relocations.len() as u16;
+        let size_of_relocations = (section.relocations.len() * COFF_RELOCATION_ENTRY_SIZE) as u32;
+        let mut pointer_to_section = 0;
+        let mut pointer_to_relocations = 0;
+
+        if !is_uninitialised_data_section(section) {
+            pointer_to_section = pointer;
+            pointer_to_relocations = offset_after(pointer_to_section, size_of_section, "section data")?;
+            pointer = offset_after(pointer_to_relocations, size_of_relocations, "section relocations")?;
+        }
+
+        section_headers.push(section_header(
+            section.short_name,
+            0,
+            0,
+            size_of_section,
+            pointer_to_section,
+            pointer_to_relocations,
+            0,
+            number_of_relocations,
+            0,
+            section.flags
+        ));
+
+        section_relocations.push(
+            section.relocations
+                .iter()
+                .map(|relocation| resolve_relocation(relocation, &symbol_indices))
+                .collect::<Option<Vec<CoffRelocationEntry>>>()
+                .ok_or(CoffError::UnknownSymbolHandle { section: section.name.clone() })?
+        );
+
+        if let Some(handle) = section.definition_symbol {
+            section_definition_symbols.insert(handle, section_symbol(size_of_section, number_of_relocations, 0, 0, 0, 0));
+        }
+    }
+
+    Ok(CoffLayout {
+        header: header(
+            coff.machine,
+            number_of_sections,
+            resolve_timestamp(coff.timestamp)?,
+            pointer,
+            number_of_symbols,
+            0,
+            coff.flags
+        ),
+        section_headers,
+        section_relocations,
+        section_definition_symbols,
+        symbol_indices,
+        strings_table_length: get_strings_table_length(coff)
+    })
+}
+
+fn layout_symbols(coff: &Coff) -> Result<(Vec<u32>, u32), CoffError> {
+    let mut symbol_indices = vec!();
+    let mut symbol_index: u32 = 0;
+
+    for entry in &coff.symbols {
+        symbol_indices.push(symbol_index);
+        symbol_index = symbol_index
+            .checked_add(1 + entry.auxillary_symbols.len() as u32)
+            .ok_or(CoffError::OffsetTooLarge { context: "symbol table" })?;
+    }
+
+    Ok((symbol_indices, symbol_index))
+}
+
+fn offset_after(pointer: u32, size: u32, context: &'static str) -> Result<u32, CoffError> {
+    pointer.checked_add(size).ok_or(CoffError::OffsetTooLarge { context })
+}
@@ -3,39 +3,86 @@ mod relocations;
 mod headers;
 mod sections;
 mod files;
+mod writers;
+mod readers;
+mod layouts;
+mod encoding;
 
-use crate::machine_code::*;
 pub use headers::*;
 pub use symbols::*;
 pub use relocations::*;
 pub use sections::*;
 pub use files::*;
+pub use writers::*;
+pub use readers::*;
+pub use layouts::*;
+pub use encoding::*;
 
 
-const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+pub const IMAGE_FILE_MACHINE_UNKNOWN: u16 = 0x0000;
+pub const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
 
-const IMAGE_SCN_CNT_INITIALISED_DATA: u32 = 0x00000040;
-const IMAGE_SCN_CNT_CODE: u32 = 0x00000020;
-const IMAGE_SCN_ALIGN_4BYTES: u32 = 0x00300000;
-const IMAGE_SCN_ALIGN_16BYTES: u32 = 0x00500000;
-const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
-const IMAGE_SCN_MEM_READ: u32 = 0x40000000;
-const IMAGE_SCN_MEM_WRITE: u32 = 0x80000000;
+pub const IMAGE_SCN_CNT_CODE: u32 = 0x00000020;
+pub const IMAGE_SCN_CNT_INITIALISED_DATA: u32 = 0x00000040;
+pub const IMAGE_SCN_CNT_UNINITIALISED_DATA: u32 = 0x00000080;
+pub const IMAGE_SCN_LNK_INFO: u32 = 0x00000200;
+pub const IMAGE_SCN_LNK_REMOVE: u32 = 0x00000800;
+pub const IMAGE_SCN_MEM_DISCARDABLE: u32 = 0x02000000;
+pub const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
+pub const IMAGE_SCN_MEM_READ: u32 = 0x40000000;
+pub const IMAGE_SCN_MEM_WRITE: u32 = 0x80000000;
+
+pub const DATA_SECTION_NAME: &str = ".data";
+pub const TEXT_SECTION_NAME: &str = ".text";
+pub const READ_ONLY_DATA_SECTION_NAME: &str = ".rdata";
+pub const UNINITIALISED_DATA_SECTION_NAME: &str = ".bss";
+pub const EXCEPTION_DATA_SECTION_NAME: &str = ".pdata";
+pub const UNWIND_DATA_SECTION_NAME: &str = ".xdata";
+pub const DEBUG_SYMBOLS_SECTION_NAME: &str = ".debug$S";
+pub const DEBUG_TYPES_SECTION_NAME: &str = ".debug$T";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CoffTimestamp {
+    Current,
+    Fixed(u32)
+}
 
 pub struct Coff {
-    header: CoffHeader,
-    data_section_header: CoffSectionHeader,
-    text_section_header: CoffSectionHeader,
-    data_section: Vec<u8>,
-    text_section: Vec<u8>,
-    relocations: Vec<CoffRelocationEntry>,
-    symbols: Vec<CoffSymbol>,    
-    strings_table_length: u32,
+    machine: u16,
+    timestamp: CoffTimestamp,
+    flags: u16,
+    sections: Vec<CoffSection>,
+    symbols: Vec<CoffSymbolTableEntry>,    
     strings: Vec<u8>
 }
 
-#[repr(packed)]
-#[allow(dead_code)]
+pub struct CoffSection {
+    name: String,
+    short_name: [u8; 8],
+    flags: u32,
+    data: Vec<u8>,
+    size_of_uninitialised_data: u32,
+    relocations: Vec<CoffRelocation>,
+    definition_symbol: Option<CoffSymbolHandle>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CoffSymbolHandle(u32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoffSymbolTableEntry {
+    symbol: CoffSymbol,
+    auxillary_symbols: Vec<CoffSymbol>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoffRelocation {
+    offset: u32,
+    symbol: CoffSymbolHandle,
+    relocation_type: u16
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffHeader {
     magic: u16,
     number_of_sections: u16,
@@ -46,8 +93,7 @@ pub struct CoffHeader {
     flags: u16,
 }
 
-#[repr(packed)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffSectionHeader {
     short_name: [u8;8],
     physical_address: u32,
@@ -61,24 +107,22 @@ pub struct CoffSectionHeader {
     flags: u32,
 }
 
-#[repr(packed)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffRelocationEntry {
     pointer_to_reference: u32,
     symbol_index: u32,
     relocation_type: u16,
 }
 
-#[repr(packed)]
-pub union CoffSymbol {
-    short_named: CoffSymbolShortNamed,
-    long_named: CoffSymbolLongNamed,
-    name: CoffSymbolName,
-    section: CoffSymbolSection
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoffSymbol {
+    ShortNamed(CoffSymbolShortNamed),
+    LongNamed(CoffSymbolLongNamed),
+    Name(CoffSymbolName),
+    Section(CoffSymbolSection)
 }
 
-#[repr(packed)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffSymbolShortNamed {
     name: [u8;8],
     value: u32,
@@ -88,8 +132,7 @@ pub struct CoffSymbolShortNamed {
     number_of_auxillary_symbols: u8,
 }
 
-#[repr(packed)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffSymbolLongNamed {
     pad: u32,
     pointer_to_string_table: u32,
@@ -100,11 +143,10 @@ pub struct CoffSymbolLongNamed {
     number_of_auxillary_symbols: u8,
 }
 
-#[repr(packed)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffSymbolName(pub [u8;18]);
 
-#[repr(packed)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoffSymbolSection {
     length: u32,
     number_of_relocations: u16,
@@ -117,45 +159,26 @@ pub struct CoffSymbolSection {
 }
 
 pub fn create_coff() -> Coff {
+    let mut coff = create_coff_without_sections();
+
+    get_or_add_data_section_number(&mut coff);
+    get_or_add_text_section_number(&mut coff);
+
+    coff
+}
+
+pub fn create_coff_without_sections() -> Coff {
     Coff {
-        header : header( 
-            IMAGE_FILE_MACHINE_AMD64,
-            2,
-            get_current_timestamp(), 
-            initial_base_dynamic_data_pointer(),
-            0,
-            0,
-            0,
-        ),
-        data_section_header: section_header(
-            ".data",
-            0,
-            0,
-            0,
-            initial_base_dynamic_data_pointer(),
-            initial_base_dynamic_data_pointer(),
-            0,
-            0,
-            0,
-            IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE
-        ),
-        text_section_header: section_header(
-            ".text",
-            0,
-            0,
-            0,
-            initial_base_dynamic_data_pointer(),
-            initial_base_dynamic_data_pointer(),
-            0,
-            0,
-            0,
-            IMAGE_SCN_CNT_CODE | IMAGE_SCN_ALIGN_16BYTES | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
-        ),
-        data_section: vec!(),
-        text_section: vec!(),
-        relocations: vec!(),
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        timestamp: current_timestamp(),
+        flags: 0,
+        sections: vec!(),
         symbols: vec!(),
-        strings_table_length: 0x4,
         strings: vec!()
     }
 }
+
+/// Looks up a section by a number returned by one of the `get_or_add_*_section_number` functions, which is always in range.
+fn get_default_section_mut(coff: &mut Coff, section_number: u16) -> &mut CoffSection {
+    &mut coff.sections[(section_number - 1) as usize]
+}
@@ -0,0 +1,299 @@
+use std::*;
+use std::mem::size_of;
+use std::collections::HashMap;
+use super::CoffSymbolHandle;
+use crate::machine_code::*;
+
+#[derive(Debug)]
+pub enum CoffReadError {
+    UnexpectedEndOfData { reading: &'static str, offset: usize, length: usize, available: usize },
+    UnsupportedMachine(u16),
+    UnsupportedOptionalHeader(u16),
+    SectionNameInvalid(String),
+    SectionDataOutOfBounds { section: String, pointer: u32, size: u32 },
+    RelocationsOutOfBounds { section: String, pointer: u32, count: u16 },
+    SymbolTableOutOfBounds { pointer: u32, count: u32 },
+    AuxillarySymbolsOutOfBounds { symbol_index: u32, count: u8 },
+    RelocationSymbolInvalid { section: String, symbol_index: u32 },
+    StringTableLengthInvalid(u32),
+    StringTableOutOfBounds { pointer: u32, length: u32 },
+}
+
+impl fmt::Display for CoffReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoffReadError::UnexpectedEndOfData { reading, offset, length, available } =>
+                write!(f, "unexpected end of data reading {} ({} bytes at {:#x}, {} available)", reading, length, offset, available),
+            CoffReadError::UnsupportedMachine(machine) =>
+                write!(f, "unsupported machine type {:#x}", machine),
+            CoffReadError::UnsupportedOptionalHeader(size) =>
+                write!(f, "optional header of {} bytes is not supported in object files", size),
+            CoffReadError::SectionNameInvalid(name) =>
+                write!(f, "section name {} does not refer to the string table", name),
+            CoffReadError::SectionDataOutOfBounds { section, pointer, size } =>
+                write!(f, "section {} data of {} bytes at {:#x} is out of bounds", section, size, pointer),
+            CoffReadError::RelocationsOutOfBounds { section, pointer, count } =>
+                write!(f, "section {} {} relocations at {:#x} are out of bounds", section, count, pointer),
+            CoffReadError::SymbolTableOutOfBounds { pointer, count } =>
+                write!(f, "symbol table of {} symbols at {:#x} is out of bounds", count, pointer),
+            CoffReadError::AuxillarySymbolsOutOfBounds { symbol_index, count } =>
+                write!(f, "symbol {} has {} auxillary symbols past the end of the symbol table", symbol_index, count),
+            CoffReadError::RelocationSymbolInvalid { section, symbol_index } =>
+                write!(f, "section {} has a relocation against symbol {} which is not a primary symbol record", section, symbol_index),
+            CoffReadError::StringTableLengthInvalid(length) =>
+                write!(f, "string table length {} is invalid", length),
+            CoffReadError::StringTableOutOfBounds { pointer, length } =>
+                write!(f, "string table of {} bytes at {:#x} is out of bounds", length, pointer),
+        }
+    }
+}
+
+impl error::Error for CoffReadError {}
+
+pub fn read_coff_from_bytes(bytes: &[u8]) -> Result<Coff, CoffReadError> {
+    let header = read_header(bytes)?;
+
+    if header.magic != IMAGE_FILE_MACHINE_AMD64 {
+        return Err(CoffReadError::UnsupportedMachine(header.magic));
+    }
+    if header.size_of_optional_header != 0 {
+        return Err(CoffReadError::UnsupportedOptionalHeader(header.size_of_optional_header));
+    }
+
+    let (symbols, symbol_handles) = read_symbols(bytes, &header)?;
+    let strings = read_strings(bytes, &header)?;
+    let mut sections = vec!();
+
+    for section_index in 0..header.number_of_sections as usize {
+        let section_header = read_section_header(
+            bytes, 
+            COFF_HEADER_SIZE + section_index * COFF_SECTION_HEADER_SIZE
+        )?;
+        let uninitialised = section_header.flags & IMAGE_SCN_CNT_UNINITIALISED_DATA == IMAGE_SCN_CNT_UNINITIALISED_DATA;
+        sections.push(CoffSection {
+            name: read_section_name(&section_header, &strings)?,
+            short_name: section_header.short_name,
+            flags: section_header.flags,
+            data: read_section_data(bytes, &header, &section_header)?,
+            size_of_uninitialised_data: if uninitialised { section_header.size_of_section } else { 0 },
+            relocations: read_relocations(bytes, &section_header, &symbol_handles)?,
+            definition_symbol: None
+        });
+    }
+
+    Ok(Coff {
+        machine: header.magic,
+        timestamp: fixed_timestamp(header.time_date_stamp),
+        flags: header.flags,
+        sections,
+        symbols,
+        strings
+    })
+}
+
+fn read_header(bytes: &[u8]) -> Result<CoffHeader, CoffReadError> {
+    Ok(decode_header(read_bytes(bytes, 0, COFF_HEADER_SIZE, "header")?))
+}
+
+fn read_section_header(bytes: &[u8], offset: usize) -> Result<CoffSectionHeader, CoffReadError> {
+    Ok(decode_section_header(read_bytes(bytes, offset, COFF_SECTION_HEADER_SIZE, "section header")?))
+}
+
+fn read_section_name(section_header: &CoffSectionHeader, strings: &[u8]) -> Result<String, CoffReadError> {
+    let short_name = section_header_name(section_header);
+
+    if !short_name.starts_with('/') {
+        return Ok(short_name);
+    }
+
+    match short_name[1..].parse::<usize>() {
+        Ok(pointer) if pointer >= size_of::<u32>() && pointer - size_of::<u32>() < strings.len() => 
+            Ok(string_from_padded_u8_array(&strings[pointer - size_of::<u32>()..])),
+        _ => Err(CoffReadError::SectionNameInvalid(short_name))
+    }
+}
+
+fn read_section_data(bytes: &[u8], header: &CoffHeader, section_header: &CoffSectionHeader) -> Result<Vec<u8>, CoffReadError> {
+    if section_header.flags & IMAGE_SCN_CNT_UNINITIALISED_DATA == IMAGE_SCN_CNT_UNINITIALISED_DATA {
+        return Ok(vec!());
+    }
+
+    let pointer = section_header.pointer_to_section;
+    let size = section_header.size_of_section;
+    let end_of_headers = COFF_HEADER_SIZE + header.number_of_sections as usize * COFF_SECTION_HEADER_SIZE;
+    if size > 0 && (pointer as usize) < end_of_headers {
+        return Err(CoffReadError::SectionDataOutOfBounds { section: section_header_name(section_header), pointer, size });
+    }
+    match read_bytes(bytes, pointer as usize, size as usize, "section data") {
+        Ok(section_bytes) => Ok(section_bytes.into()),
+        Err(_) => Err(CoffReadError::SectionDataOutOfBounds { section: section_header_name(section_header), pointer, size })
+    }
+}
+
+fn read_relocations(
+    bytes: &[u8], 
+    section_header: &CoffSectionHeader, 
+    symbol_handles: &HashMap<u32, CoffSymbolHandle>
+) -> Result<Vec<CoffRelocation>, CoffReadError> {
+    let pointer = section_header.pointer_to_relocations;
+    let count = section_header.number_of_relocations;
+    let relocation_bytes = match read_bytes(bytes, pointer as usize, count as usize * COFF_RELOCATION_ENTRY_SIZE, "relocations") {
+        Ok(relocation_bytes) => relocation_bytes,
+        Err(_) => return Err(CoffReadError::RelocationsOutOfBounds { section: section_header_name(section_header), pointer, count })
+    };
+
+    relocation_bytes
+        .chunks_exact(COFF_RELOCATION_ENTRY_SIZE)
+        .map(decode_relocation_entry)
+        .map(|entry| match symbol_handles.get(&entry.symbol_index) {
+            Some(symbol) => Ok(CoffRelocation { offset: entry.pointer_to_reference, symbol: *symbol, relocation_type: entry.relocation_type }),
+            None => Err(CoffReadError::RelocationSymbolInvalid { section: section_header_name(section_header), symbol_index: entry.symbol_index })
+        })
+        .collect()
+}
+
+fn read_symbols(bytes: &[u8], header: &CoffHeader) -> Result<(Vec<CoffSymbolTableEntry>, HashMap<u32, CoffSymbolHandle>), CoffReadError> {
+    let pointer = header.pointer_to_symbol_table;
+    let count = header.number_of_symbols;
+    if pointer == 0 && count > 0 {
+        return Err(CoffReadError::SymbolTableOutOfBounds { pointer, count });
+    }
+    let symbol_bytes = match read_bytes(bytes, pointer as usize, count as usize * COFF_SYMBOL_SIZE, "symbol table") {
+        Ok(symbol_bytes) => symbol_bytes,
+        Err(_) => return Err(CoffReadError::SymbolTableOutOfBounds { pointer, count })
+    };
+
+    let mut symbols = vec!();
+    let mut symbol_handles = HashMap::new();
+    let mut symbol_index = 0;
+    let mut records = symbol_bytes.chunks_exact(COFF_SYMBOL_SIZE);
+
+    while let Some(record) = records.next() {
+        let section_number = u16_from_bytes(record, 12);
+        let storage_class = record[16];
+        let number_of_auxillary_symbols = record[17];
+        let mut auxillary_symbols = vec!();
+
+        for _ in 0..number_of_auxillary_symbols {
+            let auxillary_record = match records.next() {
+                Some(auxillary_record) => auxillary_record,
+                None => return Err(CoffReadError::AuxillarySymbolsOutOfBounds { symbol_index, count: number_of_auxillary_symbols })
+            };
+            auxillary_symbols.push(read_auxillary_symbol(auxillary_record, section_number, storage_class));
+        }
+
+        symbol_handles.insert(symbol_index, CoffSymbolHandle(symbols.len() as u32));
+        symbols.push(CoffSymbolTableEntry { symbol: decode_primary_symbol(record), auxillary_symbols });
+        symbol_index += 1 + number_of_auxillary_symbols as u32;
+    }
+
+    Ok((symbols, symbol_handles))
+}
+
+fn read_auxillary_symbol(record: &[u8], section_number: u16, storage_class: u8) -> CoffSymbol {
+    if storage_class == IMAGE_SYM_CLASS_STATIC && section_number > 0 && section_number < IMAGE_SYM_DEBUG {
+        decode_section_symbol(record)
+    } else {
+        decode_name_symbol(record)
+    }
+}
+
+fn read_strings(bytes: &[u8], header: &CoffHeader) -> Result<Vec<u8>, CoffReadError> {
+    if header.pointer_to_symbol_table == 0 {
+        return Ok(vec!());
+    }
+
+    let pointer = header.pointer_to_symbol_table as usize + header.number_of_symbols as usize * COFF_SYMBOL_SIZE;
+
+    if pointer == bytes.len() {
+        return Ok(vec!());
+    }
+
+    let length = u32_from_bytes(read_bytes(bytes, pointer, size_of::<u32>(), "string table length")?, 0);
+
+    if (length as usize) < size_of::<u32>() {
+        return Err(CoffReadError::StringTableLengthInvalid(length));
+    }
+
+    match read_bytes(bytes, pointer + size_of::<u32>(), length as usize - size_of::<u32>(), "string table") {
+        Ok(strings) => Ok(strings.into()),
+        Err(_) => Err(CoffReadError::StringTableOutOfBounds { pointer: pointer as u32, length })
+    }
+}
+
+fn read_bytes<'a>(bytes: &'a [u8], offset: usize, length: usize, reading: &'static str) -> Result<&'a [u8], CoffReadError> {
+    match offset.checked_add(length) {
+        Some(end) if end <= bytes.len() => Ok(&bytes[offset..end]),
+        _ => Err(CoffReadError::UnexpectedEndOfData { reading, offset, length, available: bytes.len() })
+    }
+}
+
+fn section_header_name(section_header: &CoffSectionHeader) -> String {
+    string_from_padded_u8_array(&section_header.short_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intemediate_representation::*;
+
+    fn built_coff() -> Coff {
+        let mut ir = create_intermediate_representation(compilation_unit_id(1), string("round_trip.hep"));
+        let main_index = add_symbol(&mut ir.symbols, external_function(string("main")));
+        let callee_index = add_symbol(&mut ir.symbols, foreign_external(string("a_name_longer_than_eight_bytes")));
+        let message = add_data_item(&mut ir.data, read_only_string_data_item(string("message\0")));
+        let buffer = add_data_item(&mut ir.data, zero_initialised_data_item(64, 16));
+        add_byte_code(&mut ir.byte_code, define_function_instruction(main_index));
+        add_byte_code(&mut ir.byte_code, load_data_section_address_to_reg_64(message, call_arg_register(0)));
+        add_byte_code(&mut ir.byte_code, load_data_section_address_to_reg_64(buffer, call_arg_register(1)));
+        add_byte_code(&mut ir.byte_code, call_to_symbol_instruction(callee_index));
+        add_byte_code(&mut ir.byte_code, ret_instruction());
+
+        let mut coff = create_coff();
+        build_machine_code_object(&mut coff, ir).unwrap();
+        set_timestamp(&mut coff, fixed_timestamp(0x12345678));
+        coff
+    }
+
+    #[test]
+    fn built_object_round_trips() {
+        let written = coff_to_bytes(&built_coff()).unwrap();
+        let read = read_coff_from_bytes(&written).unwrap();
+
+        assert_eq!(get_timestamp(&read), fixed_timestamp(0x12345678));
+        assert_eq!(get_sections(&read).iter().map(get_section_name).collect::<Vec<_>>(), [".data", ".text", ".rdata", ".bss", ".xdata", ".pdata"]);
+        assert_eq!(get_string(&read, 4).as_deref(), Some("a_name_longer_than_eight_bytes"));
+        assert_eq!(coff_to_bytes(&read).unwrap(), written);
+    }
+
+    #[test]
+    fn baseline_objects_round_trip() {
+        for bytes in [
+            &include_bytes!("../../../hello-main.obj")[..],
+            &include_bytes!("../../../hello-print.obj")[..],
+            &include_bytes!("../../../hello-STD_OUTPUT_HANDLE.obj")[..]
+        ] {
+            assert_eq!(coff_to_bytes(&read_coff_from_bytes(bytes).unwrap()).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn object_without_symbol_table_has_no_strings() {
+        let bytes = encode_header(&header(IMAGE_FILE_MACHINE_AMD64, 0, 0, 0, 0, 0, 0));
+        let coff = read_coff_from_bytes(&bytes).unwrap();
+
+        assert!(get_symbols(&coff).is_empty());
+        assert!(get_strings(&coff).is_empty());
+    }
+
+    #[test]
+    fn section_data_without_pointer_is_rejected() {
+        let mut bytes = encode_header(&header(IMAGE_FILE_MACHINE_AMD64, 1, 0, 0, 0, 0, 0));
+        bytes.extend(encode_section_header(&section_header(*b".data\0\0\0", 0, 0, 4, 0, 0, 0, 0, 0, IMAGE_SCN_CNT_INITIALISED_DATA)));
+
+        assert!(matches!(
+            read_coff_from_bytes(&bytes),
+            Err(CoffReadError::SectionDataOutOfBounds { pointer: 0, size: 4, .. })
+        ));
+    }
+}
@@ -1,8 +1,18 @@
-use std::mem::size_of;
+use super::get_default_section_mut;
 use crate::machine_code::*;
+use crate::errors::*;
 
+pub const IMAGE_REL_AMD64_ADDR64: u16 = 0x01;
 pub const IMAGE_REL_AMD64_ADDR32: u16 = 0x02;
+pub const IMAGE_REL_AMD64_ADDR32NB: u16 = 0x03;
 pub const IMAGE_REL_AMD64_REL32: u16 = 0x04;
+pub const IMAGE_REL_AMD64_REL32_1: u16 = 0x05;
+pub const IMAGE_REL_AMD64_REL32_2: u16 = 0x06;
+pub const IMAGE_REL_AMD64_REL32_3: u16 = 0x07;
+pub const IMAGE_REL_AMD64_REL32_4: u16 = 0x08;
+pub const IMAGE_REL_AMD64_REL32_5: u16 = 0x09;
+pub const IMAGE_REL_AMD64_SECTION: u16 = 0x0A;
+pub const IMAGE_REL_AMD64_SECREL: u16 = 0x0B;
 
 fn relocation_entry(
     pointer_to_reference: u32,
@@ -16,29 +26,69 @@ fn relocation_entry(
     }
 }
 
+pub fn resolve_relocation(relocation: &CoffRelocation, symbol_indices: &[u32]) -> Option<CoffRelocationEntry> {
+    symbol_indices
+        .get(relocation.symbol.0 as usize)
+        .map(|symbol_index| relocation_entry(relocation.offset, *symbol_index, relocation.relocation_type))
+}
+
+pub fn get_relocation_offset(relocation: &CoffRelocation) -> u32 {
+    relocation.offset
+}
+
+pub fn get_relocation_symbol(relocation: &CoffRelocation) -> CoffSymbolHandle {
+    relocation.symbol
+}
+
+pub fn get_relocation_type(relocation: &CoffRelocation) -> u16 {
+    relocation.relocation_type
+}
+
 pub struct RelocatableValue { 
-    symbol_index: u32,
+    symbol: CoffSymbolHandle,
     initial_value_to_use: u32
 }
 
-pub fn relocatable_value(symbol_index: u32, initial_value_to_use: u32) -> RelocatableValue {
-    RelocatableValue { symbol_index, initial_value_to_use }
+pub fn relocatable_value(symbol: CoffSymbolHandle, initial_value_to_use: u32) -> RelocatableValue {
+    RelocatableValue { symbol, initial_value_to_use }
 }
 
-fn add_relocation_entry(coff: &mut Coff, entry: CoffRelocationEntry) {
-    coff.relocations.push(entry);
-    coff.text_section_header.number_of_relocations += 1;
-    coff.header.pointer_to_symbol_table += size_of::<CoffRelocationEntry>() as u32;
-    set_current_timestamp(coff);
+/// Adds a relocation against entries already in the section, for fields that are not a 32 bit value at the section end.
+pub fn add_relocation_at_offset(
+    coff: &mut Coff,
+    section_number: u16,
+    offset: u32,
+    symbol: CoffSymbolHandle,
+    relocation_type: u16
+) -> Result<(), CoffError> {
+    get_section_mut(coff, section_number)?.relocations.push(CoffRelocation { offset, symbol, relocation_type });
+    Ok(())
+}
+
+fn add_relocatable_entry(section: &mut CoffSection, relocatable_value: RelocatableValue, relocation_type: u16) {
+    section.relocations.push(CoffRelocation {
+        offset: get_section_size(section),
+        symbol: relocatable_value.symbol,
+        relocation_type
+    });
+    section.data.extend(u32_to_bytes(&relocatable_value.initial_value_to_use));
+}
+
+pub fn add_relocatable_entry_and_section_initial_entry(
+    coff: &mut Coff,
+    section_number: u16,
+    relocatable_value: RelocatableValue,
+    relocation_type: u16
+) -> Result<(), CoffError> {
+    let section = get_section_mut(coff, section_number)?;
+    if is_uninitialised_data_section(section) {
+        return Err(CoffError::UninitialisedDataSectionEntries(section.name.clone()));
+    }
+    add_relocatable_entry(section, relocatable_value, relocation_type);
+    Ok(())
 }
 
 pub fn add_relocatable_entry_and_text_section_inital_entry(coff: &mut Coff, relocatable_value: RelocatableValue, relocation_type: u16) { 
-    add_relocation_entry(
-        coff, 
-        relocation_entry(
-            coff.text_section_header.size_of_section, 
-            relocatable_value.symbol_index, relocation_type
-        )
-    );
-    add_entries_to_text_section(coff, u32_to_bytes(&relocatable_value.initial_value_to_use));
+    let section_number = get_or_add_text_section_number(coff);
+    add_relocatable_entry(get_default_section_mut(coff, section_number), relocatable_value, relocation_type);
 }
\ No newline at end of file
@@ -1,36 +1,298 @@
+use super::get_default_section_mut;
 use crate::machine_code::*;
+use crate::errors::*;
 
-fn advance_data_section(coff: &mut Coff, amount: u32) {
-    coff.data_section_header.size_of_section += amount;
-    coff.data_section_header.pointer_to_relocations += amount;
-    coff.text_section_header.pointer_to_section += amount;
-    coff.text_section_header.pointer_to_relocations += amount;
-    coff.header.pointer_to_symbol_table += amount;
-    set_current_timestamp(coff);
+const IMAGE_SCN_ALIGN_4BYTES: u32 = 0x00300000;
+const IMAGE_SCN_ALIGN_16BYTES: u32 = 0x00500000;
+const IMAGE_SCN_ALIGN_SHIFT: u32 = 20;
+pub const IMAGE_SCN_ALIGN_MASK: u32 = 0x00F00000;
+const MAX_SECTION_ALIGNMENT: u32 = 8192;
+
+pub fn section_alignment_flags(alignment: u32) -> Result<u32, CoffError> {
+    if !alignment.is_power_of_two() || alignment > MAX_SECTION_ALIGNMENT {
+        return Err(CoffError::InvalidAlignment(alignment));
+    }
+    Ok((alignment.trailing_zeros() + 1) << IMAGE_SCN_ALIGN_SHIFT)
 }
 
-pub fn add_string_to_data_section(coff: &mut Coff, to_add: &str) -> u32 {
-    let pointer = coff.data_section_header.size_of_section; 
-    let mut string_bytes = string_to_bytes(to_add);
-    advance_data_section(coff, string_bytes.len() as u32);
-    coff.data_section.append(&mut string_bytes);
+fn section_alignment(flags: u32) -> u32 {
+    match (flags & IMAGE_SCN_ALIGN_MASK) >> IMAGE_SCN_ALIGN_SHIFT {
+        0 => 1,
+        shift => 1 << (shift - 1)
+    }
+}
+
+pub fn get_section_alignment(section: &CoffSection) -> u32 {
+    section_alignment(section.flags)
+}
+
+pub fn is_uninitialised_data_section(section: &CoffSection) -> bool {
+    section.flags & IMAGE_SCN_CNT_UNINITIALISED_DATA == IMAGE_SCN_CNT_UNINITIALISED_DATA
+}
+
+pub fn is_discardable_section(section: &CoffSection) -> bool {
+    section.flags & (IMAGE_SCN_LNK_REMOVE | IMAGE_SCN_LNK_INFO | IMAGE_SCN_MEM_DISCARDABLE) != 0
+}
+
+pub fn get_section_size(section: &CoffSection) -> u32 {
+    if is_uninitialised_data_section(section) {
+        section.size_of_uninitialised_data
+    } else {
+        section.data.len() as u32
+    }
+}
+
+pub fn add_section(coff: &mut Coff, name: &str, flags: u32, alignment: u32) -> Result<u16, CoffError> {
+    let alignment_flags = section_alignment_flags(alignment)?;
+    let short_name = match get_8_padded_u8_array_from_string(name) {
+        Ok(short_name) => short_name,
+        Err(_) => {
+            let short_name = get_8_padded_u8_array_from_string(&format!("/{}", get_strings_table_length(coff)))?;
+            add_string(coff, name);
+            short_name
+        }
+    };
+    Ok(push_section(coff, name, short_name, flags | alignment_flags))
+}
+
+pub fn add_default_section(coff: &mut Coff, name: &str, flags: u32) -> u16 {
+    push_section(coff, name, get_truncated_8_padded_u8_array_from_string(name), flags)
+}
+
+fn push_section(coff: &mut Coff, name: &str, short_name: [u8; 8], flags: u32) -> u16 {
+    coff.sections.push(CoffSection {
+        name: string(name),
+        short_name,
+        flags,
+        data: vec!(),
+        size_of_uninitialised_data: 0,
+        relocations: vec!(),
+        definition_symbol: None
+    });
+    get_number_of_sections(coff)
+}
+
+pub fn get_number_of_sections(coff: &Coff) -> u16 {
+    coff.sections.len() as u16
+}
+
+pub fn get_section_number(coff: &Coff, name: &str) -> Option<u16> {
+    coff.sections
+        .iter()
+        .position(|section| section.name == name)
+        .map(|index| (index + 1) as u16)
+}
+
+pub fn get_sections(coff: &Coff) -> &[CoffSection] {
+    &coff.sections
+}
+
+pub fn get_section_name(section: &CoffSection) -> &str {
+    &section.name
+}
+
+pub fn get_section_flags(section: &CoffSection) -> u32 {
+    section.flags
+}
+
+pub fn get_section_data(section: &CoffSection) -> &[u8] {
+    &section.data
+}
+
+pub fn get_section_relocations(section: &CoffSection) -> &[CoffRelocation] {
+    &section.relocations
+}
+
+pub fn get_section_definition_symbol(section: &CoffSection) -> Option<CoffSymbolHandle> {
+    section.definition_symbol
+}
+
+fn get_or_add_default_section_number(coff: &mut Coff, name: &str, flags: u32) -> u16 {
+    match get_section_number(coff, name) {
+        Some(section_number) => section_number,
+        None => add_default_section(coff, name, flags)
+    }
+}
+
+pub fn get_or_add_data_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        DATA_SECTION_NAME,
+        IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE
+    )
+}
+
+pub fn get_or_add_text_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        TEXT_SECTION_NAME,
+        IMAGE_SCN_CNT_CODE | IMAGE_SCN_ALIGN_16BYTES | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ
+    )
+}
+
+pub fn get_or_add_read_only_data_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        READ_ONLY_DATA_SECTION_NAME,
+        IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_READ
+    )
+}
+
+pub fn get_or_add_uninitialised_data_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        UNINITIALISED_DATA_SECTION_NAME,
+        IMAGE_SCN_CNT_UNINITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE
+    )
+}
+
+pub fn get_or_add_exception_data_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        EXCEPTION_DATA_SECTION_NAME,
+        IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_READ
+    )
+}
+
+pub fn get_or_add_unwind_data_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        UNWIND_DATA_SECTION_NAME,
+        IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_READ
+    )
+}
+
+pub fn get_or_add_debug_symbols_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        DEBUG_SYMBOLS_SECTION_NAME,
+        IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_DISCARDABLE | IMAGE_SCN_MEM_READ
+    )
+}
+
+pub fn get_or_add_debug_types_section_number(coff: &mut Coff) -> u16 {
+    get_or_add_default_section_number(
+        coff,
+        DEBUG_TYPES_SECTION_NAME,
+        IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_ALIGN_4BYTES | IMAGE_SCN_MEM_DISCARDABLE | IMAGE_SCN_MEM_READ
+    )
+}
+
+pub fn get_section(coff: &Coff, section_number: u16) -> Result<&CoffSection, CoffError> {
+    Ok(&coff.sections[section_index(coff, section_number)?])
+}
+
+pub fn get_section_mut(coff: &mut Coff, section_number: u16) -> Result<&mut CoffSection, CoffError> {
+    let index = section_index(coff, section_number)?;
+    Ok(&mut coff.sections[index])
+}
+
+fn section_index(coff: &Coff, section_number: u16) -> Result<usize, CoffError> {
+    let number_of_sections = get_number_of_sections(coff);
+    if section_number == 0 || section_number > number_of_sections {
+        return Err(CoffError::SectionNumberOutOfRange { section_number, number_of_sections });
+    }
+    Ok((section_number - 1) as usize)
+}
+
+fn append_entries(section: &mut CoffSection, mut entries: Vec<u8>) -> u32 {
+    let pointer = get_section_size(section);
+    section.data.append(&mut entries);
     pointer
 }
 
+pub fn add_entries_to_section(coff: &mut Coff, section_number: u16, entries: Vec<u8>) -> Result<u32, CoffError> {
+    let section = get_section_mut(coff, section_number)?;
+    if is_uninitialised_data_section(section) {
+        return Err(CoffError::UninitialisedDataSectionEntries(section.name.clone()));
+    }
+    Ok(append_entries(section, entries))
+}
+
+pub fn patch_entries_in_section(coff: &mut Coff, section_number: u16, offset: u32, entries: &[u8]) -> Result<(), CoffError> {
+    patch_entries(get_section_mut(coff, section_number)?, offset, entries);
+    Ok(())
+}
+
+fn patch_entries(section: &mut CoffSection, offset: u32, entries: &[u8]) {
+    let start = offset as usize;
+    section.data[start..start + entries.len()].copy_from_slice(entries);
+}
+
+pub fn get_current_section_pointer(coff: &Coff, section_number: u16) -> Result<u32, CoffError> {
+    Ok(get_section_size(get_section(coff, section_number)?))
+}
+
+pub fn add_string_to_data_section(coff: &mut Coff, to_add: &str) -> u32 {
+    let section_number = get_or_add_data_section_number(coff);
+    append_entries(get_default_section_mut(coff, section_number), string_to_bytes(to_add))
+}
+
+pub fn add_string_to_read_only_data_section(coff: &mut Coff, to_add: &str) -> u32 {
+    add_bytes_to_read_only_data_section(coff, string_to_bytes(to_add))
+}
+
+pub fn add_bytes_to_read_only_data_section(coff: &mut Coff, to_add: Vec<u8>) -> u32 {
+    let section_number = get_or_add_read_only_data_section_number(coff);
+    append_entries(get_default_section_mut(coff, section_number), to_add)
+}
+
+pub fn reserve_uninitialised_data(coff: &mut Coff, size: u32, alignment: u32) -> Result<u32, CoffError> {
+    let alignment_flags = section_alignment_flags(alignment)?;
+    let section_number = get_or_add_uninitialised_data_section_number(coff);
+    let section = get_default_section_mut(coff, section_number);
+    
+    if alignment > section_alignment(section.flags) {
+        section.flags = (section.flags & !IMAGE_SCN_ALIGN_MASK) | alignment_flags;
+    }
+    
+    let pointer = align_up(section.size_of_uninitialised_data, alignment);
+    section.size_of_uninitialised_data = pointer
+        .checked_add(size)
+        .ok_or(CoffError::OffsetTooLarge { context: "uninitialised data" })?;
+    Ok(pointer)
+}
+
 pub fn add_entry_to_text_section(coff: &mut Coff, entry: u8) {
-    coff.text_section.push(entry);
-    coff.text_section_header.size_of_section += 1;
-    coff.text_section_header.pointer_to_relocations += 1;
-    coff.header.pointer_to_symbol_table += 1;
-    set_current_timestamp(coff);
+    add_entries_to_text_section(coff, vec!(entry));
 }
 
 pub fn add_entries_to_text_section(coff: &mut Coff, entries: Vec<u8>) {
-    for entry in entries {
-        add_entry_to_text_section(coff, entry);
-    }
+    let section_number = get_or_add_text_section_number(coff);
+    append_entries(get_default_section_mut(coff, section_number), entries);
+}
+
+pub fn patch_entries_in_text_section(coff: &mut Coff, offset: u32, entries: &[u8]) {
+    let section_number = get_or_add_text_section_number(coff);
+    patch_entries(get_default_section_mut(coff, section_number), offset, entries);
 }
 
 pub fn get_current_text_section_pointer(coff: &Coff) -> u32 {
-    coff.text_section_header.size_of_section
-}
\ No newline at end of file
+    match get_section_number(coff, TEXT_SECTION_NAME).and_then(|section_number| get_section(coff, section_number).ok()) {
+        Some(section) => get_section_size(section),
+        None => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_numbers_out_of_range_are_rejected() {
+        let mut coff = create_coff();
+
+        assert!(matches!(get_section(&coff, 0), Err(CoffError::SectionNumberOutOfRange { section_number: 0, number_of_sections: 2 })));
+        assert!(matches!(add_entries_to_section(&mut coff, 3, vec!(0)), Err(CoffError::SectionNumberOutOfRange { section_number: 3, .. })));
+    }
+
+    #[test]
+    fn entries_cannot_be_added_to_uninitialised_data() {
+        let mut coff = create_coff();
+        reserve_uninitialised_data(&mut coff, 8, 8).unwrap();
+        let section_number = get_or_add_uninitialised_data_section_number(&mut coff);
+
+        assert!(matches!(add_entries_to_section(&mut coff, section_number, vec!(0)), Err(CoffError::UninitialisedDataSectionEntries(_))));
+        assert_eq!(get_current_section_pointer(&coff, section_number).unwrap(), 8);
+    }
+}
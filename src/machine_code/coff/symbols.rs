@@ -1,31 +1,35 @@
+use std::mem::size_of;
+use super::CoffSymbolHandle;
 use crate::machine_code::*;
+use crate::errors::*;
 
-const IMAGE_SYM_DEBUG: u16 = 0xFFFE;
-const IMAGE_SYM_CLASS_FILE: u8 = 0x67;
-const IMAGE_SYM_CLASS_EXTERNAL: u8 = 0x02;
-const IMAGE_SYM_CLASS_STATIC: u8 = 0x03;
-const IMAGE_SYM_ABSOLUTE: u16 = 0xFFFF;
+pub const IMAGE_SYM_UNDEFINED: u16 = 0x0000;
+pub const IMAGE_SYM_DEBUG: u16 = 0xFFFE;
+pub const IMAGE_SYM_CLASS_FILE: u8 = 0x67;
+pub const IMAGE_SYM_CLASS_SECTION: u8 = 0x68;
+pub const IMAGE_SYM_CLASS_EXTERNAL: u8 = 0x02;
+pub const IMAGE_SYM_CLASS_STATIC: u8 = 0x03;
+pub const IMAGE_SYM_ABSOLUTE: u16 = 0xFFFF;
+pub const IMAGE_SYM_DTYPE_FUNCTION: u16 = 0x20;
 
 fn short_named_symbol(
-    name: &str,
+    name: [u8; 8],
     value: u32,
     section_number: u16,
     symbol_type: u16,
     storage_class: u8,
     number_of_auxillary_symbols: u8
 ) -> CoffSymbol {
-    CoffSymbol { 
-        short_named: {
-            CoffSymbolShortNamed {
-                name: get_8_padded_u8_array_from_string(name),
-                value,
-                section_number,
-                symbol_type,
-                storage_class,
-                number_of_auxillary_symbols
-            }
+    CoffSymbol::ShortNamed(
+        CoffSymbolShortNamed {
+            name,
+            value,
+            section_number,
+            symbol_type,
+            storage_class,
+            number_of_auxillary_symbols
         }
-    }
+    )
 }
 
 fn long_named_symbol(
@@ -36,30 +40,24 @@ fn long_named_symbol(
     storage_class: u8,
     number_of_auxillary_symbols: u8
 ) -> CoffSymbol {
-    CoffSymbol { 
-        long_named: {
-            CoffSymbolLongNamed {
-                pad: 0,
-                pointer_to_string_table,
-                value,
-                section_number,
-                symbol_type,
-                storage_class,
-                number_of_auxillary_symbols
-            }
+    CoffSymbol::LongNamed(
+        CoffSymbolLongNamed {
+            pad: 0,
+            pointer_to_string_table,
+            value,
+            section_number,
+            symbol_type,
+            storage_class,
+            number_of_auxillary_symbols
         }
-    }
+    )
 }
 
 fn name_symbol(name: &str) -> CoffSymbol {
-    CoffSymbol { 
-        name: { 
-            CoffSymbolName(get_truncated_18_padded_u8_array_from_string(name))
-        }
-    }
+    CoffSymbol::Name(CoffSymbolName(get_truncated_18_padded_u8_array_from_string(name)))
 }
 
-fn section_symbol(
+pub fn section_symbol(
     length: u32,
     number_of_relocations: u16,
     number_of_line_numbers: u16,
@@ -67,37 +65,147 @@ fn section_symbol(
     number: u16,
     selection: u8
 ) -> CoffSymbol {
-    CoffSymbol { 
-        section: {
-            CoffSymbolSection {
-                length,
-                number_of_relocations,
-                number_of_line_numbers,
-                checksum,
-                number,
-                selection,
-                pad1: 0,
-                pad2: 0
-            }
+    CoffSymbol::Section(
+        CoffSymbolSection {
+            length,
+            number_of_relocations,
+            number_of_line_numbers,
+            checksum,
+            number,
+            selection,
+            pad1: 0,
+            pad2: 0
         }
-    }
+    )
 }
 
-fn add_symbol(coff: &mut Coff, entry: CoffSymbol) {
-    coff.symbols.push(entry);
-    coff.header.number_of_symbols += 1;
-    set_current_timestamp(coff);
+fn add_symbol_table_entry(coff: &mut Coff, symbol: CoffSymbol, auxillary_symbols: Vec<CoffSymbol>) -> CoffSymbolHandle {
+    coff.symbols.push(CoffSymbolTableEntry { symbol, auxillary_symbols });
+    CoffSymbolHandle((coff.symbols.len() - 1) as u32)
 }
 
-fn add_string(coff: &mut Coff, entry: &str) -> u32 {
+pub fn add_string(coff: &mut Coff, entry: &str) -> u32 {
     let mut new_string = string_to_bytes_zero_terminated(entry);
-    let pointer = coff.strings_table_length;
-    coff.strings_table_length += new_string.len() as u32;
+    let pointer = get_strings_table_length(coff);
     coff.strings.append(&mut new_string);
-    set_current_timestamp(coff);
     pointer
 }
 
+pub fn get_strings_table_length(coff: &Coff) -> u32 {
+    (size_of::<u32>() + coff.strings.len()) as u32
+}
+
+pub fn get_strings(coff: &Coff) -> &[u8] {
+    &coff.strings
+}
+
+pub fn get_string(coff: &Coff, pointer: u32) -> Option<String> {
+    let offset = (pointer as usize).checked_sub(size_of::<u32>())?;
+    if offset >= coff.strings.len() {
+        return None;
+    }
+    Some(string_from_padded_u8_array(&coff.strings[offset..]))
+}
+
+pub fn get_symbols(coff: &Coff) -> &[CoffSymbolTableEntry] {
+    &coff.symbols
+}
+
+pub fn get_symbol_handles(coff: &Coff) -> impl Iterator<Item = CoffSymbolHandle> {
+    (0..coff.symbols.len() as u32).map(CoffSymbolHandle)
+}
+
+pub fn get_symbol(coff: &Coff, handle: CoffSymbolHandle) -> Option<&CoffSymbolTableEntry> {
+    coff.symbols.get(handle.0 as usize)
+}
+
+pub fn get_primary_symbol(entry: &CoffSymbolTableEntry) -> &CoffSymbol {
+    &entry.symbol
+}
+
+pub fn get_auxillary_symbols(entry: &CoffSymbolTableEntry) -> &[CoffSymbol] {
+    &entry.auxillary_symbols
+}
+
+pub fn get_number_of_symbol_records(coff: &Coff) -> usize {
+    coff.symbols
+        .iter()
+        .map(|entry| 1 + entry.auxillary_symbols.len())
+        .sum()
+}
+
+pub fn get_symbol_name(coff: &Coff, symbol: &CoffSymbol) -> Option<String> {
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => Some(string_from_padded_u8_array(&short_named.name)),
+        CoffSymbol::LongNamed(long_named) => get_string(coff, long_named.pointer_to_string_table),
+        CoffSymbol::Name(name) => Some(string_from_padded_u8_array(&name.0)),
+        CoffSymbol::Section(_) => None
+    }
+}
+
+pub fn get_symbol_value(symbol: &CoffSymbol) -> Option<u32> {
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => Some(short_named.value),
+        CoffSymbol::LongNamed(long_named) => Some(long_named.value),
+        _ => None
+    }
+}
+
+pub fn get_symbol_section_number(symbol: &CoffSymbol) -> Option<u16> {
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => Some(short_named.section_number),
+        CoffSymbol::LongNamed(long_named) => Some(long_named.section_number),
+        _ => None
+    }
+}
+
+pub fn get_symbol_type(symbol: &CoffSymbol) -> Option<u16> {
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => Some(short_named.symbol_type),
+        CoffSymbol::LongNamed(long_named) => Some(long_named.symbol_type),
+        _ => None
+    }
+}
+
+pub fn get_symbol_storage_class(symbol: &CoffSymbol) -> Option<u8> {
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => Some(short_named.storage_class),
+        CoffSymbol::LongNamed(long_named) => Some(long_named.storage_class),
+        _ => None
+    }
+}
+
+pub fn get_defined_external_symbol_names(coff: &Coff) -> Vec<String> {
+    coff.symbols
+        .iter()
+        .map(|entry| &entry.symbol)
+        .filter(|symbol| get_symbol_storage_class(symbol) == Some(IMAGE_SYM_CLASS_EXTERNAL))
+        .filter(|symbol| get_symbol_section_number(symbol) != Some(IMAGE_SYM_UNDEFINED))
+        .filter_map(|symbol| get_symbol_name(coff, symbol))
+        .collect()
+}
+
+pub fn set_symbol_value(coff: &mut Coff, handle: CoffSymbolHandle, value: u32) -> Result<(), CoffError> {
+    let number_of_symbols = coff.symbols.len();
+    let entry = coff.symbols
+        .get_mut(handle.0 as usize)
+        .ok_or(CoffError::SymbolIndexOutOfRange { symbol_index: handle.0, number_of_symbols })?;
+    match &mut entry.symbol {
+        CoffSymbol::ShortNamed(short_named) => short_named.value = value,
+        CoffSymbol::LongNamed(long_named) => long_named.value = value,
+        _ => return Err(CoffError::SymbolHasNoValue(handle.0))
+    }
+    Ok(())
+}
+
+pub fn get_symbol_number_of_auxillary_symbols(symbol: &CoffSymbol) -> u8 {
+    match symbol {
+        CoffSymbol::ShortNamed(short_named) => short_named.number_of_auxillary_symbols,
+        CoffSymbol::LongNamed(long_named) => long_named.number_of_auxillary_symbols,
+        _ => 0
+    }
+}
+
 fn add_named_symbol(
     coff: &mut Coff,
     name: &str,
@@ -105,62 +213,122 @@ fn add_named_symbol(
     section_number: u16,
     symbol_type: u16,
     storage_class: u8,
-    number_of_auxillary_symbols: u8
-) {
-    if name.len() <= 8 {
-        add_symbol(coff, short_named_symbol(name, value, section_number, symbol_type, storage_class, number_of_auxillary_symbols));
-    } else {
-        let name_pointer = add_string(coff, name);
-        add_symbol(coff, long_named_symbol(name_pointer, value, section_number, symbol_type, storage_class, number_of_auxillary_symbols));
+    auxillary_symbols: Vec<CoffSymbol>
+) -> CoffSymbolHandle {
+    let number_of_auxillary_symbols = auxillary_symbols.len() as u8;
+    let symbol = match get_8_padded_u8_array_from_string(name) {
+        Ok(short_name) => 
+            short_named_symbol(short_name, value, section_number, symbol_type, storage_class, number_of_auxillary_symbols),
+        Err(_) => {
+            let name_pointer = add_string(coff, name);
+            long_named_symbol(name_pointer, value, section_number, symbol_type, storage_class, number_of_auxillary_symbols)
+        }
+    };
+    add_symbol_table_entry(coff, symbol, auxillary_symbols)
+}
+
+pub fn add_debug_file_name_symbols(coff: &mut Coff, file_name: &str) -> CoffSymbolHandle {
+    add_named_symbol(coff, ".file", 0, IMAGE_SYM_DEBUG, 0, IMAGE_SYM_CLASS_FILE, vec!(name_symbol(file_name)))
+}
+
+/// The aux record is filled in from the section's final size and relocation count by [`layout_coff`].
+pub fn get_or_add_section_symbol(coff: &mut Coff, section_number: u16) -> Result<CoffSymbolHandle, CoffError> {
+    let section = get_section(coff, section_number)?;
+    if let Some(handle) = section.definition_symbol {
+        return Ok(handle);
     }
+    let section_name = string(get_section_name(section));
+    let handle = add_named_symbol(
+        coff, 
+        &section_name, 
+        0, 
+        section_number, 
+        0, 
+        IMAGE_SYM_CLASS_STATIC, 
+        vec!(section_symbol(0, 0, 0, 0, 0, 0))
+    );
+    get_section_mut(coff, section_number)?.definition_symbol = Some(handle);
+    Ok(handle)
+}
+
+pub fn add_section_header_symbols(coff: &mut Coff) -> Result<(), CoffError> {
+    for section_number in 1..=get_number_of_sections(coff) {
+        get_or_add_section_symbol(coff, section_number)?;
+    }
+    Ok(())
+}
+
+pub fn add_absolute_static_symbol(coff: &mut Coff, name: &str, value: u32) -> CoffSymbolHandle {
+    add_static_symbol(coff, name, value, IMAGE_SYM_ABSOLUTE)
+}
+
+pub fn add_absolute_external_symbol(coff: &mut Coff, name: &str, value: u32) -> CoffSymbolHandle {
+    add_external_symbol(coff, name, value, IMAGE_SYM_ABSOLUTE)
 }
 
-pub fn add_debug_file_name_symbols(coff: &mut Coff, file_name: &str) {
-    add_named_symbol(coff, ".file", 0, IMAGE_SYM_DEBUG, 0, IMAGE_SYM_CLASS_FILE, 1);
-    add_symbol(coff, name_symbol(file_name));
+pub fn add_section_static_symbol(coff: &mut Coff, section_number: u16, name: &str, value: u32) -> CoffSymbolHandle {
+    add_static_symbol(coff, name, value, section_number)
 }
 
-fn add_section_symbols(coff: &mut Coff, section_name: &str, section_number: u16, section_length: u32, number_of_relocations: u16) {
-    add_named_symbol(coff, section_name, 0, section_number, 0, IMAGE_SYM_CLASS_STATIC, 1);
-    add_symbol(coff, section_symbol(section_length, number_of_relocations, 0, 0, 0, 0));
+pub fn add_section_external_symbol(coff: &mut Coff, section_number: u16, name: &str, value: u32) -> CoffSymbolHandle {
+    add_external_symbol(coff, name, value, section_number)
 }
 
-pub fn add_data_section_header_symbols(coff: &mut Coff) {
-    let section_size = coff.data_section_header.size_of_section;
-    let number_of_relocations = coff.data_section_header.number_of_relocations;
-    add_section_symbols(coff, ".data", 1, section_size, number_of_relocations);
+pub fn add_data_section_static_symbol(coff: &mut Coff, name: &str, value: u32) -> CoffSymbolHandle {
+    let section_number = get_or_add_data_section_number(coff);
+    add_section_static_symbol(coff, section_number, name, value)
 }
 
-pub fn add_text_section_header_symbols(coff: &mut Coff) {
-    let section_size = coff.text_section_header.size_of_section;
-    let number_of_relocations = coff.text_section_header.number_of_relocations;
-    add_section_symbols(coff, ".text", 2, section_size, number_of_relocations);
+pub fn add_foreign_external_symbol(coff: &mut Coff, name: &str) -> CoffSymbolHandle {
+    add_external_symbol(coff, name, 0, IMAGE_SYM_UNDEFINED)
 }
 
-pub fn add_absolute_static_symbol(coff: &mut Coff, name: &str, value: u32) {
-    add_static_symbol(coff, name, value, IMAGE_SYM_ABSOLUTE);
+/// Adds an undefined symbol that the linker resolves to the start of the output section grouping of that name,
+/// such as `.idata$5` in an import descriptor.
+pub fn add_section_reference_symbol(coff: &mut Coff, name: &str) -> CoffSymbolHandle {
+    add_named_symbol(coff, name, 0, IMAGE_SYM_UNDEFINED, 0, IMAGE_SYM_CLASS_SECTION, vec!())
 }
 
-pub fn add_absolute_external_symbol(coff: &mut Coff, name: &str, value: u32) {
-    add_external_symbol(coff, name, value, IMAGE_SYM_ABSOLUTE);
+pub fn add_text_section_external_symbol(coff: &mut Coff, name: &str, value: u32) -> CoffSymbolHandle {
+    let section_number = get_or_add_text_section_number(coff);
+    add_section_external_symbol(coff, section_number, name, value)
 }
 
-pub fn add_data_section_static_symbol(coff: &mut Coff, name: &str, value: u32) {
-    add_static_symbol(coff, name, value, 1);
+pub fn add_text_section_external_function_symbol(coff: &mut Coff, name: &str, value: u32) -> CoffSymbolHandle {
+    let section_number = get_or_add_text_section_number(coff);
+    add_named_symbol(coff, name, value, section_number, IMAGE_SYM_DTYPE_FUNCTION, IMAGE_SYM_CLASS_EXTERNAL, vec!())
 }
 
-pub fn add_foreign_external_symbol(coff: &mut Coff, name: &str) {
-    add_external_symbol(coff, name, 0, 0);
+pub fn add_text_section_static_function_symbol(coff: &mut Coff, name: &str, value: u32) -> CoffSymbolHandle {
+    let section_number = get_or_add_text_section_number(coff);
+    add_named_symbol(coff, name, value, section_number, IMAGE_SYM_DTYPE_FUNCTION, IMAGE_SYM_CLASS_STATIC, vec!())
 }
 
-pub fn add_text_section_external_symbol(coff: &mut Coff, name: &str, value: u32) {
-    add_external_symbol(coff, name, value, 2);
+fn add_static_symbol(coff: &mut Coff, name: &str, value: u32, section_number: u16) -> CoffSymbolHandle {
+    add_named_symbol(coff, name, value, section_number, 0, IMAGE_SYM_CLASS_STATIC, vec!())
 }
 
-fn add_static_symbol(coff: &mut Coff, name: &str, value: u32, section_number: u16) {
-    add_named_symbol(coff, name, value, section_number, 0, IMAGE_SYM_CLASS_STATIC, 0);
+fn add_external_symbol(coff: &mut Coff, name: &str, value: u32, section_number: u16) -> CoffSymbolHandle {
+    add_named_symbol(coff, name, value, section_number, 0, IMAGE_SYM_CLASS_EXTERNAL, vec!())
 }
 
-fn add_external_symbol(coff: &mut Coff, name: &str, value: u32, section_number: u16) {
-    add_named_symbol(coff, name, value, section_number, 0, IMAGE_SYM_CLASS_EXTERNAL, 0);
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_named_symbols_in_the_table_have_values_set() {
+        let mut coff = create_coff();
+        let named = add_text_section_external_symbol(&mut coff, "main", 0);
+        let unnamed = add_symbol_table_entry(&mut coff, section_symbol(0, 0, 0, 0, 1, 0), vec!());
+
+        set_symbol_value(&mut coff, named, 16).unwrap();
+        assert_eq!(get_symbol(&coff, named).map(get_primary_symbol).and_then(get_symbol_value), Some(16));
+
+        assert!(matches!(set_symbol_value(&mut coff, unnamed, 16), Err(CoffError::SymbolHasNoValue(_))));
+        assert!(matches!(
+            set_symbol_value(&mut coff, CoffSymbolHandle(99), 16),
+            Err(CoffError::SymbolIndexOutOfRange { symbol_index: 99, .. })
+        ));
+    }
+}
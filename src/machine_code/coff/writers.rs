@@ -0,0 +1,104 @@
+use std::*;
+use std::io::Write;
+use super::CoffSymbolHandle;
+use crate::machine_code::*;
+use crate::errors::*;
+
+pub fn write_coff<W: Write>(coff: &Coff, writer: &mut W) -> Result<(), CoffError> {
+    let layout = layout_coff(coff)?;
+    write_coff_layout(coff, &layout, writer).map_err(|error| io_error("could not write object", error))
+}
+
+fn write_coff_layout<W: Write>(coff: &Coff, layout: &CoffLayout, writer: &mut W) -> io::Result<()> {
+    writer.write_all(&encode_header(&layout.header))?;
+    for section_header in &layout.section_headers {
+        writer.write_all(&encode_section_header(section_header))?;
+    }
+    for (section, relocations) in coff.sections.iter().zip(&layout.section_relocations) {
+        writer.write_all(&section.data)?;
+        for relocation in relocations {
+            writer.write_all(&encode_relocation_entry(relocation))?;
+        }
+    }
+    for (handle, entry) in coff.symbols.iter().enumerate() {
+        writer.write_all(&encode_symbol(&entry.symbol))?;
+        match layout.section_definition_symbols.get(&CoffSymbolHandle(handle as u32)) {
+            Some(section_definition_symbol) => writer.write_all(&encode_symbol(section_definition_symbol))?,
+            None => for auxillary_symbol in &entry.auxillary_symbols {
+                writer.write_all(&encode_symbol(auxillary_symbol))?;
+            }
+        }
+    }
+    writer.write_all(&layout.strings_table_length.to_le_bytes())?;
+    writer.write_all(&coff.strings)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn coff_to_bytes(coff: &Coff) -> Result<Vec<u8>, CoffError> {
+    let mut bytes = vec!();
+    write_coff(coff, &mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn empty_object_is_header_and_string_table_length() {
+        let mut coff = create_coff_without_sections();
+        set_timestamp(&mut coff, fixed_timestamp(0x01020304));
+        let mut bytes = vec!();
+        write_coff(&coff, &mut bytes).unwrap();
+
+        assert_eq!(bytes, [
+            0x64, 0x86, 0x00, 0x00, 0x04, 0x03, 0x02, 0x01, 0x14, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00
+        ]);
+    }
+
+    #[test]
+    fn written_sections_and_relocations_read_back() {
+        let mut coff = create_coff();
+        set_timestamp(&mut coff, fixed_timestamp(0));
+        let text_section_number = get_or_add_text_section_number(&mut coff);
+        let symbol = add_foreign_external_symbol(&mut coff, "callee");
+        add_entries_to_text_section(&mut coff, vec!(0xE8));
+        add_relocatable_entry_and_text_section_inital_entry(&mut coff, relocatable_value(symbol, 0), IMAGE_REL_AMD64_REL32);
+        let mut bytes = vec!();
+        write_coff(&coff, &mut bytes).unwrap();
+
+        let header = decode_header(&bytes);
+        assert_eq!(header, layout_coff(&coff).unwrap().header);
+        assert_eq!(bytes, coff_to_bytes(&coff).unwrap());
+
+        let read = read_coff_from_bytes(&bytes).unwrap();
+        let text_section = get_section(&read, text_section_number).unwrap();
+        assert_eq!(get_section_data(text_section), [0xE8, 0, 0, 0, 0]);
+        let relocation = &get_section_relocations(text_section)[0];
+        assert_eq!(get_relocation_offset(relocation), 1);
+        assert_eq!(get_relocation_type(relocation), IMAGE_REL_AMD64_REL32);
+        let relocation_symbol = get_primary_symbol(get_symbol(&read, get_relocation_symbol(relocation)).unwrap());
+        assert_eq!(get_symbol_name(&read, relocation_symbol).as_deref(), Some("callee"));
+    }
+
+    #[test]
+    fn writer_errors_are_reported() {
+        let coff = create_coff_without_sections();
+
+        assert!(matches!(write_coff(&coff, &mut FailingWriter), Err(CoffError::Io { .. })));
+    }
+}
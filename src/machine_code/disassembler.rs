@@ -0,0 +1,541 @@
+use std::*;
+use crate::machine_code::*;
+use crate::errors::*;
+
+const REGISTER_NAMES_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15"
+];
+const REGISTER_NAMES_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d"
+];
+const REGISTER_NAMES_16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w"
+];
+const REGISTER_NAMES_8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b"
+];
+const REGISTER_NAMES_8_LEGACY: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+
+const ALU_MNEMONICS: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFT_MNEMONICS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+const UNARY_MNEMONICS: [&str; 8] = ["test", "test", "not", "neg", "mul", "imul", "div", "idiv"];
+const CONDITION_SUFFIXES: [&str; 16] = ["o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g"];
+
+const PREFIX_OPERAND_SIZE_OVERRIDE: u8 = 0x66;
+const REX_W: u8 = 0x08;
+const REX_R: u8 = 0x04;
+const REX_X: u8 = 0x02;
+const REX_B: u8 = 0x01;
+
+/// One decoded instruction; bytes that do not decode are listed one at a time as `db`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledInstruction {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+    pub text: String
+}
+
+pub fn disassemble_text_section(coff: &Coff) -> Vec<DisassembledInstruction> {
+    match get_section_number(coff, TEXT_SECTION_NAME).and_then(|section_number| get_section(coff, section_number).ok()) {
+        Some(section) => disassemble_coff_section(coff, section),
+        None => vec!()
+    }
+}
+
+pub fn disassemble_section(coff: &Coff, section_number: u16) -> Result<Vec<DisassembledInstruction>, CoffError> {
+    Ok(disassemble_coff_section(coff, get_section(coff, section_number)?))
+}
+
+fn disassemble_coff_section(coff: &Coff, section: &CoffSection) -> Vec<DisassembledInstruction> {
+    let data = get_section_data(section);
+    let mut instructions = vec!();
+    let mut position = 0;
+
+    while position < data.len() {
+        let mut decoder = instruction_decoder(coff, section, position);
+        let text = match decoder.decode() {
+            Some(text) => text,
+            None => {
+                decoder.position = position + 1;
+                format!("db 0x{:02x}", data[position])
+            }
+        };
+        instructions.push(DisassembledInstruction {
+            offset: position as u32,
+            bytes: data[position..decoder.position].to_vec(),
+            text
+        });
+        position = decoder.position;
+    }
+
+    instructions
+}
+
+pub fn text_section_listing(coff: &Coff) -> String {
+    let section_number = match get_section_number(coff, TEXT_SECTION_NAME) {
+        Some(section_number) => section_number,
+        None => return String::new()
+    };
+    let labels: Vec<(u32, String)> = get_symbols(coff)
+        .iter()
+        .map(get_primary_symbol)
+        .filter(|symbol| get_symbol_section_number(symbol) == Some(section_number) && get_symbol_value(symbol).is_some())
+        .filter(|symbol| get_symbol_name(coff, symbol).as_deref() != Some(TEXT_SECTION_NAME))
+        .filter_map(|symbol| Some((get_symbol_value(symbol)?, get_symbol_name(coff, symbol)?)))
+        .collect();
+
+    let mut listing = String::new();
+    for instruction in disassemble_text_section(coff) {
+        for (_, name) in labels.iter().filter(|(value, _)| *value == instruction.offset) {
+            listing.push_str(&format!("{}:\n", name));
+        }
+        let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        listing.push_str(&format!("{:8x}:  {:<30}{}\n", instruction.offset, bytes.join(" "), instruction.text));
+    }
+    listing
+}
+
+struct InstructionDecoder<'a> {
+    coff: &'a Coff,
+    data: &'a [u8],
+    relocations: &'a [CoffRelocation],
+    position: usize,
+    operand_size_override: bool,
+    rex: Option<u8>
+}
+
+fn instruction_decoder<'a>(coff: &'a Coff, section: &'a CoffSection, position: usize) -> InstructionDecoder<'a> {
+    InstructionDecoder {
+        coff,
+        data: get_section_data(section),
+        relocations: get_section_relocations(section),
+        position,
+        operand_size_override: false,
+        rex: None
+    }
+}
+
+enum ModRmOperand {
+    Register(u8),
+    Memory(String)
+}
+
+impl<'a> InstructionDecoder<'a> {
+    fn decode(&mut self) -> Option<String> {
+        let mut opcode = self.read_u8()?;
+
+        if opcode == PREFIX_OPERAND_SIZE_OVERRIDE {
+            self.operand_size_override = true;
+            opcode = self.read_u8()?;
+        }
+        if opcode & 0xF0 == 0x40 {
+            self.rex = Some(opcode & 0x0F);
+            opcode = self.read_u8()?;
+        }
+
+        match opcode {
+            0x00..=0x3F if opcode & 0x07 < 0x06 => self.decode_alu(opcode),
+            0x50..=0x57 => Some(format!("push {}", REGISTER_NAMES_64[self.extend(opcode & 0x07, REX_B)])),
+            0x58..=0x5F => Some(format!("pop {}", REGISTER_NAMES_64[self.extend(opcode & 0x07, REX_B)])),
+            0x63 => {
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("movsxd {}, {}", self.register_name(register, self.operand_size()), self.operand(operand, 4)))
+            },
+            0x68 => Some(format!("push {}", self.read_immediate(4, 8)?)),
+            0x6A => Some(format!("push {}", self.read_immediate(1, 8)?)),
+            0x69 | 0x6B => {
+                let size = self.operand_size();
+                let (register, operand) = self.read_mod_rm()?;
+                let operand = self.operand(operand, size);
+                let immediate = self.read_immediate(if opcode == 0x6B { 1 } else { immediate_size(size) }, size)?;
+                Some(format!("imul {}, {}, {}", self.register_name(register, size), operand, immediate))
+            },
+            0x70..=0x7F => {
+                let target = self.read_relative(1)?;
+                Some(format!("j{} {}", CONDITION_SUFFIXES[(opcode & 0x0F) as usize], target))
+            },
+            0x80 | 0x81 | 0x83 => {
+                let size = if opcode == 0x80 { 1 } else { self.operand_size() };
+                let (extension, operand) = self.read_mod_rm()?;
+                let operand = self.operand(operand, size);
+                let immediate = self.read_immediate(if opcode == 0x81 { immediate_size(size) } else { 1 }, size)?;
+                Some(format!("{} {}, {}", ALU_MNEMONICS[(extension & 0x07) as usize], operand, immediate))
+            },
+            0x84..=0x89 => {
+                let size = if opcode & 0x01 == 0 { 1 } else { self.operand_size() };
+                let mnemonic = match opcode { 0x84 | 0x85 => "test", 0x86 | 0x87 => "xchg", _ => "mov" };
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("{} {}, {}", mnemonic, self.operand(operand, size), self.register_name(register, size)))
+            },
+            0x8A | 0x8B => {
+                let size = if opcode == 0x8A { 1 } else { self.operand_size() };
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("mov {}, {}", self.register_name(register, size), self.operand(operand, size)))
+            },
+            0x8D => {
+                let (register, operand) = self.read_mod_rm()?;
+                match operand {
+                    ModRmOperand::Memory(memory) => Some(format!("lea {}, {}", self.register_name(register, self.operand_size()), memory)),
+                    ModRmOperand::Register(_) => None
+                }
+            },
+            0x90 if self.rex.is_none() => Some(string("nop")),
+            0x98 => Some(string(match self.operand_size() { 2 => "cbw", 4 => "cwde", _ => "cdqe" })),
+            0x99 => Some(string(match self.operand_size() { 2 => "cwd", 4 => "cdq", _ => "cqo" })),
+            0xB0..=0xB7 => {
+                let register = self.extend(opcode & 0x07, REX_B) as u8;
+                Some(format!("mov {}, {}", self.register_name(register, 1), self.read_immediate(1, 1)?))
+            },
+            0xB8..=0xBF => {
+                let size = self.operand_size();
+                let register = self.extend(opcode & 0x07, REX_B) as u8;
+                Some(format!("mov {}, {}", self.register_name(register, size), self.read_immediate(size, size)?))
+            },
+            0xC0 | 0xC1 | 0xD0 | 0xD1 | 0xD2 | 0xD3 => {
+                let size = if opcode & 0x01 == 0 { 1 } else { self.operand_size() };
+                let (extension, operand) = self.read_mod_rm()?;
+                let operand = self.operand(operand, size);
+                let count = match opcode {
+                    0xC0 | 0xC1 => self.read_immediate(1, 1)?,
+                    0xD0 | 0xD1 => string("1"),
+                    _ => string("cl")
+                };
+                Some(format!("{} {}, {}", SHIFT_MNEMONICS[(extension & 0x07) as usize], operand, count))
+            },
+            0xC3 => Some(string("ret")),
+            0xC6 | 0xC7 => {
+                let size = if opcode == 0xC6 { 1 } else { self.operand_size() };
+                let (extension, operand) = self.read_mod_rm()?;
+                if extension & 0x07 != 0 {
+                    return None;
+                }
+                let operand = self.operand(operand, size);
+                Some(format!("mov {}, {}", operand, self.read_immediate(immediate_size(size), size)?))
+            },
+            0xCC => Some(string("int3")),
+            0xE8 => Some(format!("call {}", self.read_relative(4)?)),
+            0xE9 => Some(format!("jmp {}", self.read_relative(4)?)),
+            0xEB => Some(format!("jmp {}", self.read_relative(1)?)),
+            0xF6 | 0xF7 => {
+                let size = if opcode == 0xF6 { 1 } else { self.operand_size() };
+                let (extension, operand) = self.read_mod_rm()?;
+                let operand = self.operand(operand, size);
+                let mnemonic = UNARY_MNEMONICS[(extension & 0x07) as usize];
+                if extension & 0x06 == 0 {
+                    return Some(format!("{} {}, {}", mnemonic, operand, self.read_immediate(immediate_size(size), size)?));
+                }
+                Some(format!("{} {}", mnemonic, operand))
+            },
+            0xFE | 0xFF => self.decode_group_five(opcode),
+            0x0F => self.decode_two_byte(),
+            _ => None
+        }
+    }
+
+    fn decode_alu(&mut self, opcode: u8) -> Option<String> {
+        let mnemonic = ALU_MNEMONICS[(opcode >> 3) as usize];
+        let size = if opcode & 0x01 == 0 { 1 } else { self.operand_size() };
+        match opcode & 0x07 {
+            0x00 | 0x01 => {
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("{} {}, {}", mnemonic, self.operand(operand, size), self.register_name(register, size)))
+            },
+            0x02 | 0x03 => {
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("{} {}, {}", mnemonic, self.register_name(register, size), self.operand(operand, size)))
+            },
+            _ => Some(format!("{} {}, {}", mnemonic, self.register_name(0, size), self.read_immediate(immediate_size(size), size)?))
+        }
+    }
+
+    fn decode_group_five(&mut self, opcode: u8) -> Option<String> {
+        let size = if opcode == 0xFE { 1 } else { self.operand_size() };
+        let (extension, operand) = self.read_mod_rm()?;
+        match (opcode, extension & 0x07) {
+            (_, 0) => Some(format!("inc {}", self.operand(operand, size))),
+            (_, 1) => Some(format!("dec {}", self.operand(operand, size))),
+            (0xFF, 2) => Some(format!("call {}", self.operand(operand, 8))),
+            (0xFF, 4) => Some(format!("jmp {}", self.operand(operand, 8))),
+            (0xFF, 6) => Some(format!("push {}", self.operand(operand, 8))),
+            _ => None
+        }
+    }
+
+    fn decode_two_byte(&mut self) -> Option<String> {
+        let opcode = self.read_u8()?;
+        match opcode {
+            0x05 => Some(string("syscall")),
+            0x0B => Some(string("ud2")),
+            0x1F => {
+                let (_, operand) = self.read_mod_rm()?;
+                Some(format!("nop {}", self.operand(operand, self.operand_size())))
+            },
+            0x40..=0x4F => {
+                let size = self.operand_size();
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!(
+                    "cmov{} {}, {}",
+                    CONDITION_SUFFIXES[(opcode & 0x0F) as usize],
+                    self.register_name(register, size),
+                    self.operand(operand, size)
+                ))
+            },
+            0x80..=0x8F => {
+                let target = self.read_relative(4)?;
+                Some(format!("j{} {}", CONDITION_SUFFIXES[(opcode & 0x0F) as usize], target))
+            },
+            0x90..=0x9F => {
+                let (_, operand) = self.read_mod_rm()?;
+                Some(format!("set{} {}", CONDITION_SUFFIXES[(opcode & 0x0F) as usize], self.operand(operand, 1)))
+            },
+            0xAF => {
+                let size = self.operand_size();
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("imul {}, {}", self.register_name(register, size), self.operand(operand, size)))
+            },
+            0xB6 | 0xB7 | 0xBE | 0xBF => {
+                let mnemonic = if opcode < 0xBE { "movzx" } else { "movsx" };
+                let source_size = if opcode & 0x01 == 0 { 1 } else { 2 };
+                let size = self.operand_size();
+                let (register, operand) = self.read_mod_rm()?;
+                Some(format!("{} {}, {}", mnemonic, self.register_name(register, size), self.operand(operand, source_size)))
+            },
+            _ => None
+        }
+    }
+
+    fn operand_size(&self) -> u8 {
+        if self.rex_bit(REX_W) {
+            8
+        } else if self.operand_size_override {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn rex_bit(&self, bit: u8) -> bool {
+        self.rex.is_some_and(|rex| rex & bit == bit)
+    }
+
+    fn extend(&self, register: u8, bit: u8) -> usize {
+        (register | if self.rex_bit(bit) { 0x08 } else { 0 }) as usize
+    }
+
+    fn register_name(&self, register: u8, size: u8) -> &'static str {
+        let register = register as usize;
+        match size {
+            1 if self.rex.is_none() => REGISTER_NAMES_8_LEGACY[register & 0x07],
+            1 => REGISTER_NAMES_8[register],
+            2 => REGISTER_NAMES_16[register],
+            4 => REGISTER_NAMES_32[register],
+            _ => REGISTER_NAMES_64[register]
+        }
+    }
+
+    fn operand(&self, operand: ModRmOperand, size: u8) -> String {
+        match operand {
+            ModRmOperand::Register(register) => string(self.register_name(register, size)),
+            ModRmOperand::Memory(memory) => format!("{} ptr {}", size_name(size), memory)
+        }
+    }
+
+    fn read_mod_rm(&mut self) -> Option<(u8, ModRmOperand)> {
+        let mod_rm = self.read_u8()?;
+        let mod_part = mod_rm >> 6;
+        let register = self.extend((mod_rm >> 3) & 0x07, REX_R) as u8;
+        let r_m_part = mod_rm & 0x07;
+
+        if mod_part == MOD_REGISTER_DIRECT {
+            return Some((register, ModRmOperand::Register(self.extend(r_m_part, REX_B) as u8)));
+        }
+
+        if mod_part == MOD_INDIRECT && r_m_part == 0x05 {
+            let displacement = self.read_displacement(4)?;
+            return Some((register, ModRmOperand::Memory(format!("[rip{}]", displacement))));
+        }
+
+        let mut parts = vec!();
+        let mut no_base = false;
+
+        if r_m_part == 0x04 {
+            let sib = self.read_u8()?;
+            let scale = 1 << (sib >> 6);
+            let index = self.extend((sib >> 3) & 0x07, REX_X);
+            let base = sib & 0x07;
+
+            if mod_part == MOD_INDIRECT && base == 0x05 {
+                no_base = true;
+            } else {
+                parts.push(string(REGISTER_NAMES_64[self.extend(base, REX_B)]));
+            }
+            if index != 0x04 {
+                parts.push(format!("{}*{}", REGISTER_NAMES_64[index], scale));
+            }
+        } else {
+            parts.push(string(REGISTER_NAMES_64[self.extend(r_m_part, REX_B)]));
+        }
+
+        let displacement = match (mod_part, no_base) {
+            (MOD_INDIRECT_DISP8, _) => self.read_displacement(1)?,
+            (MOD_INDIRECT_DISP32, _) | (_, true) => self.read_displacement(4)?,
+            _ => String::new()
+        };
+
+        if parts.is_empty() {
+            return Some((register, ModRmOperand::Memory(format!("[{}]", displacement.trim_start_matches(" + ")))));
+        }
+        Some((register, ModRmOperand::Memory(format!("[{}{}]", parts.join(" + "), displacement))))
+    }
+
+    fn read_displacement(&mut self, size: usize) -> Option<String> {
+        if let Some(symbol) = self.relocation_at(self.position) {
+            self.position += size;
+            return Some(format!(" + {}", symbol));
+        }
+        let value = self.read_signed(size)?;
+        Some(match value {
+            0 => String::new(),
+            value if value < 0 => format!(" - 0x{:x}", -value),
+            value => format!(" + 0x{:x}", value)
+        })
+    }
+
+    /// Reads an immediate and sign-extends it to the operand size, as the CPU does for imm8 and imm32 forms.
+    fn read_immediate(&mut self, size: u8, operand_size: u8) -> Option<String> {
+        if let Some(symbol) = self.relocation_at(self.position) {
+            self.position += size as usize;
+            return Some(symbol);
+        }
+        let value = self.read_signed(size as usize)? as u64;
+        let shift = 64 - operand_size as u32 * 8;
+        Some(format!("0x{:x}", value << shift >> shift))
+    }
+
+    fn read_relative(&mut self, size: usize) -> Option<String> {
+        if let Some(symbol) = self.relocation_at(self.position) {
+            self.position += size;
+            return Some(symbol);
+        }
+        let displacement = self.read_signed(size)?;
+        Some(format!("0x{:x}", self.position as i64 + displacement))
+    }
+
+    fn relocation_at(&self, position: usize) -> Option<String> {
+        let relocation = self.relocations
+            .iter()
+            .find(|relocation| get_relocation_offset(relocation) as usize == position)?;
+        let name = get_symbol(self.coff, get_relocation_symbol(relocation))
+            .and_then(|entry| get_symbol_name(self.coff, get_primary_symbol(entry)))
+            .unwrap_or_else(|| string("?"));
+        let addend = self.data.get(position..position + 4).map_or(0, |bytes| u32_from_bytes(bytes, 0) as i32);
+        Some(match addend {
+            0 => name,
+            addend if addend < 0 => format!("{} - 0x{:x}", name, -(addend as i64)),
+            addend => format!("{} + 0x{:x}", name, addend)
+        })
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn read_unsigned(&mut self, size: usize) -> Option<u64> {
+        let bytes = self.data.get(self.position..self.position + size)?;
+        self.position += size;
+        Some(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64))
+    }
+
+    fn read_signed(&mut self, size: usize) -> Option<i64> {
+        let value = self.read_unsigned(size)?;
+        let shift = 64 - size * 8;
+        Some(((value << shift) as i64) >> shift)
+    }
+}
+
+fn immediate_size(operand_size: u8) -> u8 {
+    cmp::min(operand_size, 4)
+}
+
+fn size_name(size: u8) -> &'static str {
+    match size {
+        1 => "byte",
+        2 => "word",
+        4 => "dword",
+        _ => "qword"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassembled(entries: Vec<u8>) -> Vec<String> {
+        let mut coff = create_coff();
+        add_entries_to_text_section(&mut coff, entries);
+        disassemble_text_section(&coff).into_iter().map(|instruction| instruction.text).collect()
+    }
+
+    #[test]
+    fn byte_immediates_are_sign_extended_to_the_operand_size() {
+        assert_eq!(disassembled(vec!(0x83, 0xC0, 0xFF)), ["add eax, 0xffffffff"]);
+        assert_eq!(disassembled(vec!(0x48, 0x83, 0xEC, 0xF8)), ["sub rsp, 0xfffffffffffffff8"]);
+        assert_eq!(disassembled(vec!(0x66, 0x83, 0xC0, 0x80)), ["add ax, 0xff80"]);
+        assert_eq!(disassembled(vec!(0x6A, 0xFF)), ["push 0xffffffffffffffff"]);
+        assert_eq!(disassembled(vec!(0x6B, 0xC1, 0xFE)), ["imul eax, ecx, 0xfffffffe"]);
+        assert_eq!(disassembled(vec!(0x83, 0xC0, 0x7F)), ["add eax, 0x7f"]);
+    }
+
+    #[test]
+    fn dword_immediates_are_sign_extended_to_qword_operands() {
+        assert_eq!(disassembled(vec!(0x48, 0x05, 0x00, 0x00, 0x00, 0x80)), ["add rax, 0xffffffff80000000"]);
+        assert_eq!(disassembled(vec!(0x48, 0xC7, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF)), ["mov rax, 0xffffffffffffffff"]);
+        assert_eq!(disassembled(vec!(0x68, 0x00, 0x00, 0x00, 0x80)), ["push 0xffffffff80000000"]);
+        assert_eq!(disassembled(vec!(0xB8, 0xFF, 0xFF, 0xFF, 0xFF)), ["mov eax, 0xffffffff"]);
+        assert_eq!(disassembled(vec!(0xC6, 0xC0, 0xFF)), ["mov al, 0xff"]);
+    }
+
+    fn relocated_text() -> Coff {
+        let mut coff = create_coff();
+        let puts = add_foreign_external_symbol(&mut coff, "puts");
+        let answer = add_absolute_external_symbol(&mut coff, "answer", 42);
+        add_string_to_data_section(&mut coff, "skip");
+        let data_section_number = get_or_add_data_section_number(&mut coff);
+        let data = get_or_add_section_symbol(&mut coff, data_section_number).unwrap();
+        add_text_section_external_function_symbol(&mut coff, "main", 0);
+        add_push_reg_op(&mut coff, REG_BP);
+        add_lea_rip_relative_to_reg_op(&mut coff, relocatable_value(data, 5), REG_CX);
+        add_call_relocatable_addr_op(&mut coff, relocatable_value(puts, 0));
+        add_text_section_static_function_symbol(&mut coff, "helper", 13);
+        add_mov_dword_relocatable_value_to_reg_op(&mut coff, relocatable_value(answer, 0), REG_AX);
+        add_pop_reg_op(&mut coff, REG_BP);
+        add_ret_op(&mut coff);
+        coff
+    }
+
+    #[test]
+    fn relocated_fields_are_shown_as_their_target_symbols() {
+        let coff = relocated_text();
+        let instructions: Vec<String> = disassemble_text_section(&coff).into_iter().map(|instruction| instruction.text).collect();
+        assert_eq!(instructions, ["push rbp", "lea rcx, [rip + .data + 0x5]", "call puts", "mov eax, answer", "pop rbp", "ret"]);
+    }
+
+    #[test]
+    fn listings_label_the_symbols_defined_in_the_text_section() {
+        let listing = text_section_listing(&relocated_text());
+        let expected = [
+            "main:",
+            "       0:  55                            push rbp",
+            "       1:  48 8d 0d 05 00 00 00          lea rcx, [rip + .data + 0x5]",
+            "       8:  e8 00 00 00 00                call puts",
+            "helper:",
+            "       d:  b8 00 00 00 00                mov eax, answer",
+            "      12:  5d                            pop rbp",
+            "      13:  c3                            ret",
+            ""
+        ];
+        assert_eq!(listing, expected.join("\n"));
+    }
+}
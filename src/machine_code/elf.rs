@@ -0,0 +1,692 @@
+use std::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use crate::machine_code::*;
+use crate::intemediate_representation::*;
+use crate::errors::*;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELF_IDENTIFICATION_SIZE: usize = 16;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ELFOSABI_NONE: u8 = 0;
+const ET_REL: u16 = 1;
+const EM_X86_64: u16 = 62;
+const ELF_HEADER_SIZE: usize = 64;
+const ELF_SECTION_HEADER_SIZE: usize = 64;
+const ELF_SYMBOL_SIZE: usize = 24;
+const ELF_RELOCATION_SIZE: usize = 24;
+const ELF_TABLE_ALIGNMENT: u64 = 8;
+const SYMBOL_TABLE_SECTION_NAME: &str = ".symtab";
+const STRING_TABLE_SECTION_NAME: &str = ".strtab";
+const SECTION_NAMES_SECTION_NAME: &str = ".shstrtab";
+const RELOCATION_SECTION_NAME_PREFIX: &str = ".rela";
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_INFO_LINK: u64 = 0x40;
+
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_ABS: u16 = 0xFFF1;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+pub const R_X86_64_64: u32 = 1;
+pub const R_X86_64_PC32: u32 = 2;
+pub const R_X86_64_PLT32: u32 = 4;
+pub const R_X86_64_32: u32 = 10;
+
+pub const ELF_READ_ONLY_DATA_SECTION_NAME: &str = ".rodata";
+pub const NON_EXECUTABLE_STACK_SECTION_NAME: &str = ".note.GNU-stack";
+
+pub struct Elf {
+    sections: Vec<ElfSection>,
+    symbols: Vec<ElfSymbol>
+}
+
+pub struct ElfSection {
+    name: String,
+    section_type: u32,
+    flags: u64,
+    alignment: u64,
+    data: Vec<u8>,
+    size_of_uninitialised_data: u64,
+    relocations: Vec<ElfRelocation>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ElfSymbolSection {
+    Undefined,
+    Absolute,
+    Section(usize)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfSymbol {
+    name: String,
+    binding: u8,
+    symbol_type: u8,
+    section: ElfSymbolSection,
+    value: u64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfRelocation {
+    offset: u64,
+    symbol: usize,
+    relocation_type: u32,
+    addend: i64
+}
+
+pub fn elf_symbol(name: &str, binding: u8, symbol_type: u8, section: ElfSymbolSection, value: u64) -> ElfSymbol {
+    ElfSymbol { name: string(name), binding, symbol_type, section, value }
+}
+
+pub fn create_elf() -> Elf {
+    Elf { sections: vec!(), symbols: vec!() }
+}
+
+pub fn add_elf_section(elf: &mut Elf, name: &str, section_type: u32, flags: u64, alignment: u64) -> usize {
+    elf.sections.push(ElfSection {
+        name: string(name),
+        section_type,
+        flags,
+        alignment,
+        data: vec!(),
+        size_of_uninitialised_data: 0,
+        relocations: vec!()
+    });
+    elf.sections.len() - 1
+}
+
+pub fn add_entries_to_elf_section(elf: &mut Elf, section: usize, entries: &[u8]) -> u64 {
+    let data = &mut elf.sections[section].data;
+    let offset = data.len() as u64;
+    data.extend_from_slice(entries);
+    offset
+}
+
+pub fn reserve_elf_uninitialised_data(elf: &mut Elf, section: usize, size: u64) {
+    elf.sections[section].size_of_uninitialised_data += size;
+}
+
+pub fn add_elf_symbol(elf: &mut Elf, symbol: ElfSymbol) -> usize {
+    elf.symbols.push(symbol);
+    elf.symbols.len() - 1
+}
+
+pub fn add_elf_relocation(elf: &mut Elf, section: usize, offset: u64, symbol: usize, relocation_type: u32, addend: i64) {
+    elf.sections[section].relocations.push(ElfRelocation { offset, symbol, relocation_type, addend });
+}
+
+pub fn get_elf_sections(elf: &Elf) -> &[ElfSection] {
+    &elf.sections
+}
+
+pub fn get_elf_section_name(section: &ElfSection) -> &str {
+    &section.name
+}
+
+pub fn get_elf_section_type(section: &ElfSection) -> u32 {
+    section.section_type
+}
+
+pub fn get_elf_section_flags(section: &ElfSection) -> u64 {
+    section.flags
+}
+
+pub fn get_elf_section_data(section: &ElfSection) -> &[u8] {
+    &section.data
+}
+
+pub fn get_elf_section_size(section: &ElfSection) -> u64 {
+    if section.section_type == SHT_NOBITS {
+        section.size_of_uninitialised_data
+    } else {
+        section.data.len() as u64
+    }
+}
+
+pub fn get_elf_section_relocations(section: &ElfSection) -> &[ElfRelocation] {
+    &section.relocations
+}
+
+pub fn get_elf_symbols(elf: &Elf) -> &[ElfSymbol] {
+    &elf.symbols
+}
+
+pub fn get_elf_symbol_name(symbol: &ElfSymbol) -> &str {
+    &symbol.name
+}
+
+pub fn get_elf_symbol_binding(symbol: &ElfSymbol) -> u8 {
+    symbol.binding
+}
+
+pub fn get_elf_symbol_type(symbol: &ElfSymbol) -> u8 {
+    symbol.symbol_type
+}
+
+pub fn get_elf_symbol_section(symbol: &ElfSymbol) -> ElfSymbolSection {
+    symbol.section
+}
+
+pub fn get_elf_symbol_value(symbol: &ElfSymbol) -> u64 {
+    symbol.value
+}
+
+pub fn get_elf_relocation_offset(relocation: &ElfRelocation) -> u64 {
+    relocation.offset
+}
+
+pub fn get_elf_relocation_symbol(relocation: &ElfRelocation) -> usize {
+    relocation.symbol
+}
+
+pub fn get_elf_relocation_type(relocation: &ElfRelocation) -> u32 {
+    relocation.relocation_type
+}
+
+pub fn get_elf_relocation_addend(relocation: &ElfRelocation) -> i64 {
+    relocation.addend
+}
+
+pub fn build_elf_object(ir: IntermediateRepresentation) -> Result<(String, Elf), CoffError> {
+    let mut coff = create_coff();
+    let object_name = build_machine_code_object_for_format(&mut coff, ir, ObjectFormat::Elf)?;
+    Ok((object_name, elf_from_coff(&coff)?))
+}
+
+fn get_elf_section_name_for_coff_section(section: &CoffSection) -> &str {
+    match get_section_name(section) {
+        READ_ONLY_DATA_SECTION_NAME => ELF_READ_ONLY_DATA_SECTION_NAME,
+        name => name
+    }
+}
+
+fn get_elf_section_flags_for_coff_section(section: &CoffSection) -> u64 {
+    let flags = get_section_flags(section);
+    let mut elf_flags = SHF_ALLOC;
+    if flags & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE) != 0 {
+        elf_flags |= SHF_EXECINSTR;
+    }
+    if flags & IMAGE_SCN_MEM_WRITE != 0 {
+        elf_flags |= SHF_WRITE;
+    }
+    elf_flags
+}
+
+/// Converts a COFF object into an ELF one: sections keep their names apart from `.rdata`, which becomes `.rodata`,
+/// and discardable sections such as CodeView debug info are left out. COFF relocations become `R_X86_64` ones
+/// with their addends taken out of the section data, so sections holding relocation types with no ELF
+/// counterpart, such as the image relative ones of Windows unwind info, cannot be converted.
+pub fn elf_from_coff(coff: &Coff) -> Result<Elf, CoffError> {
+    let mut elf = create_elf();
+
+    let mut section_indices = HashMap::new();
+    let mut section_symbols = HashMap::new();
+    for (section_index, section) in get_sections(coff).iter().enumerate() {
+        if is_discardable_section(section) {
+            continue;
+        }
+        let section_type = if is_uninitialised_data_section(section) { SHT_NOBITS } else { SHT_PROGBITS };
+        let elf_section = add_elf_section(
+            &mut elf,
+            get_elf_section_name_for_coff_section(section),
+            section_type,
+            get_elf_section_flags_for_coff_section(section),
+            get_section_alignment(section) as u64
+        );
+        if section_type == SHT_NOBITS {
+            reserve_elf_uninitialised_data(&mut elf, elf_section, get_section_size(section) as u64);
+        } else {
+            add_entries_to_elf_section(&mut elf, elf_section, get_section_data(section));
+        }
+        section_indices.insert(section_index as u16 + 1, elf_section);
+        if let Some(handle) = get_section_definition_symbol(section) {
+            section_symbols.insert(handle, elf_section);
+        }
+    }
+    add_elf_section(&mut elf, NON_EXECUTABLE_STACK_SECTION_NAME, SHT_PROGBITS, 0, 1);
+
+    let mut symbol_indices = HashMap::new();
+    for handle in get_symbol_handles(coff) {
+        let Some(entry) = get_symbol(coff, handle) else {
+            continue;
+        };
+        if let Some(symbol) = elf_symbol_for_coff_symbol(coff, entry, &section_indices, &section_symbols, handle) {
+            symbol_indices.insert(handle, add_elf_symbol(&mut elf, symbol));
+        }
+    }
+
+    for (section_index, section) in get_sections(coff).iter().enumerate() {
+        let Some(elf_section) = section_indices.get(&(section_index as u16 + 1)).copied() else {
+            continue;
+        };
+        for relocation in get_section_relocations(section) {
+            let symbol = symbol_indices
+                .get(&get_relocation_symbol(relocation))
+                .copied()
+                .ok_or(CoffError::UnknownSymbolHandle { section: string(get_section_name(section)) })?;
+            convert_relocation(&mut elf, elf_section, section, relocation, symbol)?;
+        }
+    }
+
+    Ok(elf)
+}
+
+fn elf_symbol_for_coff_symbol(
+    coff: &Coff,
+    entry: &CoffSymbolTableEntry,
+    section_indices: &HashMap<u16, usize>,
+    section_symbols: &HashMap<CoffSymbolHandle, usize>,
+    handle: CoffSymbolHandle
+) -> Option<ElfSymbol> {
+    let symbol = get_primary_symbol(entry);
+    let name = get_symbol_name(coff, symbol)?;
+    let value = get_symbol_value(symbol)? as u64;
+    let storage_class = get_symbol_storage_class(symbol)?;
+    let binding = if storage_class == IMAGE_SYM_CLASS_EXTERNAL { STB_GLOBAL } else { STB_LOCAL };
+
+    if storage_class == IMAGE_SYM_CLASS_FILE {
+        let file_name: String = get_auxillary_symbols(entry)
+            .iter()
+            .filter_map(|auxillary_symbol| match auxillary_symbol {
+                CoffSymbol::Name(name) => Some(string_from_padded_u8_array(&name.0)),
+                _ => None
+            })
+            .collect();
+        return Some(elf_symbol(&file_name, STB_LOCAL, STT_FILE, ElfSymbolSection::Absolute, 0));
+    }
+    if storage_class != IMAGE_SYM_CLASS_EXTERNAL && storage_class != IMAGE_SYM_CLASS_STATIC {
+        return None;
+    }
+
+    match get_symbol_section_number(symbol)? {
+        IMAGE_SYM_UNDEFINED => Some(elf_symbol(&name, binding, STT_NOTYPE, ElfSymbolSection::Undefined, 0)),
+        IMAGE_SYM_ABSOLUTE => Some(elf_symbol(&name, binding, STT_NOTYPE, ElfSymbolSection::Absolute, value)),
+        IMAGE_SYM_DEBUG => None,
+        section_number => {
+            let elf_section = *section_indices.get(&section_number)?;
+            if let Some(elf_section) = section_symbols.get(&handle) {
+                return Some(elf_symbol("", STB_LOCAL, STT_SECTION, ElfSymbolSection::Section(*elf_section), 0));
+            }
+            let symbol_type = if get_symbol_type(symbol) == Some(IMAGE_SYM_DTYPE_FUNCTION) {
+                STT_FUNC
+            } else if get_section_flags(get_section(coff, section_number).ok()?) & IMAGE_SCN_CNT_CODE == 0 {
+                STT_OBJECT
+            } else {
+                STT_NOTYPE
+            };
+            Some(elf_symbol(&name, binding, symbol_type, ElfSymbolSection::Section(elf_section), value))
+        }
+    }
+}
+
+fn convert_relocation(
+    elf: &mut Elf,
+    elf_section: usize,
+    section: &CoffSection,
+    relocation: &CoffRelocation,
+    symbol: usize
+) -> Result<(), CoffError> {
+    let offset = get_relocation_offset(relocation) as usize;
+    let relocation_type = get_relocation_type(relocation);
+    let data = get_section_data(section);
+    let out_of_range = || CoffError::RelocationOutOfRange { symbol: elf.symbols[symbol].name.clone(), relocation_type };
+
+    let (elf_relocation_type, addend, width) = match relocation_type {
+        IMAGE_REL_AMD64_ADDR64 => {
+            let field = data.get(offset..offset + 8).ok_or_else(out_of_range)?;
+            (R_X86_64_64, i64::from_le_bytes(field.try_into().unwrap()), 8)
+        },
+        IMAGE_REL_AMD64_ADDR32 => (R_X86_64_32, u32_from_bytes(data.get(offset..offset + 4).ok_or_else(out_of_range)?, 0) as i64, 4),
+        IMAGE_REL_AMD64_REL32..=IMAGE_REL_AMD64_REL32_5 => {
+            let displacement = u32_from_bytes(data.get(offset..offset + 4).ok_or_else(out_of_range)?, 0) as i32 as i64;
+            let end_of_field = 4 + (relocation_type - IMAGE_REL_AMD64_REL32) as i64;
+            let elf_relocation_type = if is_call_or_jump_displacement(data, offset as u32) { R_X86_64_PLT32 } else { R_X86_64_PC32 };
+            (elf_relocation_type, displacement - end_of_field, 4)
+        },
+        _ => return Err(CoffError::UnsupportedRelocation { section: string(get_section_name(section)), relocation_type })
+    };
+
+    elf.sections[elf_section].data[offset..offset + width].fill(0);
+    add_elf_relocation(elf, elf_section, offset as u64, symbol, elf_relocation_type, addend);
+    Ok(())
+}
+
+struct ElfStringTable {
+    entries: Vec<u8>
+}
+
+fn elf_string_table() -> ElfStringTable {
+    ElfStringTable { entries: vec!(0) }
+}
+
+impl ElfStringTable {
+    fn add(&mut self, name: &str) -> u32 {
+        if name.is_empty() {
+            return 0;
+        }
+        let offset = self.entries.len() as u32;
+        self.entries.extend(string_to_bytes_zero_terminated(name));
+        offset
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn elf_section_header(
+    name: u32,
+    section_type: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    alignment: u64,
+    entry_size: u64
+) -> Vec<u8> {
+    let mut entries = vec!();
+    entries.extend(name.to_le_bytes());
+    entries.extend(section_type.to_le_bytes());
+    entries.extend(flags.to_le_bytes());
+    entries.extend(0u64.to_le_bytes());
+    entries.extend(offset.to_le_bytes());
+    entries.extend(size.to_le_bytes());
+    entries.extend(link.to_le_bytes());
+    entries.extend(info.to_le_bytes());
+    entries.extend(alignment.to_le_bytes());
+    entries.extend(entry_size.to_le_bytes());
+    entries
+}
+
+fn align_file_offset(entries: &mut Vec<u8>, alignment: u64) -> u64 {
+    let alignment = alignment.max(1) as usize;
+    entries.resize(entries.len().div_ceil(alignment) * alignment, 0);
+    entries.len() as u64
+}
+
+/// Local symbols are written before global ones, as ELF requires.
+pub fn elf_to_bytes(elf: &Elf) -> Result<Vec<u8>, CoffError> {
+    let mut symbol_order: Vec<usize> = (0..elf.symbols.len()).collect();
+    symbol_order.sort_by_key(|index| elf.symbols[*index].binding != STB_LOCAL);
+    let mut symbol_indices = vec![0u32; elf.symbols.len()];
+    for (position, index) in symbol_order.iter().enumerate() {
+        symbol_indices[*index] = position as u32 + 1;
+    }
+    let first_global_symbol = symbol_order.iter().take_while(|index| elf.symbols[**index].binding == STB_LOCAL).count() as u32 + 1;
+
+    let relocated_sections: Vec<usize> = (0..elf.sections.len()).filter(|index| !elf.sections[*index].relocations.is_empty()).collect();
+    let symbol_table_index = (elf.sections.len() + relocated_sections.len() + 1) as u32;
+    let string_table_index = symbol_table_index + 1;
+    let section_names_index = string_table_index + 1;
+
+    let mut section_names = elf_string_table();
+    let mut section_headers = elf_section_header(0, SHT_NULL, 0, 0, 0, 0, 0, 0, 0);
+    let mut entries = vec![0; ELF_HEADER_SIZE];
+
+    for section in &elf.sections {
+        let offset = align_file_offset(&mut entries, section.alignment);
+        if section.section_type != SHT_NOBITS {
+            entries.extend_from_slice(&section.data);
+        }
+        section_headers.extend(elf_section_header(
+            section_names.add(&section.name),
+            section.section_type,
+            section.flags,
+            offset,
+            get_elf_section_size(section),
+            0,
+            0,
+            section.alignment,
+            0
+        ));
+    }
+
+    for section_index in &relocated_sections {
+        let section = &elf.sections[*section_index];
+        let offset = align_file_offset(&mut entries, ELF_TABLE_ALIGNMENT);
+        for relocation in &section.relocations {
+            let symbol_index = *symbol_indices
+                .get(relocation.symbol)
+                .ok_or(CoffError::UnknownSymbolHandle { section: section.name.clone() })?;
+            entries.extend(relocation.offset.to_le_bytes());
+            entries.extend(((symbol_index as u64) << 32 | relocation.relocation_type as u64).to_le_bytes());
+            entries.extend(relocation.addend.to_le_bytes());
+        }
+        section_headers.extend(elf_section_header(
+            section_names.add(&format!("{}{}", RELOCATION_SECTION_NAME_PREFIX, section.name)),
+            SHT_RELA,
+            SHF_INFO_LINK,
+            offset,
+            (section.relocations.len() * ELF_RELOCATION_SIZE) as u64,
+            symbol_table_index,
+            *section_index as u32 + 1,
+            ELF_TABLE_ALIGNMENT,
+            ELF_RELOCATION_SIZE as u64
+        ));
+    }
+
+    let mut symbol_names = elf_string_table();
+    let symbol_table_offset = align_file_offset(&mut entries, ELF_TABLE_ALIGNMENT);
+    entries.extend([0; ELF_SYMBOL_SIZE]);
+    for index in &symbol_order {
+        let symbol = &elf.symbols[*index];
+        let section_index = match symbol.section {
+            ElfSymbolSection::Undefined => SHN_UNDEF,
+            ElfSymbolSection::Absolute => SHN_ABS,
+            ElfSymbolSection::Section(section) => section as u16 + 1
+        };
+        entries.extend(symbol_names.add(&symbol.name).to_le_bytes());
+        entries.push(symbol.binding << 4 | symbol.symbol_type);
+        entries.push(0);
+        entries.extend(section_index.to_le_bytes());
+        entries.extend(symbol.value.to_le_bytes());
+        entries.extend(0u64.to_le_bytes());
+    }
+    section_headers.extend(elf_section_header(
+        section_names.add(SYMBOL_TABLE_SECTION_NAME),
+        SHT_SYMTAB,
+        0,
+        symbol_table_offset,
+        ((elf.symbols.len() + 1) * ELF_SYMBOL_SIZE) as u64,
+        string_table_index,
+        first_global_symbol,
+        ELF_TABLE_ALIGNMENT,
+        ELF_SYMBOL_SIZE as u64
+    ));
+
+    let string_table_offset = entries.len() as u64;
+    entries.extend_from_slice(&symbol_names.entries);
+    section_headers.extend(elf_section_header(
+        section_names.add(STRING_TABLE_SECTION_NAME),
+        SHT_STRTAB,
+        0,
+        string_table_offset,
+        symbol_names.entries.len() as u64,
+        0,
+        0,
+        1,
+        0
+    ));
+
+    let section_names_name = section_names.add(SECTION_NAMES_SECTION_NAME);
+    let section_names_offset = entries.len() as u64;
+    entries.extend_from_slice(&section_names.entries);
+    section_headers.extend(elf_section_header(
+        section_names_name,
+        SHT_STRTAB,
+        0,
+        section_names_offset,
+        section_names.entries.len() as u64,
+        0,
+        0,
+        1,
+        0
+    ));
+
+    let section_headers_offset = align_file_offset(&mut entries, ELF_TABLE_ALIGNMENT);
+    entries.extend(section_headers);
+    let number_of_sections = u16::try_from(section_names_index + 1).map_err(|_| CoffError::OffsetTooLarge { context: "ELF section count" })?;
+
+    let mut header = vec!();
+    header.extend_from_slice(ELF_MAGIC);
+    header.extend([ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE]);
+    header.resize(ELF_IDENTIFICATION_SIZE, 0);
+    header.extend(ET_REL.to_le_bytes());
+    header.extend(EM_X86_64.to_le_bytes());
+    header.extend((EV_CURRENT as u32).to_le_bytes());
+    header.extend(0u64.to_le_bytes());
+    header.extend(0u64.to_le_bytes());
+    header.extend(section_headers_offset.to_le_bytes());
+    header.extend(0u32.to_le_bytes());
+    header.extend((ELF_HEADER_SIZE as u16).to_le_bytes());
+    header.extend(0u16.to_le_bytes());
+    header.extend(0u16.to_le_bytes());
+    header.extend((ELF_SECTION_HEADER_SIZE as u16).to_le_bytes());
+    header.extend(number_of_sections.to_le_bytes());
+    header.extend((section_names_index as u16).to_le_bytes());
+    entries[..ELF_HEADER_SIZE].copy_from_slice(&header);
+
+    Ok(entries)
+}
+
+pub fn write_elf<W: Write>(elf: &Elf, writer: &mut W) -> Result<(), CoffError> {
+    let entries = elf_to_bytes(elf)?;
+    writer.write_all(&entries).map_err(|error| io_error("could not write object", error))?;
+    writer.flush().map_err(|error| io_error("could not write object", error))
+}
+
+pub fn write_elf_to_file(elf: &Elf, file: &mut File) -> Result<(), CoffError> {
+    write_elf(elf, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_calls_and_jumps_go_through_the_plt() {
+        let mut coff = create_coff();
+        let puts = add_foreign_external_symbol(&mut coff, "puts");
+        add_lea_rip_relative_to_reg_op(&mut coff, relocatable_value(puts, 0), REG_AX);
+        add_call_relocatable_addr_op(&mut coff, relocatable_value(puts, 0));
+
+        let elf = elf_from_coff(&coff).unwrap();
+        let text = get_elf_sections(&elf).iter().find(|section| get_elf_section_name(section) == ".text").unwrap();
+        let relocations: Vec<(u64, u32, i64)> = get_elf_section_relocations(text)
+            .iter()
+            .map(|relocation| (get_elf_relocation_offset(relocation), get_elf_relocation_type(relocation), get_elf_relocation_addend(relocation)))
+            .collect();
+        assert_eq!(relocations, [(3, R_X86_64_PC32, -4), (8, R_X86_64_PLT32, -4)]);
+    }
+
+    fn u64_from_bytes(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn zero_terminated_name(bytes: &[u8], offset: usize) -> String {
+        let length = bytes[offset..].iter().position(|byte| *byte == 0).unwrap();
+        String::from_utf8_lossy(&bytes[offset..offset + length]).into_owned()
+    }
+
+    fn hello_elf() -> Elf {
+        let mut elf = create_elf();
+        let text = add_elf_section(&mut elf, ".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 16);
+        let data = add_elf_section(&mut elf, ".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 8);
+        let bss = add_elf_section(&mut elf, ".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 4);
+        add_entries_to_elf_section(&mut elf, text, &[0xE8, 0, 0, 0, 0, 0xC3]);
+        add_entries_to_elf_section(&mut elf, data, &[0; 8]);
+        reserve_elf_uninitialised_data(&mut elf, bss, 16);
+
+        let puts = add_elf_symbol(&mut elf, elf_symbol("puts", STB_GLOBAL, STT_NOTYPE, ElfSymbolSection::Undefined, 0));
+        let main = add_elf_symbol(&mut elf, elf_symbol("main", STB_GLOBAL, STT_FUNC, ElfSymbolSection::Section(text), 0));
+        add_elf_symbol(&mut elf, elf_symbol("hello.hep", STB_LOCAL, STT_FILE, ElfSymbolSection::Absolute, 0));
+        add_elf_symbol(&mut elf, elf_symbol("", STB_LOCAL, STT_SECTION, ElfSymbolSection::Section(data), 0));
+
+        add_elf_relocation(&mut elf, text, 1, puts, R_X86_64_PLT32, -4);
+        add_elf_relocation(&mut elf, data, 0, main, R_X86_64_64, 2);
+        elf
+    }
+
+    #[test]
+    fn written_objects_link_relocations_and_symbols_by_section_index() {
+        let bytes = elf_to_bytes(&hello_elf()).unwrap();
+        assert_eq!(&bytes[..4], ELF_MAGIC);
+        assert_eq!(u16_from_bytes(&bytes, 16), ET_REL);
+        assert_eq!(u16_from_bytes(&bytes, 18), EM_X86_64);
+
+        let section_headers_offset = u64_from_bytes(&bytes, 40) as usize;
+        let number_of_sections = u16_from_bytes(&bytes, 60) as usize;
+        let section_header = |index: usize| section_headers_offset + index * ELF_SECTION_HEADER_SIZE;
+        let section_names_offset = u64_from_bytes(&bytes, section_header(u16_from_bytes(&bytes, 62) as usize) + 24) as usize;
+
+        let sections: Vec<(String, u32, u32, u32)> = (0..number_of_sections)
+            .map(|index| {
+                let header = section_header(index);
+                (
+                    zero_terminated_name(&bytes, section_names_offset + u32_from_bytes(&bytes, header) as usize),
+                    u32_from_bytes(&bytes, header + 4),
+                    u32_from_bytes(&bytes, header + 40),
+                    u32_from_bytes(&bytes, header + 44)
+                )
+            })
+            .collect();
+        assert_eq!(sections, [
+            (string(""), SHT_NULL, 0, 0),
+            (string(".text"), SHT_PROGBITS, 0, 0),
+            (string(".data"), SHT_PROGBITS, 0, 0),
+            (string(".bss"), SHT_NOBITS, 0, 0),
+            (string(".rela.text"), SHT_RELA, 6, 1),
+            (string(".rela.data"), SHT_RELA, 6, 2),
+            (string(".symtab"), SHT_SYMTAB, 7, 3),
+            (string(".strtab"), SHT_STRTAB, 0, 0),
+            (string(".shstrtab"), SHT_STRTAB, 0, 0)
+        ]);
+
+        let symbol_table_offset = u64_from_bytes(&bytes, section_header(6) + 24) as usize;
+        let string_table_offset = u64_from_bytes(&bytes, section_header(7) + 24) as usize;
+        let symbols: Vec<(String, u8, u16)> = (1..5)
+            .map(|index| {
+                let entry = symbol_table_offset + index * ELF_SYMBOL_SIZE;
+                (
+                    zero_terminated_name(&bytes, string_table_offset + u32_from_bytes(&bytes, entry) as usize),
+                    bytes[entry + 4],
+                    u16_from_bytes(&bytes, entry + 6)
+                )
+            })
+            .collect();
+        assert_eq!(symbols, [
+            (string("hello.hep"), STB_LOCAL << 4 | STT_FILE, SHN_ABS),
+            (string(""), STB_LOCAL << 4 | STT_SECTION, 2),
+            (string("puts"), STB_GLOBAL << 4 | STT_NOTYPE, SHN_UNDEF),
+            (string("main"), STB_GLOBAL << 4 | STT_FUNC, 1)
+        ]);
+
+        let relocations: Vec<(u64, u64, i64)> = [4, 5]
+            .iter()
+            .map(|index| {
+                let entry = u64_from_bytes(&bytes, section_header(*index) + 24) as usize;
+                (u64_from_bytes(&bytes, entry), u64_from_bytes(&bytes, entry + 8), u64_from_bytes(&bytes, entry + 16) as i64)
+            })
+            .collect();
+        assert_eq!(relocations, [(1, 3 << 32 | R_X86_64_PLT32 as u64, -4), (0, 4 << 32 | R_X86_64_64 as u64, 2)]);
+    }
+}
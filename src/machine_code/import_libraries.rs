@@ -0,0 +1,243 @@
+use std::path::Path;
+use crate::machine_code::*;
+use crate::errors::*;
+
+const IMPORT_OBJECT_HDR_SIG2: u16 = 0xFFFF;
+const IMPORT_OBJECT_VERSION: u16 = 0;
+const IMPORT_OBJECT_CODE: u16 = 0;
+const IMPORT_OBJECT_DATA: u16 = 1;
+const IMPORT_OBJECT_ORDINAL: u16 = 0;
+const IMPORT_OBJECT_NAME: u16 = 1;
+const IMPORT_OBJECT_NAME_TYPE_SHIFT: u16 = 2;
+const IMPORT_OBJECT_TYPE_MASK: u16 = 0x3;
+const IMPORT_OBJECT_NAME_TYPE_MASK: u16 = 0x7;
+const IMPORT_OBJECT_HEADER_SIZE: usize = 20;
+const IMPORT_DIRECTORY_LOOKUP_TABLE_OFFSET: u32 = 0;
+const IMPORT_DIRECTORY_NAME_OFFSET: u32 = 12;
+const IMPORT_DIRECTORY_ADDRESS_TABLE_OFFSET: u32 = 16;
+
+pub const IMPORT_DIRECTORY_ENTRY_SIZE: usize = 20;
+pub const IMPORT_THUNK_SIZE: usize = 8;
+pub const IMPORT_SYMBOL_PREFIX: &str = "__imp_";
+pub const IMPORT_DESCRIPTOR_SYMBOL_PREFIX: &str = "__IMPORT_DESCRIPTOR_";
+pub const NULL_IMPORT_DESCRIPTOR_SYMBOL_NAME: &str = "__NULL_IMPORT_DESCRIPTOR";
+pub const IMPORT_DIRECTORY_SECTION_NAME: &str = ".idata$2";
+pub const NULL_IMPORT_DIRECTORY_SECTION_NAME: &str = ".idata$3";
+pub const IMPORT_LOOKUP_TABLE_SECTION_NAME: &str = ".idata$4";
+pub const IMPORT_ADDRESS_TABLE_SECTION_NAME: &str = ".idata$5";
+pub const IMPORT_NAMES_SECTION_NAME: &str = ".idata$6";
+
+/// How the loader finds an export in the DLL: by name, starting its search at `hint` in the export name table, or by ordinal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DllImportBy {
+    Name { hint: u16 },
+    Ordinal(u16)
+}
+
+/// Functions are imported through both a call thunk named after the export and its `__imp_` pointer, variables only through the pointer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DllExport {
+    pub name: String,
+    pub import_by: DllImportBy,
+    pub data: bool
+}
+
+pub fn dll_function_export(name: &str) -> DllExport {
+    DllExport { name: string(name), import_by: DllImportBy::Name { hint: 0 }, data: false }
+}
+
+pub fn dll_function_export_by_ordinal(name: &str, ordinal: u16) -> DllExport {
+    DllExport { name: string(name), import_by: DllImportBy::Ordinal(ordinal), data: false }
+}
+
+pub fn dll_data_export(name: &str) -> DllExport {
+    DllExport { name: string(name), import_by: DllImportBy::Name { hint: 0 }, data: true }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DllImport {
+    pub dll_name: String,
+    pub export: DllExport
+}
+
+pub fn dll_import(dll_name: &str, export: DllExport) -> DllImport {
+    DllImport { dll_name: string(dll_name), export }
+}
+
+pub fn import_symbol_name(export_name: &str) -> String {
+    format!("{}{}", IMPORT_SYMBOL_PREFIX, export_name)
+}
+
+fn import_library_name(dll_name: &str) -> String {
+    Path::new(dll_name)
+        .file_stem()
+        .map_or_else(|| string(dll_name), |stem| stem.to_string_lossy().into_owned())
+}
+
+fn null_thunk_data_symbol_name(library_name: &str) -> String {
+    format!("\x7f{}_NULL_THUNK_DATA", library_name)
+}
+
+/// Builds an import library for `dll_name` in the format lib.exe produces: an import descriptor,
+/// the null import descriptor and null thunk that terminate the import tables, then a short import member per export.
+pub fn create_import_library(dll_name: &str, exports: &[DllExport]) -> Result<CoffArchive, CoffError> {
+    let library_name = import_library_name(dll_name);
+    let mut archive = create_coff_archive();
+
+    add_coff_to_archive(&mut archive, dll_name, &import_descriptor_coff(dll_name, &library_name)?)?;
+    add_coff_to_archive(&mut archive, dll_name, &null_import_descriptor_coff()?)?;
+    add_coff_to_archive(&mut archive, dll_name, &null_thunk_data_coff(&library_name)?)?;
+
+    for export in exports {
+        let symbols = if export.data {
+            vec!(import_symbol_name(&export.name))
+        } else {
+            vec!(import_symbol_name(&export.name), export.name.clone())
+        };
+        add_archive_member(&mut archive, dll_name, short_import_entries(dll_name, export), symbols)?;
+    }
+
+    Ok(archive)
+}
+
+/// Encodes an IMPORT_OBJECT_HEADER followed by the export and DLL names, from which the linker synthesizes
+/// the import table entries and, for functions, the call thunk.
+pub fn short_import_entries(dll_name: &str, export: &DllExport) -> Vec<u8> {
+    let (ordinal_or_hint, name_type) = match export.import_by {
+        DllImportBy::Name { hint } => (hint, IMPORT_OBJECT_NAME),
+        DllImportBy::Ordinal(ordinal) => (ordinal, IMPORT_OBJECT_ORDINAL)
+    };
+    let import_type = if export.data { IMPORT_OBJECT_DATA } else { IMPORT_OBJECT_CODE };
+
+    let mut names = string_to_bytes_zero_terminated(&export.name);
+    names.extend(string_to_bytes_zero_terminated(dll_name));
+
+    let mut entries = vec!();
+    entries.extend(IMAGE_FILE_MACHINE_UNKNOWN.to_le_bytes());
+    entries.extend(IMPORT_OBJECT_HDR_SIG2.to_le_bytes());
+    entries.extend(IMPORT_OBJECT_VERSION.to_le_bytes());
+    entries.extend(IMAGE_FILE_MACHINE_AMD64.to_le_bytes());
+    entries.extend(0u32.to_le_bytes());
+    entries.extend((names.len() as u32).to_le_bytes());
+    entries.extend(ordinal_or_hint.to_le_bytes());
+    entries.extend((import_type | name_type << IMPORT_OBJECT_NAME_TYPE_SHIFT).to_le_bytes());
+    entries.extend(names);
+    entries
+}
+
+/// Import library members are stamped with zero, as their archive member headers are, so libraries are reproducible.
+fn import_library_coff() -> Coff {
+    let mut coff = create_coff_without_sections();
+    set_timestamp(&mut coff, fixed_timestamp(0));
+    coff
+}
+
+fn add_import_data_section(coff: &mut Coff, name: &str, alignment: u32) -> Result<u16, CoffError> {
+    let section_number = add_section(coff, name, IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE, alignment)?;
+    get_or_add_section_symbol(coff, section_number)?;
+    Ok(section_number)
+}
+
+fn import_descriptor_coff(dll_name: &str, library_name: &str) -> Result<Coff, CoffError> {
+    let mut coff = import_library_coff();
+    let directory_section_number = add_import_data_section(&mut coff, IMPORT_DIRECTORY_SECTION_NAME, 4)?;
+    let names_section_number = add_import_data_section(&mut coff, IMPORT_NAMES_SECTION_NAME, 2)?;
+
+    add_section_external_symbol(&mut coff, directory_section_number, &format!("{}{}", IMPORT_DESCRIPTOR_SYMBOL_PREFIX, library_name), 0);
+    let names_symbol = get_or_add_section_symbol(&mut coff, names_section_number)?;
+    let lookup_table_symbol = add_section_reference_symbol(&mut coff, IMPORT_LOOKUP_TABLE_SECTION_NAME);
+    let address_table_symbol = add_section_reference_symbol(&mut coff, IMPORT_ADDRESS_TABLE_SECTION_NAME);
+    add_foreign_external_symbol(&mut coff, NULL_IMPORT_DESCRIPTOR_SYMBOL_NAME);
+    add_foreign_external_symbol(&mut coff, &null_thunk_data_symbol_name(library_name));
+
+    add_entries_to_section(&mut coff, directory_section_number, vec![0; IMPORT_DIRECTORY_ENTRY_SIZE])?;
+    for (offset, symbol) in [
+        (IMPORT_DIRECTORY_LOOKUP_TABLE_OFFSET, lookup_table_symbol),
+        (IMPORT_DIRECTORY_NAME_OFFSET, names_symbol),
+        (IMPORT_DIRECTORY_ADDRESS_TABLE_OFFSET, address_table_symbol)
+    ] {
+        add_relocation_at_offset(&mut coff, directory_section_number, offset, symbol, IMAGE_REL_AMD64_ADDR32NB)?;
+    }
+
+    let mut name = string_to_bytes_zero_terminated(dll_name);
+    if name.len() % 2 == 1 {
+        name.push(0);
+    }
+    add_entries_to_section(&mut coff, names_section_number, name)?;
+    Ok(coff)
+}
+
+fn null_import_descriptor_coff() -> Result<Coff, CoffError> {
+    let mut coff = import_library_coff();
+    let section_number = add_import_data_section(&mut coff, NULL_IMPORT_DIRECTORY_SECTION_NAME, 4)?;
+    add_section_external_symbol(&mut coff, section_number, NULL_IMPORT_DESCRIPTOR_SYMBOL_NAME, 0);
+    add_entries_to_section(&mut coff, section_number, vec![0; IMPORT_DIRECTORY_ENTRY_SIZE])?;
+    Ok(coff)
+}
+
+fn null_thunk_data_coff(library_name: &str) -> Result<Coff, CoffError> {
+    let mut coff = import_library_coff();
+    let address_table_section_number = add_import_data_section(&mut coff, IMPORT_ADDRESS_TABLE_SECTION_NAME, 8)?;
+    let lookup_table_section_number = add_import_data_section(&mut coff, IMPORT_LOOKUP_TABLE_SECTION_NAME, 8)?;
+    add_section_external_symbol(&mut coff, address_table_section_number, &null_thunk_data_symbol_name(library_name), 0);
+    add_entries_to_section(&mut coff, address_table_section_number, vec![0; IMPORT_THUNK_SIZE])?;
+    add_entries_to_section(&mut coff, lookup_table_section_number, vec![0; IMPORT_THUNK_SIZE])?;
+    Ok(coff)
+}
+
+pub fn read_short_import(entries: &[u8]) -> Option<DllImport> {
+    if entries.len() < IMPORT_OBJECT_HEADER_SIZE 
+        || u16_from_bytes(entries, 0) != IMAGE_FILE_MACHINE_UNKNOWN 
+        || u16_from_bytes(entries, 2) != IMPORT_OBJECT_HDR_SIG2 {
+        return None;
+    }
+
+    let ordinal_or_hint = u16_from_bytes(entries, 16);
+    let import_type = u16_from_bytes(entries, 18) & IMPORT_OBJECT_TYPE_MASK;
+    let name_type = (u16_from_bytes(entries, 18) >> IMPORT_OBJECT_NAME_TYPE_SHIFT) & IMPORT_OBJECT_NAME_TYPE_MASK;
+    let mut names = entries[IMPORT_OBJECT_HEADER_SIZE..]
+        .split(|byte| *byte == 0)
+        .map(|name| String::from_utf8_lossy(name).into_owned());
+    let name = names.next()?;
+    let dll_name = names.next()?;
+
+    let import_by = if name_type == IMPORT_OBJECT_ORDINAL {
+        DllImportBy::Ordinal(ordinal_or_hint)
+    } else {
+        DllImportBy::Name { hint: ordinal_or_hint }
+    };
+    Some(DllImport { dll_name, export: DllExport { name, import_by, data: import_type == IMPORT_OBJECT_DATA } })
+}
+
+pub fn get_import_library_imports(archive: &CoffArchive) -> Vec<DllImport> {
+    get_archive_members(archive)
+        .iter()
+        .filter_map(|member| read_short_import(get_archive_member_data(member)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_library_members_have_a_zero_timestamp() {
+        let archive = create_import_library("kernel32.dll", &[dll_function_export("GetStdHandle"), dll_data_export("_environ")]).unwrap();
+        let members = get_archive_members(&archive);
+        assert_eq!(members.len(), 5);
+
+        for member in members {
+            let data = get_archive_member_data(member);
+            let timestamp_offset = if read_short_import(data).is_some() { 8 } else { 4 };
+            assert_eq!(u32_from_bytes(data, timestamp_offset), 0);
+        }
+    }
+
+    #[test]
+    fn short_import_members_read_back() {
+        let exports = [dll_function_export("GetStdHandle"), dll_function_export_by_ordinal("WriteFile", 7), dll_data_export("_environ")];
+        let archive = create_import_library("kernel32.dll", &exports).unwrap();
+        let imports: Vec<DllImport> = exports.iter().map(|export| dll_import("kernel32.dll", export.clone())).collect();
+        assert_eq!(get_import_library_imports(&archive), imports);
+    }
+}
@@ -0,0 +1,786 @@
+use std::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use crate::machine_code::*;
+use crate::errors::*;
+
+const DOS_SIGNATURE: &[u8] = b"MZ";
+const DOS_HEADER_SIZE: usize = 64;
+const DOS_NEW_HEADER_POINTER_OFFSET: usize = 0x3C;
+const PE_SIGNATURE: &[u8] = b"PE\0\0";
+const OPTIONAL_HEADER_SIZE: usize = 240;
+const IMAGE_NT_OPTIONAL_HDR64_MAGIC: u16 = 0x20B;
+const IMAGE_FILE_RELOCS_STRIPPED: u16 = 0x0001;
+const IMAGE_FILE_EXECUTABLE_IMAGE: u16 = 0x0002;
+const IMAGE_FILE_LARGE_ADDRESS_AWARE: u16 = 0x0020;
+const IMAGE_DLLCHARACTERISTICS_NX_COMPAT: u16 = 0x0100;
+const IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE: u16 = 0x8000;
+const IMAGE_NUMBEROF_DIRECTORY_ENTRIES: usize = 16;
+const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+const IMAGE_DIRECTORY_ENTRY_EXCEPTION: usize = 3;
+const IMAGE_DIRECTORY_ENTRY_IAT: usize = 12;
+const IMAGE_ORDINAL_FLAG64: u64 = 0x8000000000000000;
+const LINKER_VERSION: (u8, u8) = (0, 1);
+const OPERATING_SYSTEM_VERSION: (u16, u16) = (6, 0);
+const SIZE_OF_STACK_RESERVE: u64 = 0x100000;
+const SIZE_OF_STACK_COMMIT: u64 = 0x1000;
+const SIZE_OF_HEAP_RESERVE: u64 = 0x100000;
+const SIZE_OF_HEAP_COMMIT: u64 = 0x1000;
+const SECTION_GROUP_SEPARATOR: char = '$';
+const CODE_PADDING: u8 = 0xCC;
+const IMPORT_THUNK: [u8; 8] = [0xFF, 0x25, 0x00, 0x00, 0x00, 0x00, 0xCC, 0xCC];
+const IMPORT_THUNK_DISPLACEMENT_OFFSET: u32 = 2;
+const IMPORT_THUNK_INSTRUCTION_SIZE: u32 = 6;
+const IMPORT_SECTION_NAME: &str = ".idata";
+
+pub const IMAGE_SUBSYSTEM_WINDOWS_GUI: u16 = 2;
+pub const IMAGE_SUBSYSTEM_WINDOWS_CUI: u16 = 3;
+pub const DEFAULT_IMAGE_BASE: u64 = 0x0000000140000000;
+pub const IMAGE_SECTION_ALIGNMENT: u32 = 0x1000;
+pub const IMAGE_FILE_ALIGNMENT: u32 = 0x200;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkerOptions {
+    pub entry_point: String,
+    pub image_base: u64,
+    pub subsystem: u16,
+    pub timestamp: CoffTimestamp
+}
+
+pub fn console_executable_options(entry_point: &str) -> LinkerOptions {
+    LinkerOptions {
+        entry_point: string(entry_point),
+        image_base: DEFAULT_IMAGE_BASE,
+        subsystem: IMAGE_SUBSYSTEM_WINDOWS_CUI,
+        timestamp: current_timestamp()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum ContributionOrigin {
+    ObjectSection { object: usize, section_number: u16 },
+    ImportThunks,
+    ImportTables
+}
+
+struct Contribution<'a> {
+    origin: ContributionOrigin,
+    name: &'a str,
+    flags: u32,
+    alignment: u32,
+    data: Option<Cow<'a, [u8]>>,
+    size: u32
+}
+
+struct ImageSection {
+    name: String,
+    flags: u32,
+    data: Vec<u8>,
+    virtual_size: u32,
+    virtual_address: u32
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Placement {
+    image_section: usize,
+    offset: u32
+}
+
+struct ImageLayout {
+    sections: Vec<ImageSection>,
+    placements: HashMap<ContributionOrigin, Placement>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LinkedAddress {
+    Relative(u32),
+    Absolute(u32)
+}
+
+struct ImportedDll<'a> {
+    name: &'a str,
+    imports: Vec<&'a DllImport>
+}
+
+struct ImportTables {
+    entries: Vec<u8>,
+    directory_size: u32,
+    address_table_offset: u32,
+    address_table_size: u32,
+    address_entry_offsets: Vec<u32>
+}
+
+pub fn link_executable(objects: &[&Coff], imports: &[DllImport], options: &LinkerOptions) -> Result<Vec<u8>, CoffError> {
+    let defined_names = get_defined_names(objects)?;
+    let referenced_imports = get_referenced_imports(objects, &defined_names, imports)?;
+    let imported_dlls = group_imports_by_dll(&referenced_imports);
+    let thunked_imports: Vec<&DllImport> = referenced_imports.iter().copied().filter(|import| !import.export.data).collect();
+
+    let import_tables_size = import_tables(&imported_dlls, 0).entries.len() as u32;
+    let mut layout = layout_image_sections(objects, thunked_imports.len() as u32, import_tables_size)?;
+    let size_of_headers = get_size_of_headers(layout.sections.len());
+    assign_virtual_addresses(&mut layout, size_of_headers)?;
+
+    let mut directories = [(0, 0); IMAGE_NUMBEROF_DIRECTORY_ENTRIES];
+    let mut imported_addresses = HashMap::new();
+    if let Some(placement) = layout.placements.get(&ContributionOrigin::ImportTables).copied() {
+        let tables_rva = get_placement_rva(&layout, placement);
+        let tables = import_tables(&imported_dlls, tables_rva);
+        directories[IMAGE_DIRECTORY_ENTRY_IMPORT] = (tables_rva, tables.directory_size);
+        directories[IMAGE_DIRECTORY_ENTRY_IAT] = (tables_rva + tables.address_table_offset, tables.address_table_size);
+
+        let imports_in_table_order = imported_dlls.iter().flat_map(|dll| dll.imports.iter());
+        for (import, address_entry_offset) in imports_in_table_order.zip(&tables.address_entry_offsets) {
+            imported_addresses.insert(import_symbol_name(&import.export.name), LinkedAddress::Relative(tables_rva + address_entry_offset));
+        }
+        patch_placement(&mut layout, placement, 0, &tables.entries);
+    }
+
+    if let Some(placement) = layout.placements.get(&ContributionOrigin::ImportThunks).copied() {
+        let thunks_rva = get_placement_rva(&layout, placement);
+        for (index, import) in thunked_imports.iter().enumerate() {
+            let thunk_offset = index as u32 * IMPORT_THUNK.len() as u32;
+            let LinkedAddress::Relative(address_entry_rva) = imported_addresses[&import_symbol_name(&import.export.name)] else {
+                unreachable!("import address entries are always image relative")
+            };
+            let displacement = address_entry_rva as i64 - (thunks_rva + thunk_offset + IMPORT_THUNK_INSTRUCTION_SIZE) as i64;
+            patch_placement(&mut layout, placement, thunk_offset + IMPORT_THUNK_DISPLACEMENT_OFFSET, &(displacement as i32).to_le_bytes());
+            imported_addresses.insert(import.export.name.clone(), LinkedAddress::Relative(thunks_rva + thunk_offset));
+        }
+    }
+
+    let mut globals = get_global_addresses(objects, &layout)?;
+    for (name, address) in imported_addresses {
+        globals.entry(name).or_insert(address);
+    }
+
+    apply_relocations(objects, &mut layout, &globals, options.image_base)?;
+
+    let entry_point = match globals.get(&options.entry_point) {
+        Some(LinkedAddress::Relative(rva)) => *rva,
+        _ => return Err(CoffError::EntryPointNotDefined(options.entry_point.clone()))
+    };
+    if let Some(section) = layout.sections.iter().find(|section| section.name == EXCEPTION_DATA_SECTION_NAME) {
+        directories[IMAGE_DIRECTORY_ENTRY_EXCEPTION] = (section.virtual_address, section.virtual_size);
+    }
+
+    image_entries(&layout, options, size_of_headers, entry_point, &directories)
+}
+
+pub fn write_executable_to_file(image: &[u8], file: &mut File) -> Result<(), CoffError> {
+    file.write_all(image).map_err(|error| io_error("could not write executable", error))?;
+    file.flush().map_err(|error| io_error("could not write executable", error))
+}
+
+fn is_defined_external(symbol: &CoffSymbol) -> bool {
+    get_symbol_storage_class(symbol) == Some(IMAGE_SYM_CLASS_EXTERNAL)
+        && !matches!(get_symbol_section_number(symbol), Some(IMAGE_SYM_UNDEFINED) | Some(IMAGE_SYM_DEBUG) | None)
+}
+
+fn is_undefined_external(symbol: &CoffSymbol) -> bool {
+    get_symbol_storage_class(symbol) == Some(IMAGE_SYM_CLASS_EXTERNAL) && get_symbol_section_number(symbol) == Some(IMAGE_SYM_UNDEFINED)
+}
+
+fn get_defined_names(objects: &[&Coff]) -> Result<HashSet<String>, CoffError> {
+    let mut defined_names = HashSet::new();
+    for coff in objects {
+        for name in get_defined_external_symbol_names(coff) {
+            if !defined_names.insert(name.clone()) {
+                return Err(CoffError::SymbolDefinedTwice(name));
+            }
+        }
+    }
+    Ok(defined_names)
+}
+
+fn get_referenced_imports<'a>(
+    objects: &[&Coff],
+    defined_names: &HashSet<String>,
+    imports: &'a [DllImport]
+) -> Result<Vec<&'a DllImport>, CoffError> {
+    let mut referenced = vec![false; imports.len()];
+
+    for coff in objects {
+        for entry in get_symbols(coff) {
+            let symbol = get_primary_symbol(entry);
+            if !is_undefined_external(symbol) {
+                continue;
+            }
+            let name = get_symbol_name(coff, symbol).unwrap_or_default();
+            if defined_names.contains(&name) {
+                continue;
+            }
+            let import_index = imports
+                .iter()
+                .position(|import| import_symbol_name(&import.export.name) == name || (!import.export.data && import.export.name == name))
+                .ok_or(CoffError::SymbolNotDefined(name))?;
+            referenced[import_index] = true;
+        }
+    }
+
+    Ok(imports.iter().zip(referenced).filter(|(_, referenced)| *referenced).map(|(import, _)| import).collect())
+}
+
+fn group_imports_by_dll<'a>(imports: &[&'a DllImport]) -> Vec<ImportedDll<'a>> {
+    let mut dlls: Vec<ImportedDll> = vec!();
+    for import in imports {
+        match dlls.iter_mut().find(|dll| dll.name.eq_ignore_ascii_case(&import.dll_name)) {
+            Some(dll) => dll.imports.push(import),
+            None => dlls.push(ImportedDll { name: &import.dll_name, imports: vec!(import) })
+        }
+    }
+    dlls
+}
+
+/// Lays out the import directory, then each DLL's lookup table, then all the address tables together
+/// so they form the one IAT, followed by the hint/name entries and DLL names the tables refer to.
+fn import_tables(dlls: &[ImportedDll], tables_rva: u32) -> ImportTables {
+    let number_of_imports: usize = dlls.iter().map(|dll| dll.imports.len()).sum();
+    let directory_size = (dlls.len() + 1) * IMPORT_DIRECTORY_ENTRY_SIZE;
+    let tables_size = (number_of_imports + dlls.len()) * IMPORT_THUNK_SIZE;
+    let lookup_table_offset = directory_size;
+    let address_table_offset = lookup_table_offset + tables_size;
+    let names_offset = address_table_offset + tables_size;
+
+    let mut names = vec!();
+    let mut table_entries = vec!();
+    for dll in dlls {
+        let mut dll_table_entries = vec!();
+        for import in &dll.imports {
+            let entry = match import.export.import_by {
+                DllImportBy::Ordinal(ordinal) => IMAGE_ORDINAL_FLAG64 | ordinal as u64,
+                DllImportBy::Name { hint } => {
+                    let hint_name_rva = tables_rva as u64 + (names_offset + names.len()) as u64;
+                    names.extend(hint.to_le_bytes());
+                    add_even_padded_name(&mut names, &import.export.name);
+                    hint_name_rva
+                }
+            };
+            dll_table_entries.push(entry);
+        }
+        table_entries.push(dll_table_entries);
+    }
+
+    let mut dll_name_offsets = vec!();
+    for dll in dlls {
+        dll_name_offsets.push(names_offset + names.len());
+        add_even_padded_name(&mut names, dll.name);
+    }
+
+    let mut entries = vec!();
+    let mut table_offset = 0;
+    for (dll_table_entries, dll_name_offset) in table_entries.iter().zip(&dll_name_offsets) {
+        entries.extend((tables_rva + (lookup_table_offset + table_offset) as u32).to_le_bytes());
+        entries.extend(0u32.to_le_bytes());
+        entries.extend(0u32.to_le_bytes());
+        entries.extend((tables_rva + *dll_name_offset as u32).to_le_bytes());
+        entries.extend((tables_rva + (address_table_offset + table_offset) as u32).to_le_bytes());
+        table_offset += (dll_table_entries.len() + 1) * IMPORT_THUNK_SIZE;
+    }
+    entries.resize(directory_size, 0);
+
+    let mut address_entry_offsets = vec!();
+    for table_offset in [lookup_table_offset, address_table_offset] {
+        for dll_table_entries in &table_entries {
+            for entry in dll_table_entries {
+                if table_offset == address_table_offset {
+                    address_entry_offsets.push(entries.len() as u32);
+                }
+                entries.extend(entry.to_le_bytes());
+            }
+            entries.extend(0u64.to_le_bytes());
+        }
+    }
+    entries.extend(names);
+
+    ImportTables {
+        entries,
+        directory_size: directory_size as u32,
+        address_table_offset: address_table_offset as u32,
+        address_table_size: tables_size as u32,
+        address_entry_offsets
+    }
+}
+
+fn add_even_padded_name(names: &mut Vec<u8>, name: &str) {
+    names.extend(string_to_bytes_zero_terminated(name));
+    if names.len() % 2 == 1 {
+        names.push(0);
+    }
+}
+
+fn get_image_section_name(name: &str) -> &str {
+    name.split(SECTION_GROUP_SEPARATOR).next().unwrap_or(name)
+}
+
+/// Orders code first, then initialised data and then uninitialised data, as a section's content determines its protection.
+fn get_image_section_class(flags: u32) -> u8 {
+    if flags & IMAGE_SCN_CNT_CODE != 0 {
+        0
+    } else if flags & IMAGE_SCN_CNT_INITIALISED_DATA == 0 && flags & IMAGE_SCN_CNT_UNINITIALISED_DATA != 0 {
+        2
+    } else {
+        1
+    }
+}
+
+fn get_contributions<'a>(objects: &[&'a Coff], number_of_thunks: u32, import_tables_size: u32) -> Vec<Contribution<'a>> {
+    let mut contributions = vec!();
+
+    for (object, coff) in objects.iter().enumerate() {
+        for (section_index, section) in get_sections(coff).iter().enumerate() {
+            if is_discardable_section(section) || get_section_size(section) == 0 {
+                continue;
+            }
+            contributions.push(Contribution {
+                origin: ContributionOrigin::ObjectSection { object, section_number: section_index as u16 + 1 },
+                name: get_section_name(section),
+                flags: get_section_flags(section) & !IMAGE_SCN_ALIGN_MASK,
+                alignment: get_section_alignment(section),
+                data: if is_uninitialised_data_section(section) { None } else { Some(Cow::Borrowed(get_section_data(section))) },
+                size: get_section_size(section)
+            });
+        }
+    }
+
+    if number_of_thunks > 0 {
+        let thunks: Vec<u8> = (0..number_of_thunks).flat_map(|_| IMPORT_THUNK).collect();
+        contributions.push(Contribution {
+            origin: ContributionOrigin::ImportThunks,
+            name: TEXT_SECTION_NAME,
+            flags: IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+            alignment: IMPORT_THUNK.len() as u32,
+            size: thunks.len() as u32,
+            data: Some(Cow::Owned(thunks))
+        });
+    }
+
+    if import_tables_size > 0 {
+        contributions.push(Contribution {
+            origin: ContributionOrigin::ImportTables,
+            name: IMPORT_SECTION_NAME,
+            flags: IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+            alignment: IMPORT_THUNK_SIZE as u32,
+            data: Some(Cow::Owned(vec![0; import_tables_size as usize])),
+            size: import_tables_size
+        });
+    }
+
+    contributions
+}
+
+/// Merges contributions into image sections by the name before any `$`, in order of first appearance within
+/// each section class, placing grouped contributions in the order of their full names.
+fn layout_image_sections(objects: &[&Coff], number_of_thunks: u32, import_tables_size: u32) -> Result<ImageLayout, CoffError> {
+    let mut contributions = get_contributions(objects, number_of_thunks, import_tables_size);
+    let mut section_names: Vec<&str> = vec!();
+    for contribution in &contributions {
+        if !section_names.contains(&get_image_section_name(contribution.name)) {
+            section_names.push(get_image_section_name(contribution.name));
+        }
+    }
+    contributions.sort_by(|left, right| get_image_section_name(left.name).cmp(get_image_section_name(right.name)).then(left.name.cmp(right.name)));
+
+    let mut sections: Vec<ImageSection> = section_names
+        .iter()
+        .map(|name| ImageSection { name: string(name), flags: 0, data: vec!(), virtual_size: 0, virtual_address: 0 })
+        .collect();
+    for contribution in &contributions {
+        let section = sections.iter_mut().find(|section| section.name == get_image_section_name(contribution.name)).unwrap();
+        section.flags |= contribution.flags;
+    }
+    sections.sort_by_key(|section| get_image_section_class(section.flags));
+
+    let mut placements = HashMap::new();
+    for contribution in contributions {
+        let image_section = sections.iter().position(|section| section.name == get_image_section_name(contribution.name)).unwrap();
+        let section = &mut sections[image_section];
+        let offset = align_up(section.virtual_size, contribution.alignment);
+        let padding = if section.flags & IMAGE_SCN_CNT_CODE != 0 { CODE_PADDING } else { 0 };
+
+        if let Some(data) = contribution.data {
+            section.data.resize(offset as usize, padding);
+            section.data.extend_from_slice(&data);
+        }
+        section.virtual_size = offset
+            .checked_add(contribution.size)
+            .ok_or(CoffError::SectionTooLarge { section: section.name.clone(), size: offset as usize + contribution.size as usize })?;
+        placements.insert(contribution.origin, Placement { image_section, offset });
+    }
+
+    Ok(ImageLayout { sections, placements })
+}
+
+fn get_size_of_headers(number_of_sections: usize) -> u32 {
+    let size = DOS_HEADER_SIZE + PE_SIGNATURE.len() + COFF_HEADER_SIZE + OPTIONAL_HEADER_SIZE + number_of_sections * COFF_SECTION_HEADER_SIZE;
+    align_up(size as u32, IMAGE_FILE_ALIGNMENT)
+}
+
+fn assign_virtual_addresses(layout: &mut ImageLayout, size_of_headers: u32) -> Result<(), CoffError> {
+    let mut virtual_address = align_up(size_of_headers, IMAGE_SECTION_ALIGNMENT);
+    for section in &mut layout.sections {
+        section.virtual_address = virtual_address;
+        virtual_address = virtual_address
+            .checked_add(align_up(section.virtual_size, IMAGE_SECTION_ALIGNMENT))
+            .ok_or(CoffError::OffsetTooLarge { context: "image size" })?;
+    }
+    Ok(())
+}
+
+fn get_placement_rva(layout: &ImageLayout, placement: Placement) -> u32 {
+    layout.sections[placement.image_section].virtual_address + placement.offset
+}
+
+fn patch_placement(layout: &mut ImageLayout, placement: Placement, offset: u32, entries: &[u8]) {
+    let start = (placement.offset + offset) as usize;
+    layout.sections[placement.image_section].data[start..start + entries.len()].copy_from_slice(entries);
+}
+
+fn get_symbol_address(layout: &ImageLayout, object: usize, symbol: &CoffSymbol) -> Option<LinkedAddress> {
+    let value = get_symbol_value(symbol)?;
+    match get_symbol_section_number(symbol)? {
+        IMAGE_SYM_ABSOLUTE => Some(LinkedAddress::Absolute(value)),
+        IMAGE_SYM_UNDEFINED | IMAGE_SYM_DEBUG => None,
+        section_number => layout.placements
+            .get(&ContributionOrigin::ObjectSection { object, section_number })
+            .map(|placement| LinkedAddress::Relative(get_placement_rva(layout, *placement) + value))
+    }
+}
+
+fn get_global_addresses(objects: &[&Coff], layout: &ImageLayout) -> Result<HashMap<String, LinkedAddress>, CoffError> {
+    let mut globals = HashMap::new();
+    for (object, coff) in objects.iter().enumerate() {
+        for entry in get_symbols(coff) {
+            let symbol = get_primary_symbol(entry);
+            if !is_defined_external(symbol) {
+                continue;
+            }
+            let name = get_symbol_name(coff, symbol).unwrap_or_default();
+            let address = get_symbol_address(layout, object, symbol).ok_or(CoffError::SymbolNotDefined(name.clone()))?;
+            globals.insert(name, address);
+        }
+    }
+    Ok(globals)
+}
+
+fn get_relocation_target(
+    coff: &Coff,
+    object: usize,
+    relocation: &CoffRelocation,
+    layout: &ImageLayout,
+    globals: &HashMap<String, LinkedAddress>,
+    section_name: &str
+) -> Result<(String, LinkedAddress), CoffError> {
+    let entry = get_symbol(coff, get_relocation_symbol(relocation)).ok_or(CoffError::UnknownSymbolHandle { section: string(section_name) })?;
+    let symbol = get_primary_symbol(entry);
+    let name = get_symbol_name(coff, symbol).unwrap_or_default();
+    let address = if is_undefined_external(symbol) {
+        globals.get(&name).copied()
+    } else {
+        get_symbol_address(layout, object, symbol)
+    };
+    match address {
+        Some(address) => Ok((name, address)),
+        None => Err(CoffError::SymbolNotDefined(name))
+    }
+}
+
+fn apply_relocations(
+    objects: &[&Coff],
+    layout: &mut ImageLayout,
+    globals: &HashMap<String, LinkedAddress>,
+    image_base: u64
+) -> Result<(), CoffError> {
+    for (object, coff) in objects.iter().enumerate() {
+        for (section_index, section) in get_sections(coff).iter().enumerate() {
+            let origin = ContributionOrigin::ObjectSection { object, section_number: section_index as u16 + 1 };
+            let Some(placement) = layout.placements.get(&origin).copied() else {
+                continue;
+            };
+
+            for relocation in get_section_relocations(section) {
+                let (name, target) = get_relocation_target(coff, object, relocation, layout, globals, get_section_name(section))?;
+                let target_section = match target {
+                    LinkedAddress::Relative(rva) => layout.sections
+                        .iter()
+                        .position(|section| rva >= section.virtual_address && rva <= section.virtual_address + section.virtual_size)
+                        .map(|index| (index as u16 + 1, rva - layout.sections[index].virtual_address)),
+                    LinkedAddress::Absolute(_) => None
+                };
+                let site_offset = placement.offset + get_relocation_offset(relocation);
+                let site_rva = get_placement_rva(layout, placement) + get_relocation_offset(relocation);
+                let relocation_type = get_relocation_type(relocation);
+                let out_of_range = || CoffError::RelocationOutOfRange { symbol: name.clone(), relocation_type };
+
+                let data = &mut layout.sections[placement.image_section].data;
+                let target_address = match target {
+                    LinkedAddress::Relative(rva) => image_base + rva as u64,
+                    LinkedAddress::Absolute(value) => value as u64
+                };
+
+                match relocation_type {
+                    IMAGE_REL_AMD64_ADDR64 => {
+                        let addend = read_relocation_field::<8>(data, site_offset).ok_or_else(out_of_range)?;
+                        let value = target_address.wrapping_add(u64::from_le_bytes(addend));
+                        write_relocation_field(data, site_offset, &value.to_le_bytes());
+                    },
+                    IMAGE_REL_AMD64_ADDR32 => {
+                        let addend = read_relocation_field::<4>(data, site_offset).ok_or_else(out_of_range)?;
+                        let value = u32::try_from(target_address + u32::from_le_bytes(addend) as u64).map_err(|_| out_of_range())?;
+                        write_relocation_field(data, site_offset, &value.to_le_bytes());
+                    },
+                    IMAGE_REL_AMD64_ADDR32NB => {
+                        let addend = read_relocation_field::<4>(data, site_offset).ok_or_else(out_of_range)?;
+                        let LinkedAddress::Relative(rva) = target else {
+                            return Err(out_of_range());
+                        };
+                        write_relocation_field(data, site_offset, &rva.wrapping_add(u32::from_le_bytes(addend)).to_le_bytes());
+                    },
+                    IMAGE_REL_AMD64_REL32..=IMAGE_REL_AMD64_REL32_5 => {
+                        let addend = read_relocation_field::<4>(data, site_offset).ok_or_else(out_of_range)?;
+                        let end_of_field = image_base + site_rva as u64 + 4 + (relocation_type - IMAGE_REL_AMD64_REL32) as u64;
+                        let displacement = target_address as i64 + i32::from_le_bytes(addend) as i64 - end_of_field as i64;
+                        let displacement = i32::try_from(displacement).map_err(|_| out_of_range())?;
+                        write_relocation_field(data, site_offset, &displacement.to_le_bytes());
+                    },
+                    IMAGE_REL_AMD64_SECTION => {
+                        let (section_number, _) = target_section.ok_or_else(out_of_range)?;
+                        read_relocation_field::<2>(data, site_offset).ok_or_else(out_of_range)?;
+                        write_relocation_field(data, site_offset, &section_number.to_le_bytes());
+                    },
+                    IMAGE_REL_AMD64_SECREL => {
+                        let (_, section_offset) = target_section.ok_or_else(out_of_range)?;
+                        let addend = read_relocation_field::<4>(data, site_offset).ok_or_else(out_of_range)?;
+                        write_relocation_field(data, site_offset, &section_offset.wrapping_add(u32::from_le_bytes(addend)).to_le_bytes());
+                    },
+                    _ => return Err(CoffError::UnsupportedRelocation { section: string(get_section_name(section)), relocation_type })
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_relocation_field<const N: usize>(data: &[u8], offset: u32) -> Option<[u8; N]> {
+    data.get(offset as usize..offset as usize + N)?.try_into().ok()
+}
+
+fn write_relocation_field(data: &mut [u8], offset: u32, entries: &[u8]) {
+    data[offset as usize..offset as usize + entries.len()].copy_from_slice(entries);
+}
+
+/// Sections with only uninitialised data take no space in the file.
+fn image_entries(
+    layout: &ImageLayout,
+    options: &LinkerOptions,
+    size_of_headers: u32,
+    entry_point: u32,
+    directories: &[(u32, u32); IMAGE_NUMBEROF_DIRECTORY_ENTRIES]
+) -> Result<Vec<u8>, CoffError> {
+    let mut section_headers = vec!();
+    let mut pointer_to_section = size_of_headers;
+    let (mut size_of_code, mut size_of_initialised_data, mut size_of_uninitialised_data) = (0u32, 0u32, 0u32);
+    for section in &layout.sections {
+        let size_of_raw_data = align_up(section.data.len() as u32, IMAGE_FILE_ALIGNMENT);
+        let pointer = if size_of_raw_data == 0 { 0 } else { pointer_to_section };
+        section_headers.push(section_header(
+            get_8_padded_u8_array_from_string(&section.name)?,
+            section.virtual_size,
+            section.virtual_address,
+            size_of_raw_data,
+            pointer,
+            0,
+            0,
+            0,
+            0,
+            section.flags
+        ));
+        pointer_to_section = pointer_to_section
+            .checked_add(size_of_raw_data)
+            .ok_or(CoffError::OffsetTooLarge { context: "image file" })?;
+
+        match get_image_section_class(section.flags) {
+            0 => size_of_code += size_of_raw_data,
+            1 => size_of_initialised_data += size_of_raw_data,
+            _ => size_of_uninitialised_data += align_up(section.virtual_size, IMAGE_FILE_ALIGNMENT)
+        }
+    }
+
+    let size_of_image = layout.sections
+        .last()
+        .map_or(align_up(size_of_headers, IMAGE_SECTION_ALIGNMENT), |section| section.virtual_address + align_up(section.virtual_size, IMAGE_SECTION_ALIGNMENT));
+    let base_of_code = layout.sections
+        .iter()
+        .find(|section| get_image_section_class(section.flags) == 0)
+        .map_or(0, |section| section.virtual_address);
+
+    let mut entries = vec![0; DOS_HEADER_SIZE];
+    entries[..DOS_SIGNATURE.len()].copy_from_slice(DOS_SIGNATURE);
+    entries[DOS_NEW_HEADER_POINTER_OFFSET..DOS_NEW_HEADER_POINTER_OFFSET + 4].copy_from_slice(&(DOS_HEADER_SIZE as u32).to_le_bytes());
+    entries.extend_from_slice(PE_SIGNATURE);
+    entries.extend(encode_header(&header(
+        IMAGE_FILE_MACHINE_AMD64,
+        layout.sections.len() as u16,
+        resolve_timestamp(options.timestamp)?,
+        0,
+        0,
+        OPTIONAL_HEADER_SIZE as u16,
+        IMAGE_FILE_RELOCS_STRIPPED | IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE
+    )));
+
+    entries.extend(IMAGE_NT_OPTIONAL_HDR64_MAGIC.to_le_bytes());
+    entries.extend([LINKER_VERSION.0, LINKER_VERSION.1]);
+    for value in [size_of_code, size_of_initialised_data, size_of_uninitialised_data, entry_point, base_of_code] {
+        entries.extend(value.to_le_bytes());
+    }
+    entries.extend(options.image_base.to_le_bytes());
+    entries.extend(IMAGE_SECTION_ALIGNMENT.to_le_bytes());
+    entries.extend(IMAGE_FILE_ALIGNMENT.to_le_bytes());
+    for version in [OPERATING_SYSTEM_VERSION, (0, 0), OPERATING_SYSTEM_VERSION] {
+        entries.extend(version.0.to_le_bytes());
+        entries.extend(version.1.to_le_bytes());
+    }
+    for value in [0, size_of_image, size_of_headers, 0u32] {
+        entries.extend(value.to_le_bytes());
+    }
+    entries.extend(options.subsystem.to_le_bytes());
+    entries.extend((IMAGE_DLLCHARACTERISTICS_NX_COMPAT | IMAGE_DLLCHARACTERISTICS_TERMINAL_SERVER_AWARE).to_le_bytes());
+    for size in [SIZE_OF_STACK_RESERVE, SIZE_OF_STACK_COMMIT, SIZE_OF_HEAP_RESERVE, SIZE_OF_HEAP_COMMIT] {
+        entries.extend(size.to_le_bytes());
+    }
+    entries.extend(0u32.to_le_bytes());
+    entries.extend((IMAGE_NUMBEROF_DIRECTORY_ENTRIES as u32).to_le_bytes());
+    for (virtual_address, size) in directories {
+        entries.extend(virtual_address.to_le_bytes());
+        entries.extend(size.to_le_bytes());
+    }
+
+    for section_header in &section_headers {
+        entries.extend(encode_section_header(section_header));
+    }
+    entries.resize(size_of_headers as usize, 0);
+
+    for section in &layout.sections {
+        entries.extend_from_slice(&section.data);
+        entries.resize(align_up(entries.len() as u32, IMAGE_FILE_ALIGNMENT) as usize, 0);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello_objects() -> Vec<Coff> {
+        let mut pointers = create_coff_without_sections();
+        let section_number = add_section(&mut pointers, ".rdata", IMAGE_SCN_CNT_INITIALISED_DATA | IMAGE_SCN_MEM_READ, 8).unwrap();
+        let main = add_foreign_external_symbol(&mut pointers, "main");
+        add_entries_to_section(&mut pointers, section_number, 8u64.to_le_bytes().to_vec()).unwrap();
+        add_relocation_at_offset(&mut pointers, section_number, 0, main, IMAGE_REL_AMD64_ADDR64).unwrap();
+
+        vec!(
+            read_coff_from_bytes(include_bytes!("../../hello-main.obj")).unwrap(),
+            read_coff_from_bytes(include_bytes!("../../hello-print.obj")).unwrap(),
+            read_coff_from_bytes(include_bytes!("../../hello-STD_OUTPUT_HANDLE.obj")).unwrap(),
+            pointers
+        )
+    }
+
+    fn link_hello() -> Vec<u8> {
+        let objects = hello_objects();
+        let objects: Vec<&Coff> = objects.iter().collect();
+        let imports = [
+            dll_import("kernel32.dll", dll_function_export("GetStdHandle")),
+            dll_import("kernel32.dll", dll_function_export("WriteFile")),
+            dll_import("kernel32.dll", dll_function_export("ExitProcess"))
+        ];
+        let options = LinkerOptions { timestamp: fixed_timestamp(0), ..console_executable_options("main") };
+        link_executable(&objects, &imports, &options).unwrap()
+    }
+
+    const OPTIONAL_HEADER_OFFSET: usize = DOS_HEADER_SIZE + 4 + COFF_HEADER_SIZE;
+    const DATA_DIRECTORIES_OFFSET: usize = OPTIONAL_HEADER_OFFSET + 112;
+    const SECTION_HEADERS_OFFSET: usize = OPTIONAL_HEADER_OFFSET + OPTIONAL_HEADER_SIZE;
+
+    fn data_directory(image: &[u8], index: usize) -> (u32, u32) {
+        let offset = DATA_DIRECTORIES_OFFSET + index * 8;
+        (u32_from_bytes(image, offset), u32_from_bytes(image, offset + 4))
+    }
+
+    fn section_headers(image: &[u8]) -> Vec<(String, u32, u32, u32, u32)> {
+        (0..u16_from_bytes(image, DOS_HEADER_SIZE + 6) as usize)
+            .map(|index| {
+                let header = &image[SECTION_HEADERS_OFFSET + index * COFF_SECTION_HEADER_SIZE..];
+                let name = String::from_utf8_lossy(&header[..8]).trim_end_matches('\0').to_string();
+                (name, u32_from_bytes(header, 8), u32_from_bytes(header, 12), u32_from_bytes(header, 16), u32_from_bytes(header, 20))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hello_objects_link_into_an_image() {
+        let image = link_hello();
+
+        assert_eq!(&image[..2], DOS_SIGNATURE);
+        assert_eq!(&image[DOS_HEADER_SIZE..DOS_HEADER_SIZE + 4], PE_SIGNATURE);
+        assert_eq!(u32_from_bytes(&image, OPTIONAL_HEADER_OFFSET + 16), 0x1000, "entry point is main at the start of .text");
+        assert_eq!(u32_from_bytes(&image, OPTIONAL_HEADER_OFFSET + 56), 0x5000, "size of image");
+        assert_eq!(u32_from_bytes(&image, OPTIONAL_HEADER_OFFSET + 60), 0x200, "size of headers");
+
+        assert_eq!(section_headers(&image), [
+            (string(".text"), 0x90, 0x1000, 0x200, 0x200),
+            (string(".data"), 0x0F, 0x2000, 0x200, 0x400),
+            (string(".rdata"), 0x08, 0x3000, 0x200, 0x600),
+            (string(".idata"), 0x82, 0x4000, 0x200, 0x800)
+        ]);
+        assert_eq!(image.len(), 0xA00);
+    }
+
+    #[test]
+    fn referenced_imports_get_a_directory_and_address_table() {
+        let image = link_hello();
+
+        assert_eq!(data_directory(&image, IMAGE_DIRECTORY_ENTRY_IMPORT), (0x4000, 2 * IMPORT_DIRECTORY_ENTRY_SIZE as u32));
+        assert_eq!(data_directory(&image, IMAGE_DIRECTORY_ENTRY_IAT), (0x4040, 3 * IMPORT_THUNK_SIZE as u32));
+
+        let directory = &image[0x800..];
+        assert_eq!(u32_from_bytes(directory, 0), 0x4028, "lookup table");
+        assert_eq!(u32_from_bytes(directory, 16), 0x4040, "address table");
+        let name = u32_from_bytes(directory, 12) as usize - 0x4000;
+        assert_eq!(&directory[name..name + 13], b"kernel32.dll\0");
+        assert_eq!(&directory[IMPORT_DIRECTORY_ENTRY_SIZE..2 * IMPORT_DIRECTORY_ENTRY_SIZE], [0; IMPORT_DIRECTORY_ENTRY_SIZE]);
+
+        let get_std_handle = u32_from_bytes(directory, 0x40) as usize - 0x4000;
+        assert_eq!(&directory[get_std_handle + 2..get_std_handle + 15], b"GetStdHandle\0");
+    }
+
+    #[test]
+    fn thunks_and_relocations_are_patched() {
+        let image = link_hello();
+        let text = &image[0x200..0x290];
+
+        for (thunk, address_entry) in [(0x80, 0x4040), (0x88, 0x4048)] {
+            assert_eq!(text[thunk..thunk + 2], [0xFF, 0x25]);
+            assert_eq!(u32_from_bytes(text, thunk + 2) as i32, address_entry - (0x1000 + thunk as i32 + 6));
+        }
+
+        assert_eq!(u32_from_bytes(text, 0x15) as i32, 0x30 - 0x19, "main calls print");
+        assert_eq!(u32_from_bytes(text, 0x0B) as i32, 0x2000 - 0x100F, "main loads its message from .data");
+        assert_eq!(u32_from_bytes(text, 0x30 + 0x19) as i32, 0x80 - (0x30 + 0x1D), "print calls the GetStdHandle thunk");
+        assert_eq!(u32_from_bytes(text, 0x30 + 0x14), 0xFFFFFFF5, "STD_OUTPUT_HANDLE is absolute");
+
+        let pointer = u32_from_bytes(&image, 0x600) as u64 | (u32_from_bytes(&image, 0x604) as u64) << 32;
+        assert_eq!(pointer, DEFAULT_IMAGE_BASE + 0x1000 + 8, "the ADDR64 site holds main plus its addend");
+    }
+
+    #[test]
+    fn unresolved_symbols_are_reported() {
+        let objects = hello_objects();
+        let objects: Vec<&Coff> = objects.iter().collect();
+        let imports = [dll_import("kernel32.dll", dll_function_export("GetStdHandle"))];
+        let result = link_executable(&objects, &imports, &console_executable_options("main"));
+        assert!(matches!(result, Err(CoffError::SymbolNotDefined(name)) if name == "WriteFile"));
+    }
+}
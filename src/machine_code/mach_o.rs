@@ -0,0 +1,843 @@
+use std::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use crate::machine_code::*;
+use crate::intemediate_representation::*;
+use crate::errors::*;
+
+const MH_MAGIC_64: u32 = 0xFEEDFACF;
+const CPU_TYPE_X86_64: u32 = 0x01000007;
+const CPU_SUBTYPE_X86_64_ALL: u32 = 3;
+const MH_OBJECT: u32 = 1;
+const MACH_HEADER_SIZE: usize = 32;
+const NUMBER_OF_LOAD_COMMANDS: u32 = 4;
+const LC_SYMTAB: u32 = 0x2;
+const LC_DYSYMTAB: u32 = 0xB;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_BUILD_VERSION: u32 = 0x32;
+const SEGMENT_COMMAND_SIZE: usize = 72;
+const SECTION_SIZE: usize = 80;
+const SYMTAB_COMMAND_SIZE: usize = 24;
+const DYSYMTAB_COMMAND_SIZE: usize = 80;
+const BUILD_VERSION_COMMAND_SIZE: usize = 24;
+const RELOCATION_INFO_SIZE: usize = 8;
+const VM_PROT_ALL: u32 = 0x7;
+const PLATFORM_MACOS: u32 = 1;
+const MINIMUM_MACOS_VERSION: u32 = 0x000A0F00;
+const NAME_FIELD_SIZE: usize = 16;
+const SYMBOL_TABLE_ALIGNMENT: usize = 8;
+const SYMBOL_NAME_PREFIX: &str = "_";
+const RELOCATION_FIELD_SIZE: u8 = 2;
+
+pub const SEG_TEXT: &str = "__TEXT";
+pub const SEG_DATA: &str = "__DATA";
+pub const SECT_TEXT: &str = "__text";
+pub const SECT_DATA: &str = "__data";
+pub const SECT_CONST: &str = "__const";
+pub const SECT_BSS: &str = "__bss";
+
+pub const SECTION_TYPE: u32 = 0xFF;
+pub const S_REGULAR: u32 = 0x0;
+pub const S_ZEROFILL: u32 = 0x1;
+pub const S_ATTR_PURE_INSTRUCTIONS: u32 = 0x80000000;
+pub const S_ATTR_SOME_INSTRUCTIONS: u32 = 0x00000400;
+
+pub const N_EXT: u8 = 0x01;
+pub const N_UNDF: u8 = 0x0;
+pub const N_ABS: u8 = 0x2;
+pub const N_SECT: u8 = 0xE;
+pub const NO_SECT: u8 = 0;
+
+pub const X86_64_RELOC_UNSIGNED: u8 = 0;
+pub const X86_64_RELOC_SIGNED: u8 = 1;
+pub const X86_64_RELOC_BRANCH: u8 = 2;
+pub const X86_64_RELOC_SIGNED_1: u8 = 6;
+pub const X86_64_RELOC_SIGNED_2: u8 = 7;
+pub const X86_64_RELOC_SIGNED_4: u8 = 8;
+
+pub struct MachO {
+    sections: Vec<MachOSection>,
+    symbols: Vec<MachOSymbol>
+}
+
+pub struct MachOSection {
+    segment_name: String,
+    section_name: String,
+    flags: u32,
+    alignment: u32,
+    data: Vec<u8>,
+    size_of_zero_fill: u64,
+    relocations: Vec<MachORelocation>
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MachOSymbolSection {
+    Undefined,
+    Absolute,
+    Section(usize)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachOSymbol {
+    name: String,
+    external: bool,
+    section: MachOSymbolSection,
+    value: u64
+}
+
+/// What a relocation refers to: a symbol, written as an external relocation, or the start of a section,
+/// written as a local relocation against the section ordinal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MachORelocationTarget {
+    Symbol(usize),
+    Section(usize)
+}
+
+/// A relocation of a 32 bit field, or a 64 bit one for `X86_64_RELOC_UNSIGNED` with `length` 3,
+/// to the address of its target plus `addend`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachORelocation {
+    offset: u32,
+    target: MachORelocationTarget,
+    relocation_type: u8,
+    length: u8,
+    addend: i64
+}
+
+pub fn mach_o_symbol(name: &str, external: bool, section: MachOSymbolSection, value: u64) -> MachOSymbol {
+    MachOSymbol { name: string(name), external, section, value }
+}
+
+pub fn mach_o_relocation(offset: u32, target: MachORelocationTarget, relocation_type: u8, length: u8, addend: i64) -> MachORelocation {
+    MachORelocation { offset, target, relocation_type, length, addend }
+}
+
+pub fn create_mach_o() -> MachO {
+    MachO { sections: vec!(), symbols: vec!() }
+}
+
+pub fn add_mach_o_section(mach_o: &mut MachO, segment_name: &str, section_name: &str, flags: u32, alignment: u32) -> usize {
+    mach_o.sections.push(MachOSection {
+        segment_name: string(segment_name),
+        section_name: string(section_name),
+        flags,
+        alignment,
+        data: vec!(),
+        size_of_zero_fill: 0,
+        relocations: vec!()
+    });
+    mach_o.sections.len() - 1
+}
+
+pub fn add_entries_to_mach_o_section(mach_o: &mut MachO, section: usize, entries: &[u8]) -> u64 {
+    let data = &mut mach_o.sections[section].data;
+    let offset = data.len() as u64;
+    data.extend_from_slice(entries);
+    offset
+}
+
+pub fn reserve_mach_o_zero_fill(mach_o: &mut MachO, section: usize, size: u64) {
+    mach_o.sections[section].size_of_zero_fill += size;
+}
+
+pub fn add_mach_o_symbol(mach_o: &mut MachO, symbol: MachOSymbol) -> usize {
+    mach_o.symbols.push(symbol);
+    mach_o.symbols.len() - 1
+}
+
+pub fn add_mach_o_relocation(mach_o: &mut MachO, section: usize, relocation: MachORelocation) {
+    mach_o.sections[section].relocations.push(relocation);
+}
+
+pub fn get_mach_o_sections(mach_o: &MachO) -> &[MachOSection] {
+    &mach_o.sections
+}
+
+pub fn get_mach_o_segment_name(section: &MachOSection) -> &str {
+    &section.segment_name
+}
+
+pub fn get_mach_o_section_name(section: &MachOSection) -> &str {
+    &section.section_name
+}
+
+pub fn get_mach_o_section_flags(section: &MachOSection) -> u32 {
+    section.flags
+}
+
+pub fn get_mach_o_section_data(section: &MachOSection) -> &[u8] {
+    &section.data
+}
+
+pub fn is_zero_fill_section(section: &MachOSection) -> bool {
+    section.flags & SECTION_TYPE == S_ZEROFILL
+}
+
+pub fn get_mach_o_section_size(section: &MachOSection) -> u64 {
+    if is_zero_fill_section(section) {
+        section.size_of_zero_fill
+    } else {
+        section.data.len() as u64
+    }
+}
+
+pub fn get_mach_o_section_relocations(section: &MachOSection) -> &[MachORelocation] {
+    &section.relocations
+}
+
+pub fn get_mach_o_symbols(mach_o: &MachO) -> &[MachOSymbol] {
+    &mach_o.symbols
+}
+
+pub fn get_mach_o_symbol_name(symbol: &MachOSymbol) -> &str {
+    &symbol.name
+}
+
+pub fn is_external_mach_o_symbol(symbol: &MachOSymbol) -> bool {
+    symbol.external
+}
+
+pub fn get_mach_o_symbol_section(symbol: &MachOSymbol) -> MachOSymbolSection {
+    symbol.section
+}
+
+pub fn get_mach_o_symbol_value(symbol: &MachOSymbol) -> u64 {
+    symbol.value
+}
+
+pub fn get_mach_o_relocation_offset(relocation: &MachORelocation) -> u32 {
+    relocation.offset
+}
+
+pub fn get_mach_o_relocation_target(relocation: &MachORelocation) -> MachORelocationTarget {
+    relocation.target
+}
+
+pub fn get_mach_o_relocation_type(relocation: &MachORelocation) -> u8 {
+    relocation.relocation_type
+}
+
+pub fn get_mach_o_relocation_addend(relocation: &MachORelocation) -> i64 {
+    relocation.addend
+}
+
+pub fn build_mach_o_object(ir: IntermediateRepresentation) -> Result<(String, MachO), CoffError> {
+    let mut coff = create_coff();
+    let object_name = build_machine_code_object_for_format(&mut coff, ir, ObjectFormat::MachO)?;
+    Ok((object_name, mach_o_from_coff(&coff)?))
+}
+
+/// Places the usual COFF sections in their Mach-O counterparts and others in `__TEXT` or `__DATA`
+/// by whether they are writable, named after the COFF section with its leading `.` turned into `__`.
+fn get_mach_o_names_for_coff_section(section: &CoffSection) -> (&'static str, String) {
+    match get_section_name(section) {
+        TEXT_SECTION_NAME => (SEG_TEXT, string(SECT_TEXT)),
+        DATA_SECTION_NAME => (SEG_DATA, string(SECT_DATA)),
+        READ_ONLY_DATA_SECTION_NAME => (SEG_TEXT, string(SECT_CONST)),
+        UNINITIALISED_DATA_SECTION_NAME => (SEG_DATA, string(SECT_BSS)),
+        name => {
+            let segment_name = if get_section_flags(section) & IMAGE_SCN_MEM_WRITE != 0 { SEG_DATA } else { SEG_TEXT };
+            let mut section_name = format!("__{}", name.trim_start_matches('.'));
+            section_name.truncate(NAME_FIELD_SIZE);
+            (segment_name, section_name)
+        }
+    }
+}
+
+fn get_mach_o_section_flags_for_coff_section(section: &CoffSection) -> u32 {
+    if is_uninitialised_data_section(section) {
+        S_ZEROFILL
+    } else if get_section_flags(section) & IMAGE_SCN_CNT_CODE != 0 {
+        S_REGULAR | S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS
+    } else {
+        S_REGULAR
+    }
+}
+
+/// Converts a COFF object into a Mach-O one. Symbol names get the `_` prefix of C names on Darwin, section
+/// symbols become local relocations against their section and discardable sections such as CodeView
+/// debug info are left out. Code is placed first and zero fill sections last, as they take no space in the file.
+pub fn mach_o_from_coff(coff: &Coff) -> Result<MachO, CoffError> {
+    let mut mach_o = create_mach_o();
+
+    let mut coff_sections: Vec<(u16, &CoffSection)> = get_sections(coff)
+        .iter()
+        .enumerate()
+        .map(|(section_index, section)| (section_index as u16 + 1, section))
+        .filter(|(_, section)| !is_discardable_section(section))
+        .collect();
+    coff_sections.sort_by_key(|(_, section)| (get_section_flags(section) & IMAGE_SCN_CNT_CODE == 0, is_uninitialised_data_section(section)));
+
+    let mut section_indices = HashMap::new();
+    let mut section_symbols = HashMap::new();
+    for (section_number, section) in &coff_sections {
+        let (segment_name, section_name) = get_mach_o_names_for_coff_section(section);
+        let mach_o_section = add_mach_o_section(
+            &mut mach_o,
+            segment_name,
+            &section_name,
+            get_mach_o_section_flags_for_coff_section(section),
+            get_section_alignment(section)
+        );
+        if is_uninitialised_data_section(section) {
+            reserve_mach_o_zero_fill(&mut mach_o, mach_o_section, get_section_size(section) as u64);
+        } else {
+            add_entries_to_mach_o_section(&mut mach_o, mach_o_section, get_section_data(section));
+        }
+        section_indices.insert(*section_number, mach_o_section);
+        if let Some(handle) = get_section_definition_symbol(section) {
+            section_symbols.insert(handle, mach_o_section);
+        }
+    }
+
+    let mut symbol_indices = HashMap::new();
+    for handle in get_symbol_handles(coff) {
+        if section_symbols.contains_key(&handle) {
+            continue;
+        }
+        let Some(entry) = get_symbol(coff, handle) else {
+            continue;
+        };
+        if let Some(symbol) = mach_o_symbol_for_coff_symbol(coff, get_primary_symbol(entry), &section_indices) {
+            symbol_indices.insert(handle, add_mach_o_symbol(&mut mach_o, symbol));
+        }
+    }
+
+    for (section_number, section) in &coff_sections {
+        let mach_o_section = section_indices[section_number];
+        for relocation in get_section_relocations(section) {
+            let handle = get_relocation_symbol(relocation);
+            let target = match (symbol_indices.get(&handle), section_symbols.get(&handle)) {
+                (Some(symbol), _) => MachORelocationTarget::Symbol(*symbol),
+                (None, Some(target_section)) => MachORelocationTarget::Section(*target_section),
+                (None, None) => return Err(CoffError::UnknownSymbolHandle { section: string(get_section_name(section)) })
+            };
+            let relocation = convert_relocation(section, relocation, target)?;
+            add_mach_o_relocation(&mut mach_o, mach_o_section, relocation);
+        }
+    }
+
+    Ok(mach_o)
+}
+
+fn mach_o_symbol_for_coff_symbol(coff: &Coff, symbol: &CoffSymbol, section_indices: &HashMap<u16, usize>) -> Option<MachOSymbol> {
+    let storage_class = get_symbol_storage_class(symbol)?;
+    if storage_class != IMAGE_SYM_CLASS_EXTERNAL && storage_class != IMAGE_SYM_CLASS_STATIC {
+        return None;
+    }
+    let name = format!("{}{}", SYMBOL_NAME_PREFIX, get_symbol_name(coff, symbol)?);
+    let external = storage_class == IMAGE_SYM_CLASS_EXTERNAL;
+    let value = get_symbol_value(symbol)? as u64;
+
+    match get_symbol_section_number(symbol)? {
+        IMAGE_SYM_UNDEFINED => Some(mach_o_symbol(&name, external, MachOSymbolSection::Undefined, 0)),
+        IMAGE_SYM_ABSOLUTE => Some(mach_o_symbol(&name, external, MachOSymbolSection::Absolute, value)),
+        IMAGE_SYM_DEBUG => None,
+        section_number => Some(mach_o_symbol(&name, external, MachOSymbolSection::Section(*section_indices.get(&section_number)?), value))
+    }
+}
+
+/// COFF addends are held in the section data; Mach-O ones are too, but are only known once the sections have addresses,
+/// so the addend is kept on the relocation until the object is written.
+fn convert_relocation(
+    section: &CoffSection,
+    relocation: &CoffRelocation,
+    target: MachORelocationTarget
+) -> Result<MachORelocation, CoffError> {
+    let offset = get_relocation_offset(relocation);
+    let relocation_type = get_relocation_type(relocation);
+    let data = get_section_data(section);
+    let unsupported = || CoffError::UnsupportedRelocation { section: string(get_section_name(section)), relocation_type };
+    let field = |width: usize| data.get(offset as usize..offset as usize + width).ok_or_else(unsupported);
+
+    match relocation_type {
+        IMAGE_REL_AMD64_ADDR64 => {
+            let addend = i64::from_le_bytes(field(8)?.try_into().unwrap());
+            Ok(mach_o_relocation(offset, target, X86_64_RELOC_UNSIGNED, 3, addend))
+        },
+        IMAGE_REL_AMD64_REL32 | IMAGE_REL_AMD64_REL32_1 | IMAGE_REL_AMD64_REL32_2 | IMAGE_REL_AMD64_REL32_4 => {
+            let addend = u32_from_bytes(field(4)?, 0) as i32 as i64;
+            let mach_o_relocation_type = match relocation_type {
+                IMAGE_REL_AMD64_REL32 if is_branch_to_symbol(data, offset, target) => X86_64_RELOC_BRANCH,
+                IMAGE_REL_AMD64_REL32 => X86_64_RELOC_SIGNED,
+                IMAGE_REL_AMD64_REL32_1 => X86_64_RELOC_SIGNED_1,
+                IMAGE_REL_AMD64_REL32_2 => X86_64_RELOC_SIGNED_2,
+                _ => X86_64_RELOC_SIGNED_4
+            };
+            Ok(mach_o_relocation(offset, target, mach_o_relocation_type, RELOCATION_FIELD_SIZE, addend))
+        },
+        _ => Err(unsupported())
+    }
+}
+
+/// Calls and jumps to symbols use branch relocations so the linker can route them through a stub
+/// when the symbol resolves into a dylib. `call rel32` and `jmp rel32` are the only instructions they may be used for.
+fn is_branch_to_symbol(data: &[u8], offset: u32, target: MachORelocationTarget) -> bool {
+    let is_symbol = matches!(target, MachORelocationTarget::Symbol(_));
+    is_symbol && is_call_or_jump_displacement(data, offset)
+}
+
+fn get_bytes_after_relocation_field(relocation_type: u8) -> i64 {
+    match relocation_type {
+        X86_64_RELOC_SIGNED_1 => 1,
+        X86_64_RELOC_SIGNED_2 => 2,
+        X86_64_RELOC_SIGNED_4 => 4,
+        _ => 0
+    }
+}
+
+fn name_field(name: &str) -> [u8; NAME_FIELD_SIZE] {
+    let mut field = [0; NAME_FIELD_SIZE];
+    let length = name.len().min(NAME_FIELD_SIZE);
+    field[..length].copy_from_slice(&name.as_bytes()[..length]);
+    field
+}
+
+fn align_entries(entries: &mut Vec<u8>, alignment: usize) {
+    entries.resize(entries.len().div_ceil(alignment.max(1)) * alignment.max(1), 0);
+}
+
+/// Writes the value of a relocated field: the target address plus addend for local relocations, made relative to the end
+/// of the instruction when PC relative, and just the addend for external ones, less any bytes after the field for `SIGNED_n`.
+fn relocated_field_entries(relocation: &MachORelocation, section_address: u64, section_addresses: &[u64]) -> Vec<u8> {
+    let bytes_after_field = get_bytes_after_relocation_field(relocation.relocation_type);
+    let value = match relocation.target {
+        MachORelocationTarget::Symbol(_) => relocation.addend - bytes_after_field,
+        MachORelocationTarget::Section(section) => {
+            let target_address = section_addresses[section] as i64 + relocation.addend;
+            if relocation.relocation_type == X86_64_RELOC_UNSIGNED {
+                target_address
+            } else {
+                target_address - (section_address as i64 + relocation.offset as i64 + 4 + bytes_after_field)
+            }
+        }
+    };
+    if relocation.length == 3 {
+        value.to_le_bytes().to_vec()
+    } else {
+        (value as i32).to_le_bytes().to_vec()
+    }
+}
+
+/// Symbols are ordered local, then defined external, then undefined, as the dynamic symbol table requires.
+pub fn mach_o_to_bytes(mach_o: &MachO) -> Result<Vec<u8>, CoffError> {
+    let mut section_addresses = vec!();
+    let mut address = 0;
+    for section in &mach_o.sections {
+        address = align_up_u64(address, section.alignment as u64);
+        section_addresses.push(address);
+        address += get_mach_o_section_size(section);
+    }
+    let vm_size = address;
+
+    let number_of_sections = mach_o.sections.len();
+    let size_of_commands = SEGMENT_COMMAND_SIZE + number_of_sections * SECTION_SIZE + SYMTAB_COMMAND_SIZE + DYSYMTAB_COMMAND_SIZE + BUILD_VERSION_COMMAND_SIZE;
+
+    let mut contents = vec![0; MACH_HEADER_SIZE + size_of_commands];
+    let segment_file_offset = contents.len();
+    let mut section_offsets = vec!();
+    for (section, section_address) in mach_o.sections.iter().zip(&section_addresses) {
+        if is_zero_fill_section(section) {
+            section_offsets.push(0);
+            continue;
+        }
+        contents.resize(segment_file_offset + *section_address as usize, 0);
+        section_offsets.push(contents.len() as u32);
+        let mut data = section.data.clone();
+        for relocation in &section.relocations {
+            let entries = relocated_field_entries(relocation, *section_address, &section_addresses);
+            let start = relocation.offset as usize;
+            data[start..start + entries.len()].copy_from_slice(&entries);
+        }
+        contents.extend(data);
+    }
+    let segment_file_size = contents.len() - segment_file_offset;
+
+    let mut symbol_order: Vec<usize> = (0..mach_o.symbols.len()).collect();
+    symbol_order.sort_by_key(|index| {
+        let symbol = &mach_o.symbols[*index];
+        match (symbol.external, symbol.section) {
+            (false, _) => (0, String::new()),
+            (true, MachOSymbolSection::Undefined) => (2, symbol.name.clone()),
+            (true, _) => (1, symbol.name.clone())
+        }
+    });
+    let mut symbol_indices = vec![0u32; mach_o.symbols.len()];
+    for (position, index) in symbol_order.iter().enumerate() {
+        symbol_indices[*index] = position as u32;
+    }
+    let number_of_local_symbols = mach_o.symbols.iter().filter(|symbol| !symbol.external).count() as u32;
+    let number_of_undefined_symbols = mach_o.symbols
+        .iter()
+        .filter(|symbol| symbol.external && symbol.section == MachOSymbolSection::Undefined)
+        .count() as u32;
+    let number_of_defined_external_symbols = mach_o.symbols.len() as u32 - number_of_local_symbols - number_of_undefined_symbols;
+
+    align_entries(&mut contents, RELOCATION_INFO_SIZE);
+    let mut relocation_offsets = vec!();
+    for section in &mach_o.sections {
+        relocation_offsets.push(if section.relocations.is_empty() { 0 } else { contents.len() as u32 });
+        for relocation in section.relocations.iter().rev() {
+            let (symbol_number, external) = match relocation.target {
+                MachORelocationTarget::Symbol(symbol) => (symbol_indices[symbol], 1),
+                MachORelocationTarget::Section(section) => (section as u32 + 1, 0)
+            };
+            let pc_relative = (relocation.relocation_type != X86_64_RELOC_UNSIGNED) as u32;
+            contents.extend(relocation.offset.to_le_bytes());
+            contents.extend((
+                symbol_number
+                    | pc_relative << 24
+                    | (relocation.length as u32) << 25
+                    | external << 27
+                    | (relocation.relocation_type as u32) << 28
+            ).to_le_bytes());
+        }
+    }
+
+    align_entries(&mut contents, SYMBOL_TABLE_ALIGNMENT);
+    let symbol_table_offset = contents.len() as u32;
+    let mut names = vec![0];
+    for index in &symbol_order {
+        let symbol = &mach_o.symbols[*index];
+        let (symbol_type, section_ordinal, value) = match symbol.section {
+            MachOSymbolSection::Undefined => (N_UNDF, NO_SECT, 0),
+            MachOSymbolSection::Absolute => (N_ABS, NO_SECT, symbol.value),
+            MachOSymbolSection::Section(section) => (N_SECT, section as u8 + 1, section_addresses[section] + symbol.value)
+        };
+        contents.extend((names.len() as u32).to_le_bytes());
+        names.extend(string_to_bytes_zero_terminated(&symbol.name));
+        contents.push(if symbol.external { symbol_type | N_EXT } else { symbol_type });
+        contents.push(section_ordinal);
+        contents.extend(0u16.to_le_bytes());
+        contents.extend(value.to_le_bytes());
+    }
+    let string_table_offset = contents.len() as u32;
+    align_entries(&mut names, SYMBOL_TABLE_ALIGNMENT);
+    contents.extend_from_slice(&names);
+
+    let mut commands = vec!();
+    commands.extend(LC_SEGMENT_64.to_le_bytes());
+    commands.extend(((SEGMENT_COMMAND_SIZE + number_of_sections * SECTION_SIZE) as u32).to_le_bytes());
+    commands.extend(name_field(""));
+    for value in [0, vm_size, segment_file_offset as u64, segment_file_size as u64] {
+        commands.extend(value.to_le_bytes());
+    }
+    for value in [VM_PROT_ALL, VM_PROT_ALL, number_of_sections as u32, 0] {
+        commands.extend(value.to_le_bytes());
+    }
+    for (index, section) in mach_o.sections.iter().enumerate() {
+        commands.extend(name_field(&section.section_name));
+        commands.extend(name_field(&section.segment_name));
+        commands.extend(section_addresses[index].to_le_bytes());
+        commands.extend(get_mach_o_section_size(section).to_le_bytes());
+        for value in [
+            section_offsets[index],
+            section.alignment.trailing_zeros(),
+            relocation_offsets[index],
+            section.relocations.len() as u32,
+            section.flags,
+            0,
+            0,
+            0
+        ] {
+            commands.extend(value.to_le_bytes());
+        }
+    }
+
+    for value in [LC_SYMTAB, SYMTAB_COMMAND_SIZE as u32, symbol_table_offset, mach_o.symbols.len() as u32, string_table_offset, names.len() as u32] {
+        commands.extend(value.to_le_bytes());
+    }
+
+    let mut dynamic_symbol_table = vec![
+        LC_DYSYMTAB,
+        DYSYMTAB_COMMAND_SIZE as u32,
+        0,
+        number_of_local_symbols,
+        number_of_local_symbols,
+        number_of_defined_external_symbols,
+        number_of_local_symbols + number_of_defined_external_symbols,
+        number_of_undefined_symbols
+    ];
+    dynamic_symbol_table.resize(DYSYMTAB_COMMAND_SIZE / 4, 0);
+    for value in dynamic_symbol_table {
+        commands.extend(value.to_le_bytes());
+    }
+
+    for value in [LC_BUILD_VERSION, BUILD_VERSION_COMMAND_SIZE as u32, PLATFORM_MACOS, MINIMUM_MACOS_VERSION, 0, 0] {
+        commands.extend(value.to_le_bytes());
+    }
+
+    let mut header = vec!();
+    for value in [MH_MAGIC_64, CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL, MH_OBJECT, NUMBER_OF_LOAD_COMMANDS, size_of_commands as u32, 0, 0] {
+        header.extend(value.to_le_bytes());
+    }
+    contents[..MACH_HEADER_SIZE].copy_from_slice(&header);
+    contents[MACH_HEADER_SIZE..MACH_HEADER_SIZE + size_of_commands].copy_from_slice(&commands);
+
+    u32::try_from(contents.len()).map_err(|_| CoffError::OffsetTooLarge { context: "Mach-O object" })?;
+    Ok(contents)
+}
+
+fn align_up_u64(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment.max(1)) * alignment.max(1)
+}
+
+pub fn write_mach_o<W: Write>(mach_o: &MachO, writer: &mut W) -> Result<(), CoffError> {
+    let entries = mach_o_to_bytes(mach_o)?;
+    writer.write_all(&entries).map_err(|error| io_error("could not write object", error))?;
+    writer.flush().map_err(|error| io_error("could not write object", error))
+}
+
+pub fn write_mach_o_to_file(mach_o: &MachO, file: &mut File) -> Result<(), CoffError> {
+    write_mach_o(mach_o, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u64_from_bytes(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn find_command(bytes: &[u8], command: u32) -> usize {
+        let mut offset = MACH_HEADER_SIZE;
+        while u32_from_bytes(bytes, offset) != command {
+            offset += u32_from_bytes(bytes, offset + 4) as usize;
+        }
+        offset
+    }
+
+    fn relocation_info(symbol_number: u32, pc_relative: u32, length: u32, external: u32, relocation_type: u8) -> u32 {
+        symbol_number | pc_relative << 24 | length << 25 | external << 27 | (relocation_type as u32) << 28
+    }
+
+    fn hello_mach_o() -> MachO {
+        let mut mach_o = create_mach_o();
+        let text = add_mach_o_section(&mut mach_o, SEG_TEXT, SECT_TEXT, S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS, 16);
+        let data = add_mach_o_section(&mut mach_o, SEG_DATA, SECT_DATA, S_REGULAR, 8);
+        let bss = add_mach_o_section(&mut mach_o, SEG_DATA, SECT_BSS, S_ZEROFILL, 4);
+
+        // call _puts; cmp byte [rip + _value], 1; lea rax, [rip + __data + 2]
+        add_entries_to_mach_o_section(&mut mach_o, text, &[
+            0xE8, 0, 0, 0, 0,
+            0x80, 0x3D, 0, 0, 0, 0, 0x01,
+            0x48, 0x8D, 0x05, 0, 0, 0, 0
+        ]);
+        add_entries_to_mach_o_section(&mut mach_o, data, &[0; 8]);
+        reserve_mach_o_zero_fill(&mut mach_o, bss, 16);
+
+        let puts = add_mach_o_symbol(&mut mach_o, mach_o_symbol("_puts", true, MachOSymbolSection::Undefined, 0));
+        add_mach_o_symbol(&mut mach_o, mach_o_symbol("_main", true, MachOSymbolSection::Section(text), 0));
+        add_mach_o_symbol(&mut mach_o, mach_o_symbol("l_local", false, MachOSymbolSection::Section(bss), 0));
+        let value = add_mach_o_symbol(&mut mach_o, mach_o_symbol("_value", true, MachOSymbolSection::Section(data), 4));
+
+        add_mach_o_relocation(&mut mach_o, text, mach_o_relocation(1, MachORelocationTarget::Symbol(puts), X86_64_RELOC_BRANCH, 2, 0));
+        add_mach_o_relocation(&mut mach_o, text, mach_o_relocation(7, MachORelocationTarget::Symbol(value), X86_64_RELOC_SIGNED_1, 2, 4));
+        add_mach_o_relocation(&mut mach_o, text, mach_o_relocation(15, MachORelocationTarget::Section(data), X86_64_RELOC_SIGNED, 2, 2));
+        add_mach_o_relocation(&mut mach_o, data, mach_o_relocation(0, MachORelocationTarget::Section(text), X86_64_RELOC_UNSIGNED, 3, 5));
+        mach_o
+    }
+
+    #[test]
+    fn header_and_sections_are_written_in_one_segment() {
+        let bytes = mach_o_to_bytes(&hello_mach_o()).unwrap();
+        let header: Vec<u32> = (0..8).map(|index| u32_from_bytes(&bytes, index * 4)).collect();
+        let size_of_commands = SEGMENT_COMMAND_SIZE + 3 * SECTION_SIZE + SYMTAB_COMMAND_SIZE + DYSYMTAB_COMMAND_SIZE + BUILD_VERSION_COMMAND_SIZE;
+        assert_eq!(header, [MH_MAGIC_64, CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL, MH_OBJECT, 4, size_of_commands as u32, 0, 0]);
+
+        let segment = find_command(&bytes, LC_SEGMENT_64);
+        let segment_file_offset = (MACH_HEADER_SIZE + size_of_commands) as u64;
+        assert_eq!(u32_from_bytes(&bytes, segment + 4) as usize, SEGMENT_COMMAND_SIZE + 3 * SECTION_SIZE);
+        assert_eq!(u64_from_bytes(&bytes, segment + 32), 48);
+        assert_eq!(u64_from_bytes(&bytes, segment + 40), segment_file_offset);
+        assert_eq!(u64_from_bytes(&bytes, segment + 48), 32);
+        assert_eq!(u32_from_bytes(&bytes, segment + 64), 3);
+
+        let relocation_offset = segment_file_offset as u32 + 32;
+        let expected = [
+            (SECT_TEXT, SEG_TEXT, 0, 19, segment_file_offset as u32, 4, relocation_offset, 3, S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS),
+            (SECT_DATA, SEG_DATA, 24, 8, segment_file_offset as u32 + 24, 3, relocation_offset + 24, 1, S_REGULAR),
+            (SECT_BSS, SEG_DATA, 32, 16, 0, 2, 0, 0, S_ZEROFILL)
+        ];
+        for (index, (section_name, segment_name, address, size, offset, alignment, relocations, number_of_relocations, flags)) in expected.into_iter().enumerate() {
+            let entry = segment + SEGMENT_COMMAND_SIZE + index * SECTION_SIZE;
+            assert_eq!(&bytes[entry..entry + NAME_FIELD_SIZE], &name_field(section_name));
+            assert_eq!(&bytes[entry + 16..entry + 16 + NAME_FIELD_SIZE], &name_field(segment_name));
+            assert_eq!(u64_from_bytes(&bytes, entry + 32), address);
+            assert_eq!(u64_from_bytes(&bytes, entry + 40), size);
+            let fields: Vec<u32> = (0..5).map(|field| u32_from_bytes(&bytes, entry + 48 + field * 4)).collect();
+            assert_eq!(fields, [offset, alignment, relocations, number_of_relocations, flags]);
+        }
+    }
+
+    #[test]
+    fn symbols_are_ordered_local_then_defined_then_undefined() {
+        let bytes = mach_o_to_bytes(&hello_mach_o()).unwrap();
+        let symbol_table = find_command(&bytes, LC_SYMTAB);
+        let symbol_table_offset = u32_from_bytes(&bytes, symbol_table + 8) as usize;
+        let string_table_offset = u32_from_bytes(&bytes, symbol_table + 16) as usize;
+        assert_eq!(symbol_table_offset % SYMBOL_TABLE_ALIGNMENT, 0);
+        assert_eq!(u32_from_bytes(&bytes, symbol_table + 12), 4);
+        assert_eq!(string_table_offset, symbol_table_offset + 4 * 16);
+        assert_eq!(u32_from_bytes(&bytes, symbol_table + 20), 32);
+        assert_eq!(string_table_offset + 32, bytes.len());
+
+        let symbols: Vec<(String, u8, u8, u64)> = (0..4)
+            .map(|index| {
+                let entry = symbol_table_offset + index * 16;
+                let name = string_table_offset + u32_from_bytes(&bytes, entry) as usize;
+                let length = bytes[name..].iter().position(|byte| *byte == 0).unwrap();
+                (String::from_utf8_lossy(&bytes[name..name + length]).into_owned(), bytes[entry + 4], bytes[entry + 5], u64_from_bytes(&bytes, entry + 8))
+            })
+            .collect();
+        assert_eq!(symbols, [
+            (string("l_local"), N_SECT, 3, 32),
+            (string("_main"), N_SECT | N_EXT, 1, 0),
+            (string("_value"), N_SECT | N_EXT, 2, 28),
+            (string("_puts"), N_UNDF | N_EXT, NO_SECT, 0)
+        ]);
+
+        let dynamic_symbol_table = find_command(&bytes, LC_DYSYMTAB);
+        let ranges: Vec<u32> = (2..8).map(|field| u32_from_bytes(&bytes, dynamic_symbol_table + field * 4)).collect();
+        assert_eq!(ranges, [0, 1, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn relocations_are_written_with_their_addends_in_place() {
+        let bytes = mach_o_to_bytes(&hello_mach_o()).unwrap();
+        let segment = find_command(&bytes, LC_SEGMENT_64);
+        let text = u64_from_bytes(&bytes, segment + 40) as usize;
+        let data = text + 24;
+        let relocations = text + 32;
+
+        let text_relocations: Vec<(u32, u32)> = (0..3)
+            .map(|index| (u32_from_bytes(&bytes, relocations + index * 8), u32_from_bytes(&bytes, relocations + index * 8 + 4)))
+            .collect();
+        assert_eq!(text_relocations, [
+            (15, relocation_info(2, 1, 2, 0, X86_64_RELOC_SIGNED)),
+            (7, relocation_info(2, 1, 2, 1, X86_64_RELOC_SIGNED_1)),
+            (1, relocation_info(3, 1, 2, 1, X86_64_RELOC_BRANCH))
+        ]);
+        assert_eq!(u32_from_bytes(&bytes, relocations + 24), 0);
+        assert_eq!(u32_from_bytes(&bytes, relocations + 28), relocation_info(1, 0, 3, 0, X86_64_RELOC_UNSIGNED));
+
+        assert_eq!(u32_from_bytes(&bytes, text + 1), 0);
+        assert_eq!(u32_from_bytes(&bytes, text + 7), 3);
+        assert_eq!(u32_from_bytes(&bytes, text + 15), 24 + 2 - 19);
+        assert_eq!(u64_from_bytes(&bytes, data), 5);
+    }
+
+    fn hello_ir() -> IntermediateRepresentation {
+        let mut ir = create_intermediate_representation(compilation_unit_id(1), string("hello.hep"));
+        let main = add_symbol(&mut ir.symbols, external_function(string("main")));
+        let puts = add_symbol(&mut ir.symbols, foreign_external(string("puts")));
+        let helper = add_symbol(&mut ir.symbols, static_function(string("helper")));
+        let counter = add_data_item(&mut ir.data, zero_initialised_data_item(16, 8));
+        add_data_item(&mut ir.data, read_only_string_data_item(string("hi\0")));
+        let there = add_data_item(&mut ir.data, read_only_string_data_item(string("there\0")));
+
+        add_byte_code(&mut ir.byte_code, define_function_instruction(main));
+        add_byte_code(&mut ir.byte_code, push_reg_64_instruction(base_pointer_register()));
+        add_byte_code(&mut ir.byte_code, load_data_section_address_to_reg_64(there, call_arg_register(0)));
+        add_byte_code(&mut ir.byte_code, call_to_symbol_instruction(puts));
+        add_byte_code(&mut ir.byte_code, load_data_section_address_to_reg_64(counter, call_return_arg_register(0)));
+        add_byte_code(&mut ir.byte_code, call_to_symbol_instruction(helper));
+        add_byte_code(&mut ir.byte_code, pop_reg_64_instruction(base_pointer_register()));
+        add_byte_code(&mut ir.byte_code, ret_instruction());
+        add_byte_code(&mut ir.byte_code, define_function_instruction(helper));
+        add_byte_code(&mut ir.byte_code, ret_instruction());
+        ir
+    }
+
+    fn converted_relocations(mach_o: &MachO, section: usize) -> Vec<(u32, MachORelocationTarget, u8, i64)> {
+        get_mach_o_section_relocations(&get_mach_o_sections(mach_o)[section])
+            .iter()
+            .map(|relocation| (
+                get_mach_o_relocation_offset(relocation),
+                get_mach_o_relocation_target(relocation),
+                get_mach_o_relocation_type(relocation),
+                get_mach_o_relocation_addend(relocation)
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn ir_is_converted_with_code_first_and_zero_fill_last() {
+        let (object_name, mach_o) = build_mach_o_object(hello_ir()).unwrap();
+        assert_eq!(object_name, "hello.o");
+
+        let sections: Vec<(&str, &str, u32)> = get_mach_o_sections(&mach_o)
+            .iter()
+            .map(|section| (get_mach_o_segment_name(section), get_mach_o_section_name(section), get_mach_o_section_flags(section)))
+            .collect();
+        assert_eq!(sections, [
+            (SEG_TEXT, SECT_TEXT, S_REGULAR | S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS),
+            (SEG_DATA, SECT_DATA, S_REGULAR),
+            (SEG_TEXT, SECT_CONST, S_REGULAR),
+            (SEG_DATA, SECT_BSS, S_ZEROFILL)
+        ]);
+
+        assert_eq!(get_mach_o_symbols(&mach_o), [
+            mach_o_symbol("_main", true, MachOSymbolSection::Section(0), 0),
+            mach_o_symbol("_puts", true, MachOSymbolSection::Undefined, 0),
+            mach_o_symbol("_helper", false, MachOSymbolSection::Section(0), 27)
+        ]);
+
+        assert_eq!(converted_relocations(&mach_o, 0), [
+            (4, MachORelocationTarget::Section(2), X86_64_RELOC_SIGNED, 3),
+            (9, MachORelocationTarget::Symbol(1), X86_64_RELOC_BRANCH, 0),
+            (16, MachORelocationTarget::Section(3), X86_64_RELOC_SIGNED, 0),
+            (21, MachORelocationTarget::Symbol(2), X86_64_RELOC_BRANCH, 0)
+        ]);
+    }
+
+    #[test]
+    fn converted_ir_is_written_with_section_relative_fields() {
+        let (_, mach_o) = build_mach_o_object(hello_ir()).unwrap();
+        let bytes = mach_o_to_bytes(&mach_o).unwrap();
+        let segment = find_command(&bytes, LC_SEGMENT_64);
+        let section_address = |index: usize| u64_from_bytes(&bytes, segment + SEGMENT_COMMAND_SIZE + index * SECTION_SIZE + 32) as u32;
+        let text = u32_from_bytes(&bytes, segment + SEGMENT_COMMAND_SIZE + 48) as usize;
+
+        assert_eq!(u32_from_bytes(&bytes, text + 4), section_address(2) + 3 - 8);
+        assert_eq!(u32_from_bytes(&bytes, text + 9), 0);
+        assert_eq!(u32_from_bytes(&bytes, text + 16), section_address(3) - 20);
+        assert_eq!(u32_from_bytes(&bytes, text + 21), 0);
+
+        let symbol_table = find_command(&bytes, LC_SYMTAB);
+        let string_table_offset = u32_from_bytes(&bytes, symbol_table + 16) as usize;
+        let names = &bytes[string_table_offset..string_table_offset + u32_from_bytes(&bytes, symbol_table + 20) as usize];
+        assert_eq!(&names[..21], b"\0_helper\0_main\0_puts\0");
+    }
+
+    #[test]
+    fn only_calls_and_jumps_to_symbols_are_branches() {
+        let mut coff = create_coff();
+        let puts = add_foreign_external_symbol(&mut coff, "puts");
+        add_lea_rip_relative_to_reg_op(&mut coff, relocatable_value(puts, 0), REG_AX);
+        add_call_relocatable_addr_op(&mut coff, relocatable_value(puts, 0));
+
+        let mach_o = mach_o_from_coff(&coff).unwrap();
+        let text = get_mach_o_sections(&mach_o).iter().position(|section| get_mach_o_section_name(section) == SECT_TEXT).unwrap();
+        assert_eq!(converted_relocations(&mach_o, text), [
+            (3, MachORelocationTarget::Symbol(0), X86_64_RELOC_SIGNED, 0),
+            (8, MachORelocationTarget::Symbol(0), X86_64_RELOC_BRANCH, 0)
+        ]);
+    }
+
+    #[test]
+    fn relocations_with_no_mach_o_counterpart_are_rejected() {
+        for relocation_type in [IMAGE_REL_AMD64_REL32_3, IMAGE_REL_AMD64_REL32_5] {
+            let mut coff = create_coff();
+            let puts = add_foreign_external_symbol(&mut coff, "puts");
+            add_entries_to_text_section(&mut coff, vec![0; 8]);
+            let text = get_or_add_text_section_number(&mut coff);
+            add_relocation_at_offset(&mut coff, text, 0, puts, relocation_type).unwrap();
+
+            let result = mach_o_from_coff(&coff);
+            assert!(matches!(result, Err(CoffError::UnsupportedRelocation { relocation_type: unsupported, .. }) if unsupported == relocation_type));
+        }
+    }
+}
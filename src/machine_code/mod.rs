@@ -1,11 +1,31 @@
 mod coff;
 mod operations;
+mod addressing;
 mod registers;
 mod builder;
+mod disassembler;
+mod verifier;
+mod unwinding;
+mod code_view;
+mod archives;
+mod import_libraries;
+mod linker;
+mod elf;
+mod mach_o;
 mod utilities;
 
 pub use coff::*;
 pub use operations::*;
+pub use addressing::*;
 pub use registers::*;
 pub use builder::*;
+pub use disassembler::*;
+pub use verifier::*;
+pub use unwinding::*;
+pub use code_view::*;
+pub use archives::*;
+pub use import_libraries::*;
+pub use linker::*;
+pub use elf::*;
+pub use mach_o::*;
 pub use utilities::*;
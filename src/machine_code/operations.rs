@@ -1,14 +1,10 @@
 use std::*;
 use crate::machine_code::*;
 
-const MOD_REGISTER_INDIRECT: u8 = 0x01;
-const MOD_REGISTER_DIRECT: u8 = 0x03;
-const REX_B: u8 = 0x41;
-const REX_R: u8 = 0x44;
+const OPERAND_SIZE_OVERRIDE: u8 = 0x66;
 const REX_W: u8 = 0x48;
 const OP_ADD: u8 = 0x83;
 const OP_LEA: u8 = 0x8D;
-const OP_XOR: u8 = 0x31;
 const OP_PUSH: u8 = 0x50;
 const OP_POP: u8 = 0x58;
 const OP_MOV_R_TO_RM: u8 = 0x89;
@@ -16,93 +12,148 @@ const OP_MOV_RM_TO_R: u8 = 0x8B;
 const OP_MOV_IMM_TO_R: u8 = 0xB8;
 const OP_MOV_IMM_TO_RM: u8 = 0xC7;
 const OP_CALL: u8 = 0xE8;
+const OP_JMP_NEAR: u8 = 0xE9;
+const OP_JMP_SHORT: u8 = 0xEB;
+const OP_JCC_SHORT: u8 = 0x70;
+const OP_TWO_BYTE_ESCAPE: u8 = 0x0F;
+const OP_JCC_NEAR: u8 = 0x80;
+const OP_ALU_IMM8_TO_RM8: u8 = 0x80;
+const OP_ALU_IMM_TO_RM: u8 = 0x81;
+const OP_ALU_IMM8_TO_RM: u8 = 0x83;
+const OP_IMUL_RM_TO_R: u8 = 0xAF;
+const OP_IMUL_IMM_TO_R: u8 = 0x69;
+const OP_IMUL_IMM8_TO_R: u8 = 0x6B;
+const OP_SHIFT_IMM8_RM8: u8 = 0xC0;
+const OP_SHIFT_IMM8_RM: u8 = 0xC1;
+const OP_SHIFT_ONE_RM8: u8 = 0xD0;
+const OP_SHIFT_ONE_RM: u8 = 0xD1;
+const OP_SHIFT_CL_RM8: u8 = 0xD2;
+const OP_SHIFT_CL_RM: u8 = 0xD3;
+const OP_UNARY_RM8: u8 = 0xF6;
+const OP_UNARY_RM: u8 = 0xF7;
+const OP_SIGN_EXTEND_AL: u8 = 0x98;
+const OP_SIGN_EXTEND_AX: u8 = 0x99;
+const OP_MOVZX_RM8_TO_R: u8 = 0xB6;
+const OP_MOV_R8_TO_RM8: u8 = 0x88;
+const OP_MOV_RM8_TO_R8: u8 = 0x8A;
+const OP_MOV_IMM8_TO_RM8: u8 = 0xC6;
 const OP_RET: u8 = 0xC3;
 
 const SECONDARY_ADD_OP_SUB: u8 = 0x5;
 const SECONDARY_OP_NONE: u8 = 0x0;
+const SECONDARY_UNARY_OP_NOT: u8 = 0x2;
+const SECONDARY_UNARY_OP_NEG: u8 = 0x3;
+const SECONDARY_UNARY_OP_DIV: u8 = 0x6;
+const SECONDARY_UNARY_OP_IDIV: u8 = 0x7;
 
-fn mod_rm(mod_part: u8, reg_part: u8, r_m_part: u8) -> u8 {
-    mod_part << 6 | reg_part << 3 | r_m_part
-}
+const REG_AH: u8 = 0x04;
+const REG_DL: u8 = 0x02;
+
+pub const ALU_ADD: u8 = 0x0;
+pub const ALU_OR: u8 = 0x1;
+pub const ALU_AND: u8 = 0x4;
+pub const ALU_SUB: u8 = 0x5;
+pub const ALU_XOR: u8 = 0x6;
+pub const ALU_CMP: u8 = 0x7;
+
+pub const SHIFT_LEFT: u8 = 0x4;
+pub const SHIFT_RIGHT: u8 = 0x5;
+pub const SHIFT_ARITHMETIC_RIGHT: u8 = 0x7;
 
+pub const CONDITION_BELOW: u8 = 0x2;
+pub const CONDITION_ABOVE_OR_EQUAL: u8 = 0x3;
+pub const CONDITION_EQUAL: u8 = 0x4;
+pub const CONDITION_NOT_EQUAL: u8 = 0x5;
+pub const CONDITION_BELOW_OR_EQUAL: u8 = 0x6;
+pub const CONDITION_ABOVE: u8 = 0x7;
+pub const CONDITION_LESS: u8 = 0xC;
+pub const CONDITION_GREATER_OR_EQUAL: u8 = 0xD;
+pub const CONDITION_LESS_OR_EQUAL: u8 = 0xE;
+pub const CONDITION_GREATER: u8 = 0xF;
+
+/// `push` and `pop` default to 64 bit operands, so they only need a REX prefix for R8 to R15.
 pub fn add_push_reg_op(coff: &mut Coff, register: u8) {
-    add_entry_to_text_section(coff, OP_PUSH + register);
+    add_register_in_opcode_op(coff, OPERAND_SIZE_DWORD, OP_PUSH, register);
 }
 
 pub fn add_pop_reg_op(coff: &mut Coff, register: u8) {
-    add_entry_to_text_section(coff, OP_POP + register);
+    add_register_in_opcode_op(coff, OPERAND_SIZE_DWORD, OP_POP, register);
 }
 
 pub fn add_sub_byte_value_from_reg_op(coff: &mut Coff, value: u8, register: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_entry_to_text_section(coff, OP_ADD);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_DIRECT, SECONDARY_ADD_OP_SUB, register));
+    add_register_direct_op(coff, OPERAND_SIZE_QWORD, &[OP_ADD], SECONDARY_ADD_OP_SUB, register, false);
     add_entry_to_text_section(coff, value);
 }
 
 pub fn add_add_byte_value_to_reg_op(coff: &mut Coff, value: u8, register: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_entry_to_text_section(coff, OP_ADD);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_DIRECT, SECONDARY_OP_NONE, register));
+    add_register_direct_op(coff, OPERAND_SIZE_QWORD, &[OP_ADD], SECONDARY_OP_NONE, register, false);
     add_entry_to_text_section(coff, value);
 }
 
 pub fn add_mov_dword_relocatable_value_to_reg_op(coff: &mut Coff, relocatable_value: RelocatableValue, register: u8) {
-    add_entry_to_text_section(coff, OP_MOV_IMM_TO_R + register);
+    add_register_in_opcode_op(coff, OPERAND_SIZE_DWORD, OP_MOV_IMM_TO_R, register);
     add_relocatable_entry_and_text_section_inital_entry(coff, relocatable_value, IMAGE_REL_AMD64_ADDR32);
 }
 
 pub fn add_mov_dword_value_to_reg_op(coff: &mut Coff, value: u32, register: u8) {
-    add_entry_to_text_section(coff, OP_MOV_IMM_TO_R + register);
+    add_register_in_opcode_op(coff, OPERAND_SIZE_DWORD, OP_MOV_IMM_TO_R, register);
     add_entries_to_text_section(coff, u32_to_bytes(&value));
 }
 
 pub fn add_mov_from_qword_reg_to_reg_op(coff: &mut Coff, register_from: u8, register_to: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_mov_from_dword_reg_to_reg_op(coff, register_from, register_to);
+    add_register_direct_op(coff, OPERAND_SIZE_QWORD, &[OP_MOV_R_TO_RM], register_from, register_to, true);
 }
 
 pub fn add_mov_from_dword_reg_to_reg_op(coff: &mut Coff, register_from: u8, register_to: u8) {
-    add_entry_to_text_section(coff, OP_MOV_R_TO_RM);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_DIRECT, register_from, register_to));
+    add_register_direct_op(coff, OPERAND_SIZE_DWORD, &[OP_MOV_R_TO_RM], register_from, register_to, true);
 }
 
+/// Stores the value sign extended to a qword, so a zero clears the whole stack slot.
 pub fn add_mov_dword_value_into_reg_plus_offset_pointer_op(coff: &mut Coff, value: u32, address_register: u8, address_offset: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_entry_to_text_section(coff,OP_MOV_IMM_TO_RM);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_INDIRECT, 0, address_register));
-    add_entry_to_text_section(coff, 0x24);
-    add_entry_to_text_section(coff, address_offset);
-    add_entries_to_text_section(coff, u32_to_bytes(&value));
+    add_mov_value_to_memory_op(coff, OPERAND_SIZE_QWORD, value as i32, byte_offset_memory_operand(address_register, address_offset));
 }
 
 pub fn add_mov_dword_reg_plus_offset_pointer_to_reg_op(coff: &mut Coff, address_register: u8, address_offset: u8, into_register: u8) {
-    if register_has_high_bit(into_register) {
-        add_entry_to_text_section(coff, REX_R);    
-    }
-    add_entry_to_text_section(coff, OP_MOV_RM_TO_R);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_INDIRECT, remove_register_high_bit(into_register), address_register));
-    add_entry_to_text_section(coff, address_offset);
+    add_mov_memory_to_reg_op(coff, OPERAND_SIZE_DWORD, byte_offset_memory_operand(address_register, address_offset), into_register);
 }
 
 pub fn add_mov_qword_reg_plus_offset_pointer_to_reg_op(coff: &mut Coff, address_register: u8, address_offset: u8, into_register: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_entry_to_text_section(coff, OP_MOV_RM_TO_R);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_INDIRECT, into_register, address_register));
-    add_entry_to_text_section(coff, address_offset);
+    add_mov_memory_to_reg_op(coff, OPERAND_SIZE_QWORD, byte_offset_memory_operand(address_register, address_offset), into_register);
 }
 
 pub fn add_mov_reg_to_reg_plus_offset_qword_pointer_op(coff: &mut Coff, from_register: u8, into_address_register: u8, into_address_offset: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_entry_to_text_section(coff, OP_MOV_R_TO_RM);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_INDIRECT, from_register, into_address_register));
-    add_entry_to_text_section(coff, into_address_offset);
+    add_mov_reg_to_memory_op(coff, OPERAND_SIZE_QWORD, from_register, byte_offset_memory_operand(into_address_register, into_address_offset));
 }
 
 pub fn add_mov_reg_to_reg_plus_offset_dword_pointer_op(coff: &mut Coff, from_register: u8, into_address_register: u8, into_address_offset: u8) {
-    add_entry_to_text_section(coff, OP_MOV_R_TO_RM);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_INDIRECT, from_register, into_address_register));
-    add_entry_to_text_section(coff, into_address_offset);
+    add_mov_reg_to_memory_op(coff, OPERAND_SIZE_DWORD, from_register, byte_offset_memory_operand(into_address_register, into_address_offset));
+}
+
+/// The byte code's offsets are signed 8 bit displacements stored as bytes, so `0xF8` is `-8`.
+fn byte_offset_memory_operand(address_register: u8, address_offset: u8) -> MemoryOperand {
+    base_memory_operand(address_register, address_offset as i8 as i32)
+}
+
+pub fn add_mov_memory_to_reg_op(coff: &mut Coff, operand_size: u8, memory: MemoryOperand, into_register: u8) {
+    let opcode = sized_opcode(operand_size, OP_MOV_RM8_TO_R8, OP_MOV_RM_TO_R);
+    add_memory_operand_op(coff, operand_size, &[opcode], into_register, true, memory, 0);
+}
+
+pub fn add_mov_reg_to_memory_op(coff: &mut Coff, operand_size: u8, from_register: u8, memory: MemoryOperand) {
+    let opcode = sized_opcode(operand_size, OP_MOV_R8_TO_RM8, OP_MOV_R_TO_RM);
+    add_memory_operand_op(coff, operand_size, &[opcode], from_register, true, memory, 0);
+}
+
+/// Stores an immediate of the operand size; qword stores take a sign extended 32 bit immediate.
+pub fn add_mov_value_to_memory_op(coff: &mut Coff, operand_size: u8, value: i32, memory: MemoryOperand) {
+    let opcode = sized_opcode(operand_size, OP_MOV_IMM8_TO_RM8, OP_MOV_IMM_TO_RM);
+    let immediate = immediate_entries(operand_size, value);
+    add_memory_operand_op(coff, operand_size, &[opcode], SECONDARY_OP_NONE, false, memory, immediate.len() as u8);
+    add_entries_to_text_section(coff, immediate);
+}
+
+pub fn add_lea_memory_to_reg_op(coff: &mut Coff, memory: MemoryOperand, into_register: u8) {
+    add_memory_operand_op(coff, OPERAND_SIZE_QWORD, &[OP_LEA], into_register, true, memory, 0);
 }
 
 pub fn add_call_relocatable_addr_op(coff: &mut Coff, relocatable_address: RelocatableValue) {
@@ -110,23 +161,251 @@ pub fn add_call_relocatable_addr_op(coff: &mut Coff, relocatable_address: Reloca
     add_relocatable_entry_and_text_section_inital_entry(coff, relocatable_address, IMAGE_REL_AMD64_REL32);
 }
 
-pub fn add_lea_reg_plus_offset_pointer_to_reg_op(coff: &mut Coff, address_register: u8, relocatable_address_offset: RelocatableValue, into_register: u8) {
-    add_entry_to_text_section(coff, REX_W);
-    add_entry_to_text_section(coff, OP_LEA);
-    add_entry_to_text_section(coff, mod_rm(0, into_register, address_register));
-    add_relocatable_entry_and_text_section_inital_entry(coff, relocatable_address_offset, IMAGE_REL_AMD64_REL32);
+pub fn is_call_or_jump_displacement(data: &[u8], offset: u32) -> bool {
+    offset > 0 && matches!(data.get(offset as usize - 1), Some(&OP_CALL) | Some(&OP_JMP_NEAR))
+}
+
+pub fn add_lea_rip_relative_to_reg_op(coff: &mut Coff, relocatable_address: RelocatableValue, into_register: u8) {
+    add_lea_memory_to_reg_op(coff, rip_relative_memory_operand(MemoryDisplacement::Relocatable(relocatable_address)), into_register);
 }
 
 pub fn add_xor_qword_reg_into_reg_op(coff: &mut Coff, register_from: u8, register_into: u8) {
-    let mut rex = REX_W | REX_B;
-    if register_has_high_bit(register_from) {
-        rex = rex | REX_R
-    }
-    add_entry_to_text_section(coff, rex);
-    add_entry_to_text_section(coff, OP_XOR);
-    add_entry_to_text_section(coff, mod_rm(MOD_REGISTER_DIRECT, remove_register_high_bit(register_from), remove_register_high_bit(register_into)));
+    add_alu_reg_into_reg_op(coff, ALU_XOR, OPERAND_SIZE_QWORD, register_from, register_into);
+}
+
+pub fn add_jmp_short_op(coff: &mut Coff, displacement: i8) {
+    add_entry_to_text_section(coff, OP_JMP_SHORT);
+    add_entries_to_text_section(coff, displacement.to_le_bytes().to_vec());
+}
+
+pub fn add_jmp_near_op(coff: &mut Coff, displacement: i32) {
+    add_entry_to_text_section(coff, OP_JMP_NEAR);
+    add_entries_to_text_section(coff, displacement.to_le_bytes().to_vec());
+}
+
+pub fn add_jcc_short_op(coff: &mut Coff, condition: u8, displacement: i8) {
+    add_entry_to_text_section(coff, OP_JCC_SHORT + condition);
+    add_entries_to_text_section(coff, displacement.to_le_bytes().to_vec());
+}
+
+pub fn add_jcc_near_op(coff: &mut Coff, condition: u8, displacement: i32) {
+    add_entry_to_text_section(coff, OP_TWO_BYTE_ESCAPE);
+    add_entry_to_text_section(coff, OP_JCC_NEAR + condition);
+    add_entries_to_text_section(coff, displacement.to_le_bytes().to_vec());
 }
 
 pub fn add_ret_op(coff: &mut Coff) {
     add_entry_to_text_section(coff, OP_RET);
-}
\ No newline at end of file
+}
+
+fn immediate_entries(operand_size: u8, value: i32) -> Vec<u8> {
+    match operand_size {
+        OPERAND_SIZE_BYTE => vec!(value as u8),
+        OPERAND_SIZE_WORD => (value as i16).to_le_bytes().to_vec(),
+        _ => value.to_le_bytes().to_vec()
+    }
+}
+
+fn sized_opcode(operand_size: u8, byte_opcode: u8, opcode: u8) -> u8 {
+    if operand_size == OPERAND_SIZE_BYTE { byte_opcode } else { opcode }
+}
+
+pub fn add_alu_reg_into_reg_op(coff: &mut Coff, operation: u8, operand_size: u8, register_from: u8, register_into: u8) {
+    let opcode = operation << 3 | sized_opcode(operand_size, 0x0, 0x1);
+    add_register_direct_op(coff, operand_size, &[opcode], register_from, register_into, true);
+}
+
+pub fn add_alu_value_into_reg_op(coff: &mut Coff, operation: u8, operand_size: u8, value: i32, register_into: u8) {
+    if operand_size == OPERAND_SIZE_BYTE {
+        add_register_direct_op(coff, operand_size, &[OP_ALU_IMM8_TO_RM8], operation, register_into, false);
+        add_entries_to_text_section(coff, immediate_entries(operand_size, value));
+    } else if i8::try_from(value).is_ok() {
+        add_register_direct_op(coff, operand_size, &[OP_ALU_IMM8_TO_RM], operation, register_into, false);
+        add_entries_to_text_section(coff, immediate_entries(OPERAND_SIZE_BYTE, value));
+    } else {
+        add_register_direct_op(coff, operand_size, &[OP_ALU_IMM_TO_RM], operation, register_into, false);
+        add_entries_to_text_section(coff, immediate_entries(operand_size, value));
+    }
+}
+
+pub fn add_imul_reg_into_reg_op(coff: &mut Coff, operand_size: u8, register_from: u8, register_into: u8) {
+    add_register_direct_op(coff, operand_size, &[OP_TWO_BYTE_ESCAPE, OP_IMUL_RM_TO_R], register_into, register_from, true);
+}
+
+pub fn add_imul_value_into_reg_op(coff: &mut Coff, operand_size: u8, value: i32, register_into: u8) {
+    if i8::try_from(value).is_ok() {
+        add_register_direct_op(coff, operand_size, &[OP_IMUL_IMM8_TO_R], register_into, register_into, true);
+        add_entries_to_text_section(coff, immediate_entries(OPERAND_SIZE_BYTE, value));
+    } else {
+        add_register_direct_op(coff, operand_size, &[OP_IMUL_IMM_TO_R], register_into, register_into, true);
+        add_entries_to_text_section(coff, immediate_entries(operand_size, value));
+    }
+}
+
+pub fn add_not_reg_op(coff: &mut Coff, operand_size: u8, register: u8) {
+    let opcode = sized_opcode(operand_size, OP_UNARY_RM8, OP_UNARY_RM);
+    add_register_direct_op(coff, operand_size, &[opcode], SECONDARY_UNARY_OP_NOT, register, false);
+}
+
+pub fn add_neg_reg_op(coff: &mut Coff, operand_size: u8, register: u8) {
+    let opcode = sized_opcode(operand_size, OP_UNARY_RM8, OP_UNARY_RM);
+    add_register_direct_op(coff, operand_size, &[opcode], SECONDARY_UNARY_OP_NEG, register, false);
+}
+
+pub fn add_shift_reg_by_value_op(coff: &mut Coff, shift: u8, operand_size: u8, value: u8, register: u8) {
+    if value == 1 {
+        let opcode = sized_opcode(operand_size, OP_SHIFT_ONE_RM8, OP_SHIFT_ONE_RM);
+        add_register_direct_op(coff, operand_size, &[opcode], shift, register, false);
+    } else {
+        let opcode = sized_opcode(operand_size, OP_SHIFT_IMM8_RM8, OP_SHIFT_IMM8_RM);
+        add_register_direct_op(coff, operand_size, &[opcode], shift, register, false);
+        add_entry_to_text_section(coff, value);
+    }
+}
+
+pub fn add_shift_reg_by_cl_op(coff: &mut Coff, shift: u8, operand_size: u8, register: u8) {
+    let opcode = sized_opcode(operand_size, OP_SHIFT_CL_RM8, OP_SHIFT_CL_RM);
+    add_register_direct_op(coff, operand_size, &[opcode], shift, register, false);
+}
+
+pub fn add_sign_extend_dividend_op(coff: &mut Coff, operand_size: u8) {
+    match operand_size {
+        OPERAND_SIZE_BYTE => add_entries_to_text_section(coff, vec!(OPERAND_SIZE_OVERRIDE, OP_SIGN_EXTEND_AL)),
+        OPERAND_SIZE_WORD => add_entries_to_text_section(coff, vec!(OPERAND_SIZE_OVERRIDE, OP_SIGN_EXTEND_AX)),
+        OPERAND_SIZE_DWORD => add_entry_to_text_section(coff, OP_SIGN_EXTEND_AX),
+        _ => add_entries_to_text_section(coff, vec!(REX_W, OP_SIGN_EXTEND_AX))
+    }
+}
+
+/// Zero extends the accumulator into the dividend for `div`: `movzx eax, al` for bytes, otherwise `xor edx, edx`.
+pub fn add_zero_extend_dividend_op(coff: &mut Coff, operand_size: u8) {
+    if operand_size == OPERAND_SIZE_BYTE {
+        add_register_direct_op(coff, OPERAND_SIZE_DWORD, &[OP_TWO_BYTE_ESCAPE, OP_MOVZX_RM8_TO_R], REG_AX, REG_AX, true);
+    } else {
+        add_alu_reg_into_reg_op(coff, ALU_XOR, OPERAND_SIZE_DWORD, REG_DX, REG_DX);
+    }
+}
+
+/// Encodes `div divisor`; for bytes AX is divided and the remainder is moved from AH to DL 
+/// so the quotient and remainder always end up in the accumulator and data registers.
+pub fn add_div_reg_op(coff: &mut Coff, operand_size: u8, divisor_register: u8) {
+    add_divide_op(coff, SECONDARY_UNARY_OP_DIV, operand_size, divisor_register);
+}
+
+pub fn add_idiv_reg_op(coff: &mut Coff, operand_size: u8, divisor_register: u8) {
+    add_divide_op(coff, SECONDARY_UNARY_OP_IDIV, operand_size, divisor_register);
+}
+
+fn add_divide_op(coff: &mut Coff, secondary_op: u8, operand_size: u8, divisor_register: u8) {
+    let opcode = sized_opcode(operand_size, OP_UNARY_RM8, OP_UNARY_RM);
+    add_register_direct_op(coff, operand_size, &[opcode], secondary_op, divisor_register, false);
+    if operand_size == OPERAND_SIZE_BYTE {
+        add_entries_to_text_section(coff, vec!(OP_MOV_R8_TO_RM8, mod_rm(MOD_REGISTER_DIRECT, REG_AH, REG_DL)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(add_op: impl FnOnce(&mut Coff)) -> Vec<u8> {
+        let mut coff = create_coff();
+        add_op(&mut coff);
+        let section_number = get_or_add_text_section_number(&mut coff);
+        get_section_data(get_section(&coff, section_number).unwrap()).to_vec()
+    }
+
+    #[test]
+    fn push_and_pop_extend_the_opcode_register() {
+        assert_eq!(encoded(|coff| add_push_reg_op(coff, REG_BP)), [0x55]);
+        assert_eq!(encoded(|coff| add_push_reg_op(coff, REG_R8)), [0x41, 0x50]);
+        assert_eq!(encoded(|coff| add_pop_reg_op(coff, REG_R9)), [0x41, 0x59]);
+    }
+
+    #[test]
+    fn mov_immediate_extends_the_opcode_register() {
+        assert_eq!(encoded(|coff| add_mov_dword_value_to_reg_op(coff, 1, REG_CX)), [0xB9, 1, 0, 0, 0]);
+        assert_eq!(encoded(|coff| add_mov_dword_value_to_reg_op(coff, 1, REG_R9)), [0x41, 0xB9, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn register_direct_ops_extend_both_registers() {
+        assert_eq!(encoded(|coff| add_mov_from_qword_reg_to_reg_op(coff, REG_SP, REG_BP)), [0x48, 0x89, 0xE5]);
+        assert_eq!(encoded(|coff| add_mov_from_qword_reg_to_reg_op(coff, REG_AX, REG_R9)), [0x49, 0x89, 0xC1]);
+        assert_eq!(encoded(|coff| add_mov_from_qword_reg_to_reg_op(coff, REG_R8, REG_AX)), [0x4C, 0x89, 0xC0]);
+        assert_eq!(encoded(|coff| add_sub_byte_value_from_reg_op(coff, 8, REG_R8)), [0x49, 0x83, 0xE8, 0x08]);
+        assert_eq!(encoded(|coff| add_add_byte_value_to_reg_op(coff, 8, REG_SP)), [0x48, 0x83, 0xC4, 0x08]);
+    }
+
+    #[test]
+    fn xor_only_extends_high_registers() {
+        assert_eq!(encoded(|coff| add_xor_qword_reg_into_reg_op(coff, REG_AX, REG_AX)), [0x48, 0x31, 0xC0]);
+        assert_eq!(encoded(|coff| add_xor_qword_reg_into_reg_op(coff, REG_R9, REG_R9)), [0x4D, 0x31, 0xC9]);
+    }
+
+    #[test]
+    fn alu_ops_are_sized_by_prefix_and_opcode() {
+        assert_eq!(encoded(|coff| add_alu_reg_into_reg_op(coff, ALU_ADD, OPERAND_SIZE_BYTE, REG_CX, REG_AX)), [0x00, 0xC8]);
+        assert_eq!(encoded(|coff| add_alu_reg_into_reg_op(coff, ALU_ADD, OPERAND_SIZE_WORD, REG_CX, REG_AX)), [0x66, 0x01, 0xC8]);
+        assert_eq!(encoded(|coff| add_alu_reg_into_reg_op(coff, ALU_SUB, OPERAND_SIZE_DWORD, REG_CX, REG_AX)), [0x29, 0xC8]);
+        assert_eq!(encoded(|coff| add_alu_reg_into_reg_op(coff, ALU_CMP, OPERAND_SIZE_QWORD, REG_R8, REG_AX)), [0x4C, 0x39, 0xC0]);
+    }
+
+    #[test]
+    fn byte_ops_on_sil_and_dil_need_a_rex_prefix() {
+        assert_eq!(encoded(|coff| add_alu_reg_into_reg_op(coff, ALU_AND, OPERAND_SIZE_BYTE, REG_SI, REG_DI)), [0x40, 0x20, 0xF7]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_OR, OPERAND_SIZE_BYTE, 1, REG_SI)), [0x40, 0x80, 0xCE, 0x01]);
+        assert_eq!(encoded(|coff| add_not_reg_op(coff, OPERAND_SIZE_BYTE, REG_DI)), [0x40, 0xF6, 0xD7]);
+    }
+
+    #[test]
+    fn alu_immediates_use_a_byte_only_when_it_fits() {
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_ADD, OPERAND_SIZE_BYTE, 0x7F, REG_CX)), [0x80, 0xC1, 0x7F]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_ADD, OPERAND_SIZE_WORD, 0x7F, REG_CX)), [0x66, 0x83, 0xC1, 0x7F]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_ADD, OPERAND_SIZE_WORD, 0x80, REG_CX)), [0x66, 0x81, 0xC1, 0x80, 0x00]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_SUB, OPERAND_SIZE_DWORD, 127, REG_CX)), [0x83, 0xE9, 0x7F]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_SUB, OPERAND_SIZE_DWORD, 128, REG_CX)), [0x81, 0xE9, 0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_SUB, OPERAND_SIZE_QWORD, -128, REG_SP)), [0x48, 0x83, 0xEC, 0x80]);
+        assert_eq!(encoded(|coff| add_alu_value_into_reg_op(coff, ALU_SUB, OPERAND_SIZE_QWORD, 128, REG_SP)), [0x48, 0x81, 0xEC, 0x80, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn imul_puts_the_destination_in_the_reg_field() {
+        assert_eq!(encoded(|coff| add_imul_reg_into_reg_op(coff, OPERAND_SIZE_WORD, REG_CX, REG_AX)), [0x66, 0x0F, 0xAF, 0xC1]);
+        assert_eq!(encoded(|coff| add_imul_reg_into_reg_op(coff, OPERAND_SIZE_DWORD, REG_CX, REG_AX)), [0x0F, 0xAF, 0xC1]);
+        assert_eq!(encoded(|coff| add_imul_reg_into_reg_op(coff, OPERAND_SIZE_QWORD, REG_R9, REG_AX)), [0x49, 0x0F, 0xAF, 0xC1]);
+        assert_eq!(encoded(|coff| add_imul_value_into_reg_op(coff, OPERAND_SIZE_DWORD, 127, REG_CX)), [0x6B, 0xC9, 0x7F]);
+        assert_eq!(encoded(|coff| add_imul_value_into_reg_op(coff, OPERAND_SIZE_QWORD, 128, REG_CX)), [0x48, 0x69, 0xC9, 0x80, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn shifts_by_one_omit_the_immediate() {
+        assert_eq!(encoded(|coff| add_shift_reg_by_value_op(coff, SHIFT_LEFT, OPERAND_SIZE_DWORD, 1, REG_AX)), [0xD1, 0xE0]);
+        assert_eq!(encoded(|coff| add_shift_reg_by_value_op(coff, SHIFT_LEFT, OPERAND_SIZE_DWORD, 2, REG_AX)), [0xC1, 0xE0, 0x02]);
+        assert_eq!(encoded(|coff| add_shift_reg_by_value_op(coff, SHIFT_RIGHT, OPERAND_SIZE_BYTE, 1, REG_DI)), [0x40, 0xD0, 0xEF]);
+        assert_eq!(encoded(|coff| add_shift_reg_by_value_op(coff, SHIFT_ARITHMETIC_RIGHT, OPERAND_SIZE_WORD, 3, REG_AX)), [0x66, 0xC1, 0xF8, 0x03]);
+        assert_eq!(encoded(|coff| add_shift_reg_by_value_op(coff, SHIFT_LEFT, OPERAND_SIZE_QWORD, 63, REG_R8)), [0x49, 0xC1, 0xE0, 0x3F]);
+        assert_eq!(encoded(|coff| add_shift_reg_by_cl_op(coff, SHIFT_LEFT, OPERAND_SIZE_QWORD, REG_AX)), [0x48, 0xD3, 0xE0]);
+    }
+
+    #[test]
+    fn dividends_are_extended_for_each_width() {
+        assert_eq!(encoded(|coff| add_sign_extend_dividend_op(coff, OPERAND_SIZE_BYTE)), [0x66, 0x98]);
+        assert_eq!(encoded(|coff| add_sign_extend_dividend_op(coff, OPERAND_SIZE_WORD)), [0x66, 0x99]);
+        assert_eq!(encoded(|coff| add_sign_extend_dividend_op(coff, OPERAND_SIZE_DWORD)), [0x99]);
+        assert_eq!(encoded(|coff| add_sign_extend_dividend_op(coff, OPERAND_SIZE_QWORD)), [0x48, 0x99]);
+        assert_eq!(encoded(|coff| add_zero_extend_dividend_op(coff, OPERAND_SIZE_BYTE)), [0x0F, 0xB6, 0xC0]);
+        assert_eq!(encoded(|coff| add_zero_extend_dividend_op(coff, OPERAND_SIZE_QWORD)), [0x31, 0xD2]);
+    }
+
+    #[test]
+    fn divides_are_sized_and_move_byte_remainders_to_dl() {
+        assert_eq!(encoded(|coff| add_div_reg_op(coff, OPERAND_SIZE_BYTE, REG_CX)), [0xF6, 0xF1, 0x88, 0xE2]);
+        assert_eq!(encoded(|coff| add_div_reg_op(coff, OPERAND_SIZE_WORD, REG_CX)), [0x66, 0xF7, 0xF1]);
+        assert_eq!(encoded(|coff| add_idiv_reg_op(coff, OPERAND_SIZE_DWORD, REG_R8)), [0x41, 0xF7, 0xF8]);
+        assert_eq!(encoded(|coff| {
+            add_sign_extend_dividend_op(coff, OPERAND_SIZE_QWORD);
+            add_idiv_reg_op(coff, OPERAND_SIZE_QWORD, REG_CX);
+        }), [0x48, 0x99, 0x48, 0xF7, 0xF9]);
+    }
+}
@@ -3,6 +3,8 @@ pub const REG_CX: u8 = 0x01;
 pub const REG_DX: u8 = 0x02;
 pub const REG_SP: u8 = 0x04;
 pub const REG_BP: u8 = 0x05;
+pub const REG_SI: u8 = 0x06;
+pub const REG_DI: u8 = 0x07;
 pub const REG_IP: u8 = 0x05;
 pub const REG_R8: u8 = 0x08; 
 pub const REG_R9: u8 = 0x09;   
@@ -14,3 +16,8 @@ pub fn register_has_high_bit(register: u8) -> bool {
 pub fn remove_register_high_bit(register: u8) -> u8 {
     register & 0x7
 }
+
+/// Byte access to SP, BP, SI and DI needs a REX prefix, without one they encode AH, CH, DH and BH.
+pub fn register_needs_rex_for_byte_access(register: u8) -> bool {
+    (REG_SP..=REG_DI).contains(&register)
+}
@@ -0,0 +1,226 @@
+use crate::machine_code::*;
+use crate::errors::*;
+
+const UNWIND_INFO_VERSION: u8 = 1;
+const UWOP_PUSH_NONVOL: u8 = 0;
+const UWOP_ALLOC_LARGE: u8 = 1;
+const UWOP_ALLOC_SMALL: u8 = 2;
+const UWOP_SET_FPREG: u8 = 3;
+const MAX_ALLOC_SMALL: u32 = 128;
+const MAX_ALLOC_LARGE_SCALED: u32 = 512 * 1024 - 8;
+const MAX_PROLOGUE_SIZE: u32 = u8::MAX as u32;
+const STACK_ALLOCATION_GRANULARITY: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnwindOperation {
+    PushNonVolatile(u8),
+    Allocate(u32),
+    SetFramePointer(u8)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnwindCode {
+    pub prologue_offset: u8,
+    pub operation: UnwindOperation
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionUnwindInfo {
+    pub start: u32,
+    pub end: u32,
+    pub unwind_codes: Vec<UnwindCode>,
+    prologue_complete: bool
+}
+
+pub fn function_unwind_info(start: u32) -> FunctionUnwindInfo {
+    FunctionUnwindInfo { start, end: start, unwind_codes: vec!(), prologue_complete: false }
+}
+
+pub fn record_prologue_operation(info: &mut FunctionUnwindInfo, operation: Option<UnwindOperation>, end_of_instruction: u32) {
+    info.end = end_of_instruction;
+    if info.prologue_complete {
+        return;
+    }
+
+    let prologue_offset = end_of_instruction - info.start;
+    match operation {
+        Some(operation) if prologue_offset <= MAX_PROLOGUE_SIZE && is_describable(info, operation) =>
+            info.unwind_codes.push(UnwindCode { prologue_offset: prologue_offset as u8, operation }),
+        _ => info.prologue_complete = true
+    }
+}
+
+fn is_describable(info: &FunctionUnwindInfo, operation: UnwindOperation) -> bool {
+    match operation {
+        UnwindOperation::PushNonVolatile(_) => true,
+        UnwindOperation::Allocate(size) => size > 0 && size % STACK_ALLOCATION_GRANULARITY == 0,
+        UnwindOperation::SetFramePointer(_) => !info.unwind_codes
+            .iter()
+            .any(|code| matches!(code.operation, UnwindOperation::SetFramePointer(_)))
+    }
+}
+
+/// Encodes an UNWIND_INFO with its codes in reverse prologue order, padded to a whole number of dwords.
+pub fn unwind_info_entries(info: &FunctionUnwindInfo) -> Vec<u8> {
+    let mut slots: Vec<u16> = vec!();
+    let mut frame_register = 0;
+
+    for code in info.unwind_codes.iter().rev() {
+        match code.operation {
+            UnwindOperation::PushNonVolatile(register) =>
+                slots.push(unwind_code_slot(code.prologue_offset, UWOP_PUSH_NONVOL, register)),
+            UnwindOperation::SetFramePointer(register) => {
+                frame_register = register;
+                slots.push(unwind_code_slot(code.prologue_offset, UWOP_SET_FPREG, 0));
+            },
+            UnwindOperation::Allocate(size) if size <= MAX_ALLOC_SMALL =>
+                slots.push(unwind_code_slot(code.prologue_offset, UWOP_ALLOC_SMALL, ((size - 8) / 8) as u8)),
+            UnwindOperation::Allocate(size) if size <= MAX_ALLOC_LARGE_SCALED => {
+                slots.push(unwind_code_slot(code.prologue_offset, UWOP_ALLOC_LARGE, 0));
+                slots.push((size / 8) as u16);
+            },
+            UnwindOperation::Allocate(size) => {
+                slots.push(unwind_code_slot(code.prologue_offset, UWOP_ALLOC_LARGE, 1));
+                slots.push(size as u16);
+                slots.push((size >> 16) as u16);
+            }
+        }
+    }
+
+    let size_of_prologue = info.unwind_codes.last().map_or(0, |code| code.prologue_offset);
+    let mut entries = vec!(UNWIND_INFO_VERSION, size_of_prologue, slots.len() as u8, frame_register);
+    if slots.len() % 2 == 1 {
+        slots.push(0);
+    }
+    for slot in slots {
+        entries.extend_from_slice(&slot.to_le_bytes());
+    }
+    entries
+}
+
+fn unwind_code_slot(prologue_offset: u8, operation: u8, operation_info: u8) -> u16 {
+    u16::from_le_bytes([prologue_offset, operation | (operation_info << 4)])
+}
+
+/// Functions without any code have no extent to describe and are skipped.
+pub fn add_unwind_info(coff: &mut Coff, infos: &[FunctionUnwindInfo]) -> Result<(), CoffError> {
+    let infos: Vec<&FunctionUnwindInfo> = infos.iter().filter(|info| info.end > info.start).collect();
+    if infos.is_empty() {
+        return Ok(());
+    }
+
+    let text_section_number = get_or_add_text_section_number(coff);
+    let text_section_symbol = get_or_add_section_symbol(coff, text_section_number)?;
+    let unwind_data_section_number = get_or_add_unwind_data_section_number(coff);
+    let unwind_data_section_symbol = get_or_add_section_symbol(coff, unwind_data_section_number)?;
+    let exception_data_section_number = get_or_add_exception_data_section_number(coff);
+    get_or_add_section_symbol(coff, exception_data_section_number)?;
+
+    for info in infos {
+        let unwind_info_offset = add_entries_to_section(coff, unwind_data_section_number, unwind_info_entries(info))?;
+        for (symbol, value) in [
+            (text_section_symbol, info.start),
+            (text_section_symbol, info.end),
+            (unwind_data_section_symbol, unwind_info_offset)
+        ] {
+            add_relocatable_entry_and_section_initial_entry(
+                coff,
+                exception_data_section_number,
+                relocatable_value(symbol, value),
+                IMAGE_REL_AMD64_ADDR32NB
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation_unwind_info(size: u32, end_of_instruction: u32) -> FunctionUnwindInfo {
+        let mut info = function_unwind_info(0);
+        record_prologue_operation(&mut info, Some(UnwindOperation::Allocate(size)), end_of_instruction);
+        info
+    }
+
+    fn frame_unwind_info(start: u32) -> FunctionUnwindInfo {
+        let mut info = function_unwind_info(start);
+        record_prologue_operation(&mut info, Some(UnwindOperation::PushNonVolatile(REG_BP)), start + 1);
+        record_prologue_operation(&mut info, Some(UnwindOperation::SetFramePointer(REG_BP)), start + 4);
+        record_prologue_operation(&mut info, Some(UnwindOperation::Allocate(0x20)), start + 8);
+        record_prologue_operation(&mut info, None, start + 9);
+        info
+    }
+
+    #[test]
+    fn frame_prologues_are_described_in_reverse_order() {
+        let info = frame_unwind_info(0);
+        assert_eq!(info.end, 9);
+        assert_eq!(unwind_info_entries(&info), [
+            UNWIND_INFO_VERSION, 8, 3, REG_BP,
+            8, UWOP_ALLOC_SMALL | 3 << 4,
+            4, UWOP_SET_FPREG,
+            1, UWOP_PUSH_NONVOL | REG_BP << 4,
+            0, 0
+        ]);
+    }
+
+    #[test]
+    fn allocations_use_the_smallest_code_that_holds_them() {
+        assert_eq!(unwind_info_entries(&allocation_unwind_info(128, 4)), [
+            UNWIND_INFO_VERSION, 4, 1, 0, 4, UWOP_ALLOC_SMALL | 15 << 4, 0, 0
+        ]);
+        assert_eq!(unwind_info_entries(&allocation_unwind_info(136, 7)), [
+            UNWIND_INFO_VERSION, 7, 2, 0, 7, UWOP_ALLOC_LARGE, 17, 0
+        ]);
+        assert_eq!(unwind_info_entries(&allocation_unwind_info(512 * 1024 - 8, 7)), [
+            UNWIND_INFO_VERSION, 7, 2, 0, 7, UWOP_ALLOC_LARGE, 0xFF, 0xFF
+        ]);
+        assert_eq!(unwind_info_entries(&allocation_unwind_info(512 * 1024, 7)), [
+            UNWIND_INFO_VERSION, 7, 3, 0, 7, UWOP_ALLOC_LARGE | 1 << 4, 0x00, 0x00, 0x08, 0x00, 0, 0
+        ]);
+    }
+
+    #[test]
+    fn undescribable_operations_end_the_prologue() {
+        let mut info = function_unwind_info(0);
+        record_prologue_operation(&mut info, Some(UnwindOperation::PushNonVolatile(REG_BP)), 1);
+        record_prologue_operation(&mut info, Some(UnwindOperation::Allocate(12)), 5);
+        record_prologue_operation(&mut info, Some(UnwindOperation::PushNonVolatile(REG_SI)), 6);
+        assert_eq!(info.unwind_codes, [UnwindCode { prologue_offset: 1, operation: UnwindOperation::PushNonVolatile(REG_BP) }]);
+        assert_eq!(info.end, 6);
+    }
+
+    #[test]
+    fn runtime_functions_are_relocated_against_the_text_and_unwind_data() {
+        let mut coff = create_coff();
+        let mut allocation_info = function_unwind_info(9);
+        record_prologue_operation(&mut allocation_info, Some(UnwindOperation::Allocate(8)), 13);
+        add_unwind_info(&mut coff, &[frame_unwind_info(0), function_unwind_info(9), allocation_info]).unwrap();
+
+        let unwind_data = get_section(&coff, get_section_number(&coff, UNWIND_DATA_SECTION_NAME).unwrap()).unwrap();
+        assert_eq!(get_section_data(unwind_data)[12..], [UNWIND_INFO_VERSION, 4, 1, 0, 4, UWOP_ALLOC_SMALL, 0, 0]);
+
+        let exception_data = get_section(&coff, get_section_number(&coff, EXCEPTION_DATA_SECTION_NAME).unwrap()).unwrap();
+        let runtime_functions: Vec<u32> = get_section_data(exception_data)
+            .chunks(4)
+            .map(|entry| u32_from_bytes(entry, 0))
+            .collect();
+        assert_eq!(runtime_functions, [0, 9, 0, 9, 13, 12]);
+
+        let relocations: Vec<(u32, u16, String)> = get_section_relocations(exception_data)
+            .iter()
+            .map(|relocation| {
+                let symbol = get_primary_symbol(get_symbol(&coff, get_relocation_symbol(relocation)).unwrap());
+                (get_relocation_offset(relocation), get_relocation_type(relocation), get_symbol_name(&coff, symbol).unwrap())
+            })
+            .collect();
+        let expected: Vec<(u32, u16, String)> = [".text", ".text", ".xdata", ".text", ".text", ".xdata"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (index as u32 * 4, IMAGE_REL_AMD64_ADDR32NB, string(name)))
+            .collect();
+        assert_eq!(relocations, expected);
+    }
+}
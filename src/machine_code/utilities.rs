@@ -2,21 +2,23 @@ use std::{
     *,
     time::*
 };
+use crate::errors::*;
 
-pub fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    unsafe { 
-        slice::from_raw_parts(
-            (p as *const T) as *const u8,
-            ::std::mem::size_of::<T>(),
-        )
+pub fn get_8_padded_u8_array_from_string(from: &str) -> Result<[u8; 8], CoffError> {
+    if from.len() > 8 {
+        return Err(CoffError::NameTooLong { name: string(from), maximum: 8 });
     }
+    
+    let mut to = [0; 8];
+    to[..from.len()].copy_from_slice(from.as_bytes());
+    Ok(to)
 }
 
-pub fn get_8_padded_u8_array_from_string(from: &str) -> [u8; 8] {
-    assert!(from.len() <= 8);
+pub fn get_truncated_8_padded_u8_array_from_string(from: &str) -> [u8; 8] {
+    let from_len = if from.len() < 8 { from.len() } else { 8 };
     
     let mut to = [0; 8];
-    to[..from.len()].copy_from_slice(&from.as_bytes());
+    to[..from_len].copy_from_slice(&from.as_bytes()[..from_len]);
     to
 }
 
@@ -24,7 +26,7 @@ pub fn get_truncated_18_padded_u8_array_from_string(from: &str) -> [u8; 18] {
     let from_len = if from.len() < 18 { from.len() } else { 18 };
     
     let mut to = [0; 18];
-    to[..from_len].copy_from_slice(&from[..from_len].as_bytes());
+    to[..from_len].copy_from_slice(&from.as_bytes()[..from_len]);
     to
 }
 
@@ -39,17 +41,34 @@ pub fn string_to_bytes(entry: &str) -> Vec<u8> {
 }
 
 pub fn u32_to_bytes(entry: &u32) -> Vec<u8> {
-    any_as_u8_slice(entry).into()
+    entry.to_le_bytes().into()
+}
+
+pub fn u16_from_bytes(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub fn u32_from_bytes(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+pub fn string_from_padded_u8_array(from: &[u8]) -> String {
+    let length = from.iter().position(|byte| *byte == 0).unwrap_or(from.len());
+    String::from_utf8_lossy(&from[..length]).into_owned()
+}
+
+pub fn align_up(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
 }
 
 pub fn string(value: &str) -> String {
     value.to_string()
 }
 
-pub fn get_current_timestamp() -> u32 {
+pub fn get_current_timestamp() -> Result<u32, CoffError> {
     // seconds since 1970-01-01 00:00:00 GMT
     match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(n) => return n.as_secs() as u32,
-        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+        Ok(n) => Ok(n.as_secs() as u32),
+        Err(_) => Err(CoffError::TimestampBeforeUnixEpoch),
     }
 }
\ No newline at end of file
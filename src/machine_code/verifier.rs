@@ -0,0 +1,458 @@
+use std::*;
+use std::collections::HashMap;
+
+use crate::{
+    machine_code::*,
+    intemediate_representation::*,
+    errors::*
+};
+
+const STACK_SLOT_SIZE: i64 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IrProblem {
+    SymbolIndexOutOfRange { symbol_index: u32, number_of_symbols: usize },
+    DataItemIndexOutOfRange { data_item: u32, number_of_data_items: usize },
+    SymbolDataItemOutOfRange { symbol_index: usize, data_item: u32, number_of_data_items: usize },
+    DuplicateSymbolName { symbol_index: usize, name: String },
+    LabelIndexOutOfRange { label: u32, number_of_labels: usize },
+    LabelNotDefined(u32),
+    LabelDefinedTwice(u32),
+    FunctionNotDefined { symbol_index: usize },
+    FunctionDefinedTwice(u32),
+    NotAFunction(u32),
+    UnsupportedRegister { register: ByteCodeRegister, reason: &'static str },
+    LineNumberTooLarge(u32),
+    ValueTooLargeForWidth { value: i32, width_in_bits: u32 },
+    ShiftCountTooLarge { count: u8, width_in_bits: u32 },
+    UnsupportedWidth { instruction: &'static str, width_in_bits: u32 },
+    StackUnbalancedAtReturn { depth: i64 },
+    StackDepthMismatchAtLabel { label: u32, depth: i64, expected: i64 }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrDiagnostic {
+    pub instruction_index: Option<usize>,
+    pub problem: IrProblem
+}
+
+fn ir_diagnostic(instruction_index: Option<usize>, problem: IrProblem) -> IrDiagnostic {
+    IrDiagnostic { instruction_index, problem }
+}
+
+impl fmt::Display for IrProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrProblem::SymbolIndexOutOfRange { symbol_index, number_of_symbols } =>
+                write!(f, "symbol index {} is out of range for {} symbols", symbol_index, number_of_symbols),
+            IrProblem::DataItemIndexOutOfRange { data_item, number_of_data_items } =>
+                write!(f, "data item {} is out of range for {} data items", data_item, number_of_data_items),
+            IrProblem::SymbolDataItemOutOfRange { symbol_index, data_item, number_of_data_items } =>
+                write!(f, "symbol {} refers to data item {} which is out of range for {} data items", symbol_index, data_item, number_of_data_items),
+            IrProblem::DuplicateSymbolName { symbol_index, name } =>
+                write!(f, "symbol {} reuses the name {}", symbol_index, name),
+            IrProblem::LabelIndexOutOfRange { label, number_of_labels } =>
+                write!(f, "label {} is out of range for {} labels", label, number_of_labels),
+            IrProblem::LabelNotDefined(label) =>
+                write!(f, "label {} is jumped to but never defined", label),
+            IrProblem::LabelDefinedTwice(label) =>
+                write!(f, "label {} is defined more than once", label),
+            IrProblem::FunctionNotDefined { symbol_index } =>
+                write!(f, "function symbol {} has no definition in the byte code", symbol_index),
+            IrProblem::FunctionDefinedTwice(symbol_index) =>
+                write!(f, "function symbol {} is defined more than once", symbol_index),
+            IrProblem::NotAFunction(symbol_index) =>
+                write!(f, "symbol {} is defined as a function but is not a function symbol", symbol_index),
+            IrProblem::UnsupportedRegister { register, reason } =>
+                write!(f, "register {:?} is not supported: {}", register, reason),
+            IrProblem::LineNumberTooLarge(line) =>
+                write!(f, "source line {} is larger than a line table can record", line),
+            IrProblem::ValueTooLargeForWidth { value, width_in_bits } =>
+                write!(f, "value {} does not fit in {} bits", value, width_in_bits),
+            IrProblem::ShiftCountTooLarge { count, width_in_bits } =>
+                write!(f, "shift count {} is not less than the {} bit width", count, width_in_bits),
+            IrProblem::UnsupportedWidth { instruction, width_in_bits } =>
+                write!(f, "{} has no {} bit form", instruction, width_in_bits),
+            IrProblem::StackUnbalancedAtReturn { depth } =>
+                write!(f, "returns with {} bytes left on the stack", depth),
+            IrProblem::StackDepthMismatchAtLabel { label, depth, expected } =>
+                write!(f, "reaches label {} with {} bytes on the stack where other paths have {}", label, depth, expected),
+        }
+    }
+}
+
+impl fmt::Display for IrDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.instruction_index {
+            Some(instruction_index) => write!(f, "instruction {}: {}", instruction_index, self.problem),
+            None => write!(f, "{}", self.problem)
+        }
+    }
+}
+
+/// Checks a unit before it is lowered, returning every problem found rather than stopping at the first.
+pub fn verify_intermediate_representation(ir: &IntermediateRepresentation) -> Vec<IrDiagnostic> {
+    verify_intermediate_representation_for_format(ir, ObjectFormat::Coff)
+}
+
+pub fn verify_intermediate_representation_for_format(ir: &IntermediateRepresentation, format: ObjectFormat) -> Vec<IrDiagnostic> {
+    let mut diagnostics = vec!();
+    verify_symbols(ir, &mut diagnostics);
+    verify_instructions(ir, format, &mut diagnostics);
+    verify_labels(ir, &mut diagnostics);
+    verify_functions(ir, &mut diagnostics);
+    verify_stack_balance(ir, &mut diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.instruction_index);
+    diagnostics
+}
+
+fn verify_symbols(ir: &IntermediateRepresentation, diagnostics: &mut Vec<IrDiagnostic>) {
+    let mut names = HashMap::new();
+
+    for (symbol_index, symbol) in ir.symbols.iter().enumerate() {
+        let name = get_byte_code_symbol_name(symbol);
+        if names.insert(name, symbol_index).is_some() {
+            diagnostics.push(ir_diagnostic(None, IrProblem::DuplicateSymbolName { symbol_index, name: string(name) }));
+        }
+
+        if let ByteCodeSymbol::DataSectionItem { data_item, .. } = *symbol {
+            if data_item as usize >= ir.data.len() {
+                diagnostics.push(ir_diagnostic(
+                    None,
+                    IrProblem::SymbolDataItemOutOfRange { symbol_index, data_item, number_of_data_items: ir.data.len() }
+                ));
+            }
+        }
+    }
+}
+
+fn verify_instructions(ir: &IntermediateRepresentation, format: ObjectFormat, diagnostics: &mut Vec<IrDiagnostic>) {
+    for (instruction_index, instruction) in ir.byte_code.iter().enumerate() {
+        let mut problems = vec!();
+
+        match *instruction {
+            ByteCodeInstruction::CallToSymbol(symbol_index) | 
+            ByteCodeInstruction::MoveSymbolToReg32 { symbol_index, .. } | 
+            ByteCodeInstruction::DefineFunction(symbol_index)
+                if symbol_index as usize >= ir.symbols.len() =>
+                problems.push(IrProblem::SymbolIndexOutOfRange { symbol_index, number_of_symbols: ir.symbols.len() }),
+            ByteCodeInstruction::LoadDataSectionAddressToReg64 { data_item, .. } if data_item as usize >= ir.data.len() =>
+                problems.push(IrProblem::DataItemIndexOutOfRange { data_item, number_of_data_items: ir.data.len() }),
+            ByteCodeInstruction::DefineLabel(label) | ByteCodeInstruction::Jump(label) | ByteCodeInstruction::JumpIf { label, .. }
+                if label as usize >= ir.labels.len() =>
+                problems.push(IrProblem::LabelIndexOutOfRange { label, number_of_labels: ir.labels.len() }),
+            ByteCodeInstruction::SourceLocation { line, .. } if line > MAX_SOURCE_LINE_NUMBER =>
+                problems.push(IrProblem::LineNumberTooLarge(line)),
+            ByteCodeInstruction::ArithmeticRegToReg { operation: ByteCodeArithmetic::Multiply, width, .. } => {
+                if let Err(CoffError::UnsupportedWidth { instruction, width_in_bits }) = get_multiply_operand_size(width) {
+                    problems.push(IrProblem::UnsupportedWidth { instruction, width_in_bits });
+                }
+            },
+            ByteCodeInstruction::ArithmeticValueToReg { operation, width, value, .. } => {
+                if operation == ByteCodeArithmetic::Multiply {
+                    if let Err(CoffError::UnsupportedWidth { instruction, width_in_bits }) = get_multiply_operand_size(width) {
+                        problems.push(IrProblem::UnsupportedWidth { instruction, width_in_bits });
+                    }
+                }
+                if let Err(CoffError::ValueTooLargeForWidth { value, width_in_bits }) = check_value_fits_width(value, width) {
+                    problems.push(IrProblem::ValueTooLargeForWidth { value, width_in_bits });
+                }
+            },
+            ByteCodeInstruction::ShiftRegByValue { width, value, .. } => {
+                if let Err(CoffError::ShiftCountTooLarge { count, width_in_bits }) = check_shift_count_fits_width(value, width) {
+                    problems.push(IrProblem::ShiftCountTooLarge { count, width_in_bits });
+                }
+            },
+            ByteCodeInstruction::ShiftRegByCount { register, .. } => {
+                if let Err(CoffError::UnsupportedRegister { register, reason }) = get_shifted_register_for_format(register, format) {
+                    problems.push(IrProblem::UnsupportedRegister { register, reason });
+                }
+            },
+            ByteCodeInstruction::Divide { divisor, .. } => {
+                if let Err(CoffError::UnsupportedRegister { register, reason }) = get_divisor_register_for_format(divisor, format) {
+                    problems.push(IrProblem::UnsupportedRegister { register, reason });
+                }
+            },
+            _ => {}
+        }
+
+        for register in get_instruction_registers(instruction) {
+            if let Err(CoffError::UnsupportedRegister { register, reason }) = get_register_for_format(register, format) {
+                problems.push(IrProblem::UnsupportedRegister { register, reason });
+            }
+        }
+
+        for problem in problems {
+            diagnostics.push(ir_diagnostic(Some(instruction_index), problem));
+        }
+    }
+}
+
+fn verify_labels(ir: &IntermediateRepresentation, diagnostics: &mut Vec<IrDiagnostic>) {
+    let mut defined = vec![false; ir.labels.len()];
+
+    for (instruction_index, instruction) in ir.byte_code.iter().enumerate() {
+        if let ByteCodeInstruction::DefineLabel(label) = *instruction {
+            if let Some(defined) = defined.get_mut(label as usize) {
+                if *defined {
+                    diagnostics.push(ir_diagnostic(Some(instruction_index), IrProblem::LabelDefinedTwice(label)));
+                }
+                *defined = true;
+            }
+        }
+    }
+
+    for (instruction_index, instruction) in ir.byte_code.iter().enumerate() {
+        if let ByteCodeInstruction::Jump(label) | ByteCodeInstruction::JumpIf { label, .. } = *instruction {
+            if defined.get(label as usize) == Some(&false) {
+                diagnostics.push(ir_diagnostic(Some(instruction_index), IrProblem::LabelNotDefined(label)));
+            }
+        }
+    }
+}
+
+fn verify_functions(ir: &IntermediateRepresentation, diagnostics: &mut Vec<IrDiagnostic>) {
+    let mut defined = vec![false; ir.symbols.len()];
+
+    for (instruction_index, instruction) in ir.byte_code.iter().enumerate() {
+        if let ByteCodeInstruction::DefineFunction(symbol_index) = *instruction {
+            match ir.symbols.get(symbol_index as usize) {
+                Some(ByteCodeSymbol::Function { .. }) => {
+                    if defined[symbol_index as usize] {
+                        diagnostics.push(ir_diagnostic(Some(instruction_index), IrProblem::FunctionDefinedTwice(symbol_index)));
+                    }
+                    defined[symbol_index as usize] = true;
+                },
+                Some(_) => diagnostics.push(ir_diagnostic(Some(instruction_index), IrProblem::NotAFunction(symbol_index))),
+                None => {}
+            }
+        }
+    }
+
+    for (symbol_index, symbol) in ir.symbols.iter().enumerate() {
+        if let ByteCodeSymbol::Function { .. } = symbol {
+            if !defined[symbol_index] {
+                diagnostics.push(ir_diagnostic(None, IrProblem::FunctionNotDefined { symbol_index }));
+            }
+        }
+    }
+}
+
+/// The byte adjustments of the stack pointer are encoded as sign extended imm8, so values above 127 move it the other way.
+/// The depth is carried to labels by the jumps to them and becomes unknown after an unconditional
+/// transfer or a stack pointer change that cannot be followed, so only known depths are checked.
+fn verify_stack_balance(ir: &IntermediateRepresentation, diagnostics: &mut Vec<IrDiagnostic>) {
+    let mut depth = Some(0);
+    let mut frame_depth = None;
+    let mut label_depths: HashMap<u32, i64> = HashMap::new();
+
+    for (instruction_index, instruction) in ir.byte_code.iter().enumerate() {
+        match *instruction {
+            ByteCodeInstruction::PushReg64(_) =>
+                depth = depth.map(|depth| depth + STACK_SLOT_SIZE),
+            ByteCodeInstruction::PopReg64(register) => {
+                depth = depth.map(|depth| depth - STACK_SLOT_SIZE);
+                if register == base_pointer_register() {
+                    frame_depth = None;
+                }
+            },
+            ByteCodeInstruction::SubValueFromReg8 { value, from: ByteCodeRegister::StackPointer } =>
+                depth = depth.map(|depth| depth + value as i8 as i64),
+            ByteCodeInstruction::AddValueToReg8 { value, to: ByteCodeRegister::StackPointer } =>
+                depth = depth.map(|depth| depth - value as i8 as i64),
+            ByteCodeInstruction::ArithmeticValueToReg { operation, width: ByteCodeWidth::QWord, value, to: ByteCodeRegister::StackPointer } =>
+                depth = match operation {
+                    ByteCodeArithmetic::Subtract => depth.map(|depth| depth + value as i64),
+                    ByteCodeArithmetic::Add => depth.map(|depth| depth - value as i64),
+                    ByteCodeArithmetic::Compare => depth,
+                    _ => None
+                },
+            ByteCodeInstruction::MoveRegToReg64 { from: ByteCodeRegister::StackPointer, to: ByteCodeRegister::BasePointer } =>
+                frame_depth = depth,
+            ByteCodeInstruction::MoveRegToReg64 { from: ByteCodeRegister::BasePointer, to: ByteCodeRegister::StackPointer } =>
+                depth = frame_depth,
+            ByteCodeInstruction::DefineFunction(_) => {
+                depth = Some(0);
+                frame_depth = None;
+            },
+            ByteCodeInstruction::DefineLabel(label) => {
+                depth = merge_label_depth(&mut label_depths, label, depth, instruction_index, diagnostics);
+            },
+            ByteCodeInstruction::Jump(label) => {
+                merge_label_depth(&mut label_depths, label, depth, instruction_index, diagnostics);
+                depth = None;
+            },
+            ByteCodeInstruction::JumpIf { label, .. } => {
+                merge_label_depth(&mut label_depths, label, depth, instruction_index, diagnostics);
+            },
+            ByteCodeInstruction::Return => {
+                if let Some(depth) = depth.filter(|depth| *depth != 0) {
+                    diagnostics.push(ir_diagnostic(Some(instruction_index), IrProblem::StackUnbalancedAtReturn { depth }));
+                }
+                depth = None;
+            },
+            _ => {
+                if get_instruction_written_register(instruction) == Some(stack_pointer_register()) {
+                    depth = None;
+                }
+                if get_instruction_written_register(instruction) == Some(base_pointer_register()) {
+                    frame_depth = None;
+                }
+            }
+        }
+    }
+}
+
+fn merge_label_depth(
+    label_depths: &mut HashMap<u32, i64>,
+    label: u32,
+    depth: Option<i64>,
+    instruction_index: usize,
+    diagnostics: &mut Vec<IrDiagnostic>
+) -> Option<i64> {
+    match (label_depths.get(&label).copied(), depth) {
+        (Some(expected), Some(depth)) if expected != depth => {
+            diagnostics.push(ir_diagnostic(Some(instruction_index), IrProblem::StackDepthMismatchAtLabel { label, depth, expected }));
+            Some(expected)
+        },
+        (Some(expected), _) => Some(expected),
+        (None, Some(depth)) => {
+            label_depths.insert(label, depth);
+            Some(depth)
+        },
+        (None, None) => None
+    }
+}
+
+fn get_instruction_registers(instruction: &ByteCodeInstruction) -> Vec<ByteCodeRegister> {
+    match *instruction {
+        ByteCodeInstruction::AddValueToReg8 { to, .. } => vec!(to),
+        ByteCodeInstruction::SubValueFromReg8 { from, .. } => vec!(from),
+        ByteCodeInstruction::MoveSymbolToReg32 { to, .. } => vec!(to),
+        ByteCodeInstruction::MoveValueToReg32 { to, .. } => vec!(to),
+        ByteCodeInstruction::MoveRegToReg64 { from, to } => vec!(from, to),
+        ByteCodeInstruction::MoveValueToRegPlusOffset32 { to, .. } => vec!(to),
+        ByteCodeInstruction::MoveRegToRegPlusOffset32 { from, to, .. } => vec!(from, to),
+        ByteCodeInstruction::MoveRegToRegPlusOffset64 { from, to, .. } => vec!(from, to),
+        ByteCodeInstruction::MoveRegPlusOffsetToReg32 { from, to, .. } => vec!(from, to),
+        ByteCodeInstruction::MoveRegPlusOffsetToReg64 { from, to, .. } => vec!(from, to),
+        ByteCodeInstruction::LoadDataSectionAddressToReg64 { to, .. } => vec!(to),
+        ByteCodeInstruction::PushReg64(register) => vec!(register),
+        ByteCodeInstruction::PopReg64(register) => vec!(register),
+        ByteCodeInstruction::ZeroReg64(register) => vec!(register),
+        ByteCodeInstruction::ArithmeticRegToReg { from, to, .. } => vec!(from, to),
+        ByteCodeInstruction::ArithmeticValueToReg { to, .. } => vec!(to),
+        ByteCodeInstruction::NotReg { register, .. } => vec!(register),
+        ByteCodeInstruction::NegateReg { register, .. } => vec!(register),
+        ByteCodeInstruction::ShiftRegByValue { register, .. } => vec!(register),
+        ByteCodeInstruction::ShiftRegByCount { register, .. } => vec!(register, shift_count_register()),
+        ByteCodeInstruction::Divide { .. } => vec!(call_return_arg_register(0), remainder_register()),
+        ByteCodeInstruction::CallToSymbol(_) |
+        ByteCodeInstruction::DefineFunction(_) |
+        ByteCodeInstruction::DefineLabel(_) |
+        ByteCodeInstruction::SourceLocation { .. } |
+        ByteCodeInstruction::Jump(_) |
+        ByteCodeInstruction::JumpIf { .. } |
+        ByteCodeInstruction::Return => vec!()
+    }
+}
+
+fn get_instruction_written_register(instruction: &ByteCodeInstruction) -> Option<ByteCodeRegister> {
+    match *instruction {
+        ByteCodeInstruction::MoveSymbolToReg32 { to, .. } |
+        ByteCodeInstruction::MoveValueToReg32 { to, .. } |
+        ByteCodeInstruction::MoveRegToReg64 { to, .. } |
+        ByteCodeInstruction::MoveRegPlusOffsetToReg32 { to, .. } |
+        ByteCodeInstruction::MoveRegPlusOffsetToReg64 { to, .. } |
+        ByteCodeInstruction::LoadDataSectionAddressToReg64 { to, .. } |
+        ByteCodeInstruction::AddValueToReg8 { to, .. } => Some(to),
+        ByteCodeInstruction::SubValueFromReg8 { from, .. } => Some(from),
+        ByteCodeInstruction::ArithmeticRegToReg { operation, to, .. } |
+        ByteCodeInstruction::ArithmeticValueToReg { operation, to, .. } if operation != ByteCodeArithmetic::Compare => Some(to),
+        ByteCodeInstruction::PopReg64(register) |
+        ByteCodeInstruction::ZeroReg64(register) |
+        ByteCodeInstruction::NotReg { register, .. } |
+        ByteCodeInstruction::NegateReg { register, .. } |
+        ByteCodeInstruction::ShiftRegByValue { register, .. } |
+        ByteCodeInstruction::ShiftRegByCount { register, .. } => Some(register),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(byte_code: Vec<ByteCodeInstruction>) -> Vec<IrProblem> {
+        let mut ir = create_intermediate_representation(compilation_unit_id(1), string("test.hep"));
+        add_symbol(&mut ir.symbols, external_function(string("main")));
+        add_byte_code(&mut ir.byte_code, define_function_instruction(0));
+        for instruction in byte_code {
+            add_byte_code(&mut ir.byte_code, instruction);
+        }
+        add_byte_code(&mut ir.byte_code, ret_instruction());
+        verify_intermediate_representation(&ir).into_iter().map(|diagnostic| diagnostic.problem).collect()
+    }
+
+    #[test]
+    fn immediates_that_do_not_fit_the_width_are_reported() {
+        assert_eq!(
+            problems(vec!(arithmetic_value_to_reg_instruction(ByteCodeArithmetic::Add, ByteCodeWidth::Byte, 256, call_arg_register(0)))),
+            [IrProblem::ValueTooLargeForWidth { value: 256, width_in_bits: 8 }]
+        );
+        assert_eq!(
+            problems(vec!(shift_reg_by_value_instruction(ByteCodeShift::Left, ByteCodeWidth::DWord, 32, call_arg_register(0)))),
+            [IrProblem::ShiftCountTooLarge { count: 32, width_in_bits: 32 }]
+        );
+        assert!(problems(vec!(
+            arithmetic_value_to_reg_instruction(ByteCodeArithmetic::Add, ByteCodeWidth::Byte, -128, call_arg_register(0)),
+            shift_reg_by_value_instruction(ByteCodeShift::Left, ByteCodeWidth::QWord, 63, call_arg_register(0))
+        )).is_empty());
+    }
+
+    #[test]
+    fn byte_multiplies_are_reported() {
+        let unsupported = [IrProblem::UnsupportedWidth { instruction: "multiply", width_in_bits: 8 }];
+        assert_eq!(
+            problems(vec!(arithmetic_reg_to_reg_instruction(ByteCodeArithmetic::Multiply, ByteCodeWidth::Byte, call_arg_register(0), call_arg_register(1)))),
+            unsupported
+        );
+        assert_eq!(
+            problems(vec!(arithmetic_value_to_reg_instruction(ByteCodeArithmetic::Multiply, ByteCodeWidth::Byte, 3, call_arg_register(0)))),
+            unsupported
+        );
+    }
+
+    #[test]
+    fn fixed_registers_are_checked_for_each_format() {
+        let mut ir = create_intermediate_representation(compilation_unit_id(1), string("test.hep"));
+        add_symbol(&mut ir.symbols, external_function(string("main")));
+        for instruction in [
+            define_function_instruction(0),
+            shift_reg_by_count_instruction(ByteCodeShift::Left, ByteCodeWidth::QWord, call_arg_register(0)),
+            shift_reg_by_count_instruction(ByteCodeShift::Left, ByteCodeWidth::QWord, call_arg_register(3)),
+            divide_instruction(false, ByteCodeWidth::QWord, remainder_register()),
+            ret_instruction()
+        ] {
+            add_byte_code(&mut ir.byte_code, instruction);
+        }
+        let problem_indexes = |format| -> Vec<Option<usize>> {
+            verify_intermediate_representation_for_format(&ir, format).into_iter().map(|diagnostic| diagnostic.instruction_index).collect()
+        };
+
+        assert_eq!(problem_indexes(ObjectFormat::Coff), [Some(1), Some(3)]);
+        assert_eq!(problem_indexes(ObjectFormat::Elf), [Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn byte_stack_adjustments_are_sign_extended() {
+        assert!(problems(vec!(
+            sub_value_from_reg_8_instruction(0xF0, stack_pointer_register()),
+            add_value_to_reg_8_instruction(0xF0, stack_pointer_register())
+        )).is_empty());
+        assert_eq!(
+            problems(vec!(sub_value_from_reg_8_instruction(0xF0, stack_pointer_register()))),
+            [IrProblem::StackUnbalancedAtReturn { depth: -16 }]
+        );
+    }
+}
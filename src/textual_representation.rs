@@ -0,0 +1,765 @@
+use std::*;
+use crate::intemediate_representation::*;
+use crate::machine_code::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IrParseError {
+    MissingUnit,
+    UnitDeclaredTwice { line: usize },
+    UnexpectedEndOfLine { line: usize, expected: &'static str },
+    UnexpectedToken { line: usize, found: String, expected: &'static str },
+    UnknownInstruction { line: usize, mnemonic: String },
+    ValueOutOfRange { line: usize, value: i64, expected: &'static str },
+    DeclarationOutOfOrder { line: usize, declaration: &'static str, index: u32, expected: u32 },
+    UnterminatedString { line: usize },
+    InvalidEscape { line: usize, escape: String }
+}
+
+impl fmt::Display for IrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrParseError::MissingUnit =>
+                write!(f, "no unit declaration found"),
+            IrParseError::UnitDeclaredTwice { line } =>
+                write!(f, "line {}: unit is declared more than once", line),
+            IrParseError::UnexpectedEndOfLine { line, expected } =>
+                write!(f, "line {}: expected {} before the end of the line", line, expected),
+            IrParseError::UnexpectedToken { line, found, expected } =>
+                write!(f, "line {}: expected {} but found {}", line, expected, found),
+            IrParseError::UnknownInstruction { line, mnemonic } =>
+                write!(f, "line {}: unknown instruction {}", line, mnemonic),
+            IrParseError::ValueOutOfRange { line, value, expected } =>
+                write!(f, "line {}: {} is out of range for {}", line, value, expected),
+            IrParseError::DeclarationOutOfOrder { line, declaration, index, expected } =>
+                write!(f, "line {}: {} {} is declared where {} {} was expected", line, declaration, index, declaration, expected),
+            IrParseError::UnterminatedString { line } =>
+                write!(f, "line {}: string is not terminated", line),
+            IrParseError::InvalidEscape { line, escape } =>
+                write!(f, "line {}: invalid escape \\{}", line, escape),
+        }
+    }
+}
+
+impl error::Error for IrParseError {}
+
+/// Parses a compilation unit from its textual form, as produced by [`intermediate_representation_to_text`].
+///
+/// The unit is declared first, followed by its data items, symbols and labels, each numbered in order,
+/// and then one instruction per line. Everything after a `;` is a comment:
+///
+/// ```text
+/// unit 1 "main.hep"
+/// data 0 read_only_string "hello\n"
+/// symbol 0 foreign_external "print"
+/// symbol 1 function "main" external
+/// label 0 "done"
+///     define_function 1 ; main
+///     location 3 5
+///     push_64 bp
+///     load_data_address_64 0 arg0
+///     call 0 ; print
+///     return
+/// ```
+pub fn read_intermediate_representation_from_text(text: &str) -> Result<IntermediateRepresentation, IrParseError> {
+    let mut ir: Option<IntermediateRepresentation> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let mut tokens = tokenise_line(index + 1, line)?;
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let keyword = tokens.next_word("declaration or instruction")?;
+        if keyword == "unit" {
+            if ir.is_some() {
+                return Err(IrParseError::UnitDeclaredTwice { line: tokens.line });
+            }
+            ir = Some(parse_unit(&mut tokens)?);
+            tokens.end()?;
+            continue;
+        }
+
+        let ir = match ir.as_mut() {
+            Some(ir) => ir,
+            None => return Err(IrParseError::UnexpectedToken { line: tokens.line, found: keyword, expected: "unit declaration" })
+        };
+
+        match keyword.as_str() {
+            "data" => {
+                tokens.next_declaration_index("data", ir.data.len())?;
+                add_data_item(&mut ir.data, parse_data_item(&mut tokens)?);
+            },
+            "symbol" => {
+                tokens.next_declaration_index("symbol", ir.symbols.len())?;
+                add_symbol(&mut ir.symbols, parse_symbol(&mut tokens)?);
+            },
+            "label" => {
+                tokens.next_declaration_index("label", ir.labels.len())?;
+                add_label(&mut ir.labels, label(tokens.next_string("label name")?));
+            },
+            mnemonic => add_byte_code(&mut ir.byte_code, parse_instruction(mnemonic, &mut tokens)?)
+        }
+        tokens.end()?;
+    }
+
+    ir.ok_or(IrParseError::MissingUnit)
+}
+
+pub fn intermediate_representation_to_text(ir: &IntermediateRepresentation) -> String {
+    let mut text = format!("unit {} {}\n", get_compilation_unit_number(ir.id), quote(&ir.filename));
+
+    if !ir.data.is_empty() {
+        text.push('\n');
+    }
+    for (index, item) in ir.data.iter().enumerate() {
+        text.push_str(&format!("data {} {}\n", index, data_item_to_text(item)));
+    }
+
+    if !ir.symbols.is_empty() {
+        text.push('\n');
+    }
+    for (index, symbol) in ir.symbols.iter().enumerate() {
+        text.push_str(&format!("symbol {} {}\n", index, symbol_to_text(symbol)));
+    }
+
+    if !ir.labels.is_empty() {
+        text.push('\n');
+    }
+    for (index, label) in ir.labels.iter().enumerate() {
+        text.push_str(&format!("label {} {}\n", index, quote(&label.name)));
+    }
+
+    if !ir.byte_code.is_empty() {
+        text.push('\n');
+    }
+    for instruction in &ir.byte_code {
+        text.push_str(&format!("    {}{}\n", instruction_to_text(instruction), reference_comment(ir, instruction)));
+    }
+
+    text
+}
+
+pub fn instruction_to_text(instruction: &ByteCodeInstruction) -> String {
+    match *instruction {
+        ByteCodeInstruction::CallToSymbol(symbol_index) =>
+            format!("call {}", symbol_index),
+        ByteCodeInstruction::AddValueToReg8 { value, to } =>
+            format!("add_value_8 {} {}", value, register_to_text(to)),
+        ByteCodeInstruction::SubValueFromReg8 { value, from } =>
+            format!("sub_value_8 {} {}", value, register_to_text(from)),
+        ByteCodeInstruction::MoveSymbolToReg32 { symbol_index, to } =>
+            format!("move_symbol_32 {} {}", symbol_index, register_to_text(to)),
+        ByteCodeInstruction::MoveValueToReg32 { value, to } =>
+            format!("move_value_32 {} {}", value, register_to_text(to)),
+        ByteCodeInstruction::MoveRegToReg64 { from, to } =>
+            format!("move_reg_64 {} {}", register_to_text(from), register_to_text(to)),
+        ByteCodeInstruction::MoveValueToRegPlusOffset32 { value, to, offset } =>
+            format!("move_value_to_offset_32 {} {} {}", value, register_to_text(to), offset),
+        ByteCodeInstruction::MoveRegToRegPlusOffset32 { from, to, offset } =>
+            format!("move_reg_to_offset_32 {} {} {}", register_to_text(from), register_to_text(to), offset),
+        ByteCodeInstruction::MoveRegToRegPlusOffset64 { from, to, offset } =>
+            format!("move_reg_to_offset_64 {} {} {}", register_to_text(from), register_to_text(to), offset),
+        ByteCodeInstruction::MoveRegPlusOffsetToReg32 { from, offset, to } =>
+            format!("move_offset_to_reg_32 {} {} {}", register_to_text(from), offset, register_to_text(to)),
+        ByteCodeInstruction::MoveRegPlusOffsetToReg64 { from, offset, to } =>
+            format!("move_offset_to_reg_64 {} {} {}", register_to_text(from), offset, register_to_text(to)),
+        ByteCodeInstruction::LoadDataSectionAddressToReg64 { data_item, to } =>
+            format!("load_data_address_64 {} {}", data_item, register_to_text(to)),
+        ByteCodeInstruction::PushReg64(register) =>
+            format!("push_64 {}", register_to_text(register)),
+        ByteCodeInstruction::PopReg64(register) =>
+            format!("pop_64 {}", register_to_text(register)),
+        ByteCodeInstruction::ZeroReg64(register) =>
+            format!("zero_64 {}", register_to_text(register)),
+        ByteCodeInstruction::ArithmeticRegToReg { operation, width, from, to } =>
+            format!("{} {} {} {}", arithmetic_to_text(operation), width_to_text(width), register_to_text(from), register_to_text(to)),
+        ByteCodeInstruction::ArithmeticValueToReg { operation, width, value, to } =>
+            format!("{} {} {} {}", arithmetic_to_text(operation), width_to_text(width), value, register_to_text(to)),
+        ByteCodeInstruction::NotReg { width, register } =>
+            format!("not {} {}", width_to_text(width), register_to_text(register)),
+        ByteCodeInstruction::NegateReg { width, register } =>
+            format!("neg {} {}", width_to_text(width), register_to_text(register)),
+        ByteCodeInstruction::ShiftRegByValue { shift, width, value, register } =>
+            format!("{} {} {} {}", shift_to_text(shift), width_to_text(width), value, register_to_text(register)),
+        ByteCodeInstruction::ShiftRegByCount { shift, width, register } =>
+            format!("{} {} {} {}", shift_to_text(shift), width_to_text(width), register_to_text(shift_count_register()), register_to_text(register)),
+        ByteCodeInstruction::Divide { signed, width, divisor } =>
+            format!("{} {} {}", if signed { "idiv" } else { "div" }, width_to_text(width), register_to_text(divisor)),
+        ByteCodeInstruction::DefineFunction(symbol_index) =>
+            format!("define_function {}", symbol_index),
+        ByteCodeInstruction::DefineLabel(label) =>
+            format!("define_label {}", label),
+        ByteCodeInstruction::SourceLocation { line, column } =>
+            format!("location {} {}", line, column),
+        ByteCodeInstruction::Jump(label) =>
+            format!("jump {}", label),
+        ByteCodeInstruction::JumpIf { condition, label } =>
+            format!("jump_if {} {}", condition_to_text(condition), label),
+        ByteCodeInstruction::Return =>
+            string("return")
+    }
+}
+
+fn reference_comment(ir: &IntermediateRepresentation, instruction: &ByteCodeInstruction) -> String {
+    let name = match *instruction {
+        ByteCodeInstruction::CallToSymbol(symbol_index) | 
+        ByteCodeInstruction::MoveSymbolToReg32 { symbol_index, .. } | 
+        ByteCodeInstruction::DefineFunction(symbol_index) =>
+            ir.symbols.get(symbol_index as usize).map(get_byte_code_symbol_name),
+        ByteCodeInstruction::DefineLabel(label) | ByteCodeInstruction::Jump(label) | ByteCodeInstruction::JumpIf { label, .. } =>
+            ir.labels.get(label as usize).map(|label| label.name.as_str()),
+        _ => None
+    };
+    match name {
+        Some(name) => format!(" ; {}", name),
+        None => String::new()
+    }
+}
+
+fn parse_unit(tokens: &mut LineTokens) -> Result<IntermediateRepresentation, IrParseError> {
+    let id = tokens.next_number("unit id")?;
+    let id = usize::try_from(id).map_err(|_| tokens.out_of_range(id, "unit id"))?;
+    let filename = tokens.next_string("file name")?;
+    Ok(create_intermediate_representation(compilation_unit_id(id), filename))
+}
+
+fn parse_data_item(tokens: &mut LineTokens) -> Result<ByteCodeDataItem, IrParseError> {
+    let kind = tokens.next_word("data item kind")?;
+    match kind.as_str() {
+        "string" => Ok(string_data_item(tokens.next_string("string value")?)),
+        "read_only_string" => Ok(read_only_string_data_item(tokens.next_string("string value")?)),
+        "read_only_bytes" => {
+            let mut bytes = vec!();
+            while !tokens.is_empty() {
+                bytes.push(tokens.next_u8("byte")?);
+            }
+            Ok(read_only_bytes_data_item(bytes))
+        },
+        "zero_initialised" => {
+            let size = tokens.next_u32("size")?;
+            Ok(zero_initialised_data_item(size, tokens.next_u32("alignment")?))
+        },
+        _ => Err(IrParseError::UnexpectedToken { line: tokens.line, found: kind, expected: "data item kind" })
+    }
+}
+
+fn parse_symbol(tokens: &mut LineTokens) -> Result<ByteCodeSymbol, IrParseError> {
+    let kind = tokens.next_word("symbol kind")?;
+    let name = tokens.next_string("symbol name")?;
+    match kind.as_str() {
+        "data_section_item" => Ok(data_section_item(name, tokens.next_u32("data item index")?)),
+        "foreign_external" => Ok(foreign_external(name)),
+        "absolute_external" => Ok(absolute_external(name, tokens.next_u32("value")?)),
+        "external_code_label" => Ok(external_code_label(name, tokens.next_u32("position")?)),
+        "function" => {
+            let linkage = tokens.next_word("linkage")?;
+            match linkage.as_str() {
+                "external" => Ok(external_function(name)),
+                "static" => Ok(static_function(name)),
+                _ => Err(IrParseError::UnexpectedToken { line: tokens.line, found: linkage, expected: "linkage" })
+            }
+        },
+        _ => Err(IrParseError::UnexpectedToken { line: tokens.line, found: kind, expected: "symbol kind" })
+    }
+}
+
+fn parse_instruction(mnemonic: &str, tokens: &mut LineTokens) -> Result<ByteCodeInstruction, IrParseError> {
+    if let Some(operation) = arithmetic_from_text(mnemonic) {
+        let width = tokens.next_width()?;
+        let instruction = match tokens.next_register_or_number("register or value")? {
+            RegisterOrNumber::Register(from) => arithmetic_reg_to_reg_instruction(operation, width, from, tokens.next_register()?),
+            RegisterOrNumber::Number(value) => {
+                let value = i32::try_from(value).map_err(|_| tokens.out_of_range(value, "32 bit signed value"))?;
+                arithmetic_value_to_reg_instruction(operation, width, value, tokens.next_register()?)
+            }
+        };
+        return Ok(instruction);
+    }
+
+    if let Some(shift) = shift_from_text(mnemonic) {
+        let width = tokens.next_width()?;
+        let instruction = match tokens.next_register_or_number("shift count or count register")? {
+            RegisterOrNumber::Register(ByteCodeRegister::ShiftCount) => shift_reg_by_count_instruction(shift, width, tokens.next_register()?),
+            RegisterOrNumber::Register(register) =>
+                return Err(IrParseError::UnexpectedToken { line: tokens.line, found: register_to_text(register), expected: "shift count or count register" }),
+            RegisterOrNumber::Number(value) => {
+                let value = u8::try_from(value).map_err(|_| tokens.out_of_range(value, "shift count"))?;
+                shift_reg_by_value_instruction(shift, width, value, tokens.next_register()?)
+            }
+        };
+        return Ok(instruction);
+    }
+
+    Ok(match mnemonic {
+        "call" => call_to_symbol_instruction(tokens.next_u32("symbol index")?),
+        "add_value_8" => add_value_to_reg_8_instruction(tokens.next_u8("value")?, tokens.next_register()?),
+        "sub_value_8" => sub_value_from_reg_8_instruction(tokens.next_u8("value")?, tokens.next_register()?),
+        "move_symbol_32" => move_symbol_to_reg_32_instruction(tokens.next_u32("symbol index")?, tokens.next_register()?),
+        "move_value_32" => move_value_to_reg_32_instruction(tokens.next_u32("value")?, tokens.next_register()?),
+        "move_reg_64" => move_reg_to_reg_64_instruction(tokens.next_register()?, tokens.next_register()?),
+        "move_value_to_offset_32" =>
+            move_value_to_reg_plus_offset_32_instruction(tokens.next_u32("value")?, tokens.next_register()?, tokens.next_u8("offset")?),
+        "move_reg_to_offset_32" =>
+            move_reg_to_reg_plus_offset_32_instruction(tokens.next_register()?, tokens.next_register()?, tokens.next_u8("offset")?),
+        "move_reg_to_offset_64" =>
+            move_reg_to_reg_plus_offset_64_instruction(tokens.next_register()?, tokens.next_register()?, tokens.next_u8("offset")?),
+        "move_offset_to_reg_32" =>
+            move_reg_plus_offset_to_reg_32_instruction(tokens.next_register()?, tokens.next_u8("offset")?, tokens.next_register()?),
+        "move_offset_to_reg_64" =>
+            move_reg_plus_offset_to_reg_64_instruction(tokens.next_register()?, tokens.next_u8("offset")?, tokens.next_register()?),
+        "load_data_address_64" => load_data_section_address_to_reg_64(tokens.next_u32("data item index")?, tokens.next_register()?),
+        "push_64" => push_reg_64_instruction(tokens.next_register()?),
+        "pop_64" => pop_reg_64_instruction(tokens.next_register()?),
+        "zero_64" => zero_reg_64_instruction(tokens.next_register()?),
+        "not" => not_reg_instruction(tokens.next_width()?, tokens.next_register()?),
+        "neg" => negate_reg_instruction(tokens.next_width()?, tokens.next_register()?),
+        "div" | "idiv" => divide_instruction(mnemonic == "idiv", tokens.next_width()?, tokens.next_register()?),
+        "define_function" => define_function_instruction(tokens.next_u32("symbol index")?),
+        "define_label" => define_label_instruction(tokens.next_u32("label index")?),
+        "location" => source_location_instruction(tokens.next_u32("source line")?, tokens.next_u16("source column")?),
+        "jump" => jump_instruction(tokens.next_u32("label index")?),
+        "jump_if" => jump_if_instruction(tokens.next_condition()?, tokens.next_u32("label index")?),
+        "return" => ret_instruction(),
+        _ => return Err(IrParseError::UnknownInstruction { line: tokens.line, mnemonic: string(mnemonic) })
+    })
+}
+
+fn data_item_to_text(item: &ByteCodeDataItem) -> String {
+    match item {
+        ByteCodeDataItem::String { value } => format!("string {}", quote(value)),
+        ByteCodeDataItem::ReadOnlyString { value } => format!("read_only_string {}", quote(value)),
+        ByteCodeDataItem::ReadOnlyBytes { value } => {
+            let bytes: Vec<String> = value.iter().map(|byte| format!(" 0x{:02x}", byte)).collect();
+            format!("read_only_bytes{}", bytes.concat())
+        },
+        ByteCodeDataItem::ZeroInitialised { size, alignment } => format!("zero_initialised {} {}", size, alignment)
+    }
+}
+
+fn symbol_to_text(symbol: &ByteCodeSymbol) -> String {
+    match symbol {
+        ByteCodeSymbol::DataSectionItem { name, data_item } => format!("data_section_item {} {}", quote(name), data_item),
+        ByteCodeSymbol::ForeignExternal { name } => format!("foreign_external {}", quote(name)),
+        ByteCodeSymbol::AbsoluteExternal { name, value } => format!("absolute_external {} {}", quote(name), value),
+        ByteCodeSymbol::ExternalCodeLabel { name, position } => format!("external_code_label {} {}", quote(name), position),
+        ByteCodeSymbol::Function { name, linkage: ByteCodeLinkage::External } => format!("function {} external", quote(name)),
+        ByteCodeSymbol::Function { name, linkage: ByteCodeLinkage::Static } => format!("function {} static", quote(name))
+    }
+}
+
+fn register_to_text(register: ByteCodeRegister) -> String {
+    match register {
+        ByteCodeRegister::CallArg(number) => format!("arg{}", number),
+        ByteCodeRegister::CallReturnArg(number) => format!("ret{}", number),
+        ByteCodeRegister::StackPointer => string("sp"),
+        ByteCodeRegister::BasePointer => string("bp"),
+        ByteCodeRegister::ShiftCount => string("count"),
+        ByteCodeRegister::Remainder => string("rem")
+    }
+}
+
+fn register_from_text(text: &str) -> Option<ByteCodeRegister> {
+    match text {
+        "sp" => Some(stack_pointer_register()),
+        "bp" => Some(base_pointer_register()),
+        "count" => Some(shift_count_register()),
+        "rem" => Some(remainder_register()),
+        _ => {
+            if let Some(number) = text.strip_prefix("arg") {
+                return register_number(number).map(call_arg_register);
+            }
+            text.strip_prefix("ret").and_then(register_number).map(call_return_arg_register)
+        }
+    }
+}
+
+fn register_number(text: &str) -> Option<usize> {
+    if text.is_empty() || !text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+const WIDTHS: [(ByteCodeWidth, &str); 4] = [
+    (ByteCodeWidth::Byte, "byte"),
+    (ByteCodeWidth::Word, "word"),
+    (ByteCodeWidth::DWord, "dword"),
+    (ByteCodeWidth::QWord, "qword")
+];
+
+const ARITHMETIC_OPERATIONS: [(ByteCodeArithmetic, &str); 7] = [
+    (ByteCodeArithmetic::Add, "add"),
+    (ByteCodeArithmetic::Subtract, "sub"),
+    (ByteCodeArithmetic::Multiply, "mul"),
+    (ByteCodeArithmetic::And, "and"),
+    (ByteCodeArithmetic::Or, "or"),
+    (ByteCodeArithmetic::Xor, "xor"),
+    (ByteCodeArithmetic::Compare, "cmp")
+];
+
+const SHIFTS: [(ByteCodeShift, &str); 3] = [
+    (ByteCodeShift::Left, "shl"),
+    (ByteCodeShift::Right, "shr"),
+    (ByteCodeShift::ArithmeticRight, "sar")
+];
+
+const CONDITIONS: [(ByteCodeCondition, &str); 10] = [
+    (ByteCodeCondition::Equal, "equal"),
+    (ByteCodeCondition::NotEqual, "not_equal"),
+    (ByteCodeCondition::Less, "less"),
+    (ByteCodeCondition::LessOrEqual, "less_or_equal"),
+    (ByteCodeCondition::Greater, "greater"),
+    (ByteCodeCondition::GreaterOrEqual, "greater_or_equal"),
+    (ByteCodeCondition::Below, "below"),
+    (ByteCodeCondition::BelowOrEqual, "below_or_equal"),
+    (ByteCodeCondition::Above, "above"),
+    (ByteCodeCondition::AboveOrEqual, "above_or_equal")
+];
+
+fn to_text<T: PartialEq>(table: &[(T, &'static str)], value: T) -> &'static str {
+    table.iter().find(|(entry, _)| *entry == value).map(|(_, text)| *text).unwrap_or_default()
+}
+
+fn from_text<T: Copy>(table: &[(T, &'static str)], text: &str) -> Option<T> {
+    table.iter().find(|(_, entry)| *entry == text).map(|(value, _)| *value)
+}
+
+fn width_to_text(width: ByteCodeWidth) -> &'static str {
+    to_text(&WIDTHS, width)
+}
+
+fn arithmetic_to_text(operation: ByteCodeArithmetic) -> &'static str {
+    to_text(&ARITHMETIC_OPERATIONS, operation)
+}
+
+fn arithmetic_from_text(text: &str) -> Option<ByteCodeArithmetic> {
+    from_text(&ARITHMETIC_OPERATIONS, text)
+}
+
+fn shift_to_text(shift: ByteCodeShift) -> &'static str {
+    to_text(&SHIFTS, shift)
+}
+
+fn shift_from_text(text: &str) -> Option<ByteCodeShift> {
+    from_text(&SHIFTS, text)
+}
+
+fn condition_to_text(condition: ByteCodeCondition) -> &'static str {
+    to_text(&CONDITIONS, condition)
+}
+
+fn quote(value: &str) -> String {
+    let mut quoted = string("\"");
+    for character in value.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\0' => quoted.push_str("\\0"),
+            character if character.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", character as u8)),
+            character => quoted.push(character)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+enum Token {
+    Word(String),
+    String(String),
+    Number(i64)
+}
+
+enum RegisterOrNumber {
+    Register(ByteCodeRegister),
+    Number(i64)
+}
+
+struct LineTokens {
+    line: usize,
+    tokens: vec::IntoIter<Token>
+}
+
+fn tokenise_line(line: usize, text: &str) -> Result<LineTokens, IrParseError> {
+    let mut tokens = vec!();
+    let mut characters = text.chars().peekable();
+
+    while let Some(&character) = characters.peek() {
+        if character.is_whitespace() {
+            characters.next();
+        } else if character == ';' {
+            break;
+        } else if character == '"' {
+            characters.next();
+            tokens.push(Token::String(tokenise_string(line, &mut characters)?));
+        } else {
+            let mut word = String::new();
+            while let Some(&character) = characters.peek() {
+                if character.is_whitespace() || character == ';' || character == '"' {
+                    break;
+                }
+                word.push(character);
+                characters.next();
+            }
+            tokens.push(tokenise_word(word));
+        }
+    }
+
+    Ok(LineTokens { line, tokens: tokens.into_iter() })
+}
+
+fn tokenise_string(line: usize, characters: &mut iter::Peekable<str::Chars>) -> Result<String, IrParseError> {
+    let mut value = String::new();
+    loop {
+        match characters.next() {
+            None => return Err(IrParseError::UnterminatedString { line }),
+            Some('"') => return Ok(value),
+            Some('\\') => value.push(tokenise_escape(line, characters)?),
+            Some(character) => value.push(character)
+        }
+    }
+}
+
+fn tokenise_escape(line: usize, characters: &mut iter::Peekable<str::Chars>) -> Result<char, IrParseError> {
+    match characters.next() {
+        Some('"') => Ok('"'),
+        Some('\\') => Ok('\\'),
+        Some('n') => Ok('\n'),
+        Some('r') => Ok('\r'),
+        Some('t') => Ok('\t'),
+        Some('0') => Ok('\0'),
+        Some('x') => {
+            let mut digits = String::new();
+            while digits.len() < 2 {
+                match characters.next_if(char::is_ascii_hexdigit) {
+                    Some(digit) => digits.push(digit),
+                    None => break
+                }
+            }
+            match u8::from_str_radix(&digits, 16) {
+                Ok(value) if digits.len() == 2 && value.is_ascii() => Ok(value as char),
+                _ => Err(IrParseError::InvalidEscape { line, escape: format!("x{}", digits) })
+            }
+        },
+        Some(character) => Err(IrParseError::InvalidEscape { line, escape: character.to_string() }),
+        None => Err(IrParseError::UnterminatedString { line })
+    }
+}
+
+fn tokenise_word(word: String) -> Token {
+    let digits = word.strip_prefix('-').unwrap_or(&word);
+    if !digits.starts_with(|character: char| character.is_ascii_digit()) {
+        return Token::Word(word);
+    }
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse()
+    };
+    match magnitude {
+        Ok(magnitude) if word.starts_with('-') => Token::Number(-magnitude),
+        Ok(magnitude) => Token::Number(magnitude),
+        Err(_) => Token::Word(word)
+    }
+}
+
+impl LineTokens {
+    fn is_empty(&self) -> bool {
+        self.tokens.len() == 0
+    }
+
+    fn end(&mut self) -> Result<(), IrParseError> {
+        match self.tokens.next() {
+            None => Ok(()),
+            Some(token) => Err(self.unexpected(token, "end of line"))
+        }
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<Token, IrParseError> {
+        self.tokens.next().ok_or(IrParseError::UnexpectedEndOfLine { line: self.line, expected })
+    }
+
+    fn next_word(&mut self, expected: &'static str) -> Result<String, IrParseError> {
+        match self.next(expected)? {
+            Token::Word(word) => Ok(word),
+            token => Err(self.unexpected(token, expected))
+        }
+    }
+
+    fn next_string(&mut self, expected: &'static str) -> Result<String, IrParseError> {
+        match self.next(expected)? {
+            Token::String(value) => Ok(value),
+            token => Err(self.unexpected(token, expected))
+        }
+    }
+
+    fn next_number(&mut self, expected: &'static str) -> Result<i64, IrParseError> {
+        match self.next(expected)? {
+            Token::Number(value) => Ok(value),
+            token => Err(self.unexpected(token, expected))
+        }
+    }
+
+    fn next_u8(&mut self, expected: &'static str) -> Result<u8, IrParseError> {
+        let value = self.next_number(expected)?;
+        u8::try_from(value).map_err(|_| self.out_of_range(value, expected))
+    }
+
+    fn next_u16(&mut self, expected: &'static str) -> Result<u16, IrParseError> {
+        let value = self.next_number(expected)?;
+        u16::try_from(value).map_err(|_| self.out_of_range(value, expected))
+    }
+
+    fn next_u32(&mut self, expected: &'static str) -> Result<u32, IrParseError> {
+        let value = self.next_number(expected)?;
+        u32::try_from(value).map_err(|_| self.out_of_range(value, expected))
+    }
+
+    fn next_declaration_index(&mut self, declaration: &'static str, expected: usize) -> Result<(), IrParseError> {
+        let index = self.next_u32("declaration index")?;
+        if index as usize != expected {
+            return Err(IrParseError::DeclarationOutOfOrder { line: self.line, declaration, index, expected: expected as u32 });
+        }
+        Ok(())
+    }
+
+    fn next_register(&mut self) -> Result<ByteCodeRegister, IrParseError> {
+        let word = self.next_word("register")?;
+        register_from_text(&word).ok_or(IrParseError::UnexpectedToken { line: self.line, found: word, expected: "register" })
+    }
+
+    fn next_register_or_number(&mut self, expected: &'static str) -> Result<RegisterOrNumber, IrParseError> {
+        match self.next(expected)? {
+            Token::Number(value) => Ok(RegisterOrNumber::Number(value)),
+            Token::Word(word) => match register_from_text(&word) {
+                Some(register) => Ok(RegisterOrNumber::Register(register)),
+                None => Err(IrParseError::UnexpectedToken { line: self.line, found: word, expected })
+            },
+            token => Err(self.unexpected(token, expected))
+        }
+    }
+
+    fn next_width(&mut self) -> Result<ByteCodeWidth, IrParseError> {
+        let word = self.next_word("width")?;
+        from_text(&WIDTHS, &word).ok_or(IrParseError::UnexpectedToken { line: self.line, found: word, expected: "width" })
+    }
+
+    fn next_condition(&mut self) -> Result<ByteCodeCondition, IrParseError> {
+        let word = self.next_word("condition")?;
+        from_text(&CONDITIONS, &word).ok_or(IrParseError::UnexpectedToken { line: self.line, found: word, expected: "condition" })
+    }
+
+    fn unexpected(&self, token: Token, expected: &'static str) -> IrParseError {
+        let found = match token {
+            Token::Word(word) => word,
+            Token::String(value) => quote(&value),
+            Token::Number(value) => value.to_string()
+        };
+        IrParseError::UnexpectedToken { line: self.line, found, expected }
+    }
+
+    fn out_of_range(&self, value: i64, expected: &'static str) -> IrParseError {
+        IrParseError::ValueOutOfRange { line: self.line, value, expected }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: &str = r#"unit 3 "tests \"all\".hep"
+
+data 0 string "tab\tquote\"slash\\nul\0bell\x07"
+data 1 read_only_string "hello\r\n"
+data 2 read_only_bytes 0x00 0x7f 0xff
+data 3 zero_initialised 16 8
+
+symbol 0 data_section_item "message" 1
+symbol 1 foreign_external "print"
+symbol 2 absolute_external "answer" 42
+symbol 3 external_code_label "entry" 4
+symbol 4 function "main" external
+symbol 5 function "helper" static
+
+label 0 "loop"
+label 1 "done"
+
+    define_function 4 ; main
+    location 3 5
+    push_64 bp
+    move_reg_64 sp bp
+    sub_value_8 32 sp
+    zero_64 ret0
+    move_value_32 7 arg0
+    move_symbol_32 2 arg1 ; answer
+    load_data_address_64 0 arg2
+    move_value_to_offset_32 9 bp 8
+    move_reg_to_offset_32 arg0 bp 12
+    move_reg_to_offset_64 arg1 sp 16
+    move_offset_to_reg_32 bp 12 arg3
+    move_offset_to_reg_64 sp 16 arg4
+    define_label 0 ; loop
+    add qword arg0 arg1
+    sub dword -1 arg2
+    mul word arg3 arg0
+    and byte 15 arg1
+    or qword 1 arg2
+    xor dword arg0 arg0
+    cmp qword 100 arg0
+    jump_if less 0 ; loop
+    not dword arg1
+    neg qword arg2
+    shl qword 3 arg0
+    shr dword 1 arg1
+    sar qword count arg2
+    move_reg_64 rem arg0
+    div dword arg3
+    idiv qword arg4
+    jump 1 ; done
+    define_label 1 ; done
+    call 1 ; print
+    add_value_8 32 sp
+    pop_64 bp
+    return
+"#;
+
+    #[test]
+    fn printed_units_parse_back_to_the_same_text() {
+        let ir = read_intermediate_representation_from_text(UNIT).unwrap();
+        let text = intermediate_representation_to_text(&ir);
+        assert_eq!(text, UNIT);
+
+        let reparsed = read_intermediate_representation_from_text(&text).unwrap();
+        assert_eq!(reparsed.byte_code, ir.byte_code);
+        assert_eq!(intermediate_representation_to_text(&reparsed), text);
+    }
+
+    #[test]
+    fn control_characters_round_trip_through_hex_escapes() {
+        let mut ir = create_intermediate_representation(compilation_unit_id(1), string("\x01\x1f\x7f"));
+        add_data_item(&mut ir.data, string_data_item(string("a\x1bb")));
+
+        let text = intermediate_representation_to_text(&ir);
+        assert!(text.contains(r#""\x01\x1f\x7f""#));
+
+        let reparsed = read_intermediate_representation_from_text(&text).unwrap();
+        assert_eq!(reparsed.filename, ir.filename);
+        assert_eq!(intermediate_representation_to_text(&reparsed), text);
+    }
+
+    #[test]
+    fn incomplete_hex_escapes_are_rejected() {
+        for (text, escape) in [
+            (r#"unit 1 "abc\x""#, "x"),
+            (r#"unit 1 "abc\x7""#, "x7"),
+            (r#"unit 1 "abc\x+7""#, "x"),
+            (r#"unit 1 "abc\xff""#, "xff")
+        ] {
+            assert_eq!(
+                read_intermediate_representation_from_text(text).unwrap_err(),
+                IrParseError::InvalidEscape { line: 1, escape: string(escape) }
+            );
+        }
+    }
+}